@@ -13,35 +13,49 @@ echo "📦 Creating canisters..."
 dfx canister create --all
 
 echo "📦 Building canisters..."
+# Built with the demo feature: order payloads are submitted in the clear
+# instead of being IBE-encrypted to the round identity
 dfx build
 
 echo "🚀 Deploying canisters..."
 dfx deploy
 
-echo "⏳ Starting round..."
-dfx canister call $CANISTER admin_start_round
+# Order payload (format v1): 0x01 followed by candid(OrderPayload), as a
+# Candid blob literal. Needs didc on PATH.
+order_payload() {
+  local side=$1 amount=$2 price=$3
+  local owner nonce hex
+  owner=$(dfx identity get-principal)
+  nonce=$(openssl rand -hex 16 | sed 's/../\\&/g')
+  hex=01$(didc encode -d mempool_chess_backend.did -t '(OrderPayload)' \
+    "(record { market_id = 0 : nat32; round_id = 1 : nat64; owner = principal \"$owner\"; side = variant { $side }; amount = $amount : nat64; price_limit = $price : nat64; nonce = blob \"$nonce\" })")
+  echo "$hex" | sed 's/../\\&/g'
+}
+
+echo "⏳ Starting BTC/USD round (market 0)..."
+dfx canister call $CANISTER admin_start_round '(0 : nat32)'
 
 echo "👤 trader1 placing BUY..."
 dfx identity use trader1
 dfx canister call $CANISTER submit_order \
-  '(variant { Buy }, variant { BTC }, 200000, 10000, vec {}, "")'
+  "(0 : nat32, variant { Buy }, 2000000000, blob \"$(order_payload Buy 200000 10000)\", \"\")"
 
 echo "👤 trader2 placing SELL..."
 dfx identity use trader2
 dfx canister call $CANISTER submit_order \
-  '(variant { Sell }, variant { BTC }, 200000, 9000, vec {}, "")'
+  "(0 : nat32, variant { Sell }, 200000, blob \"$(order_payload Sell 200000 9000)\", \"\")"
 
 echo "👤 trader3 placing BUY..."
 dfx identity use trader3
 dfx canister call $CANISTER submit_order \
-  '(variant { Buy }, variant { BTC }, 100000, 11000, vec {}, "")'
+  "(0 : nat32, variant { Buy }, 1100000000, blob \"$(order_payload Buy 100000 11000)\", \"\")"
 
 echo "👤 trader4 placing SELL..."
 dfx identity use trader4
 dfx canister call $CANISTER submit_order \
-  '(variant { Sell }, variant { BTC }, 100000, 8500, vec {}, "")'
+  "(0 : nat32, variant { Sell }, 100000, blob \"$(order_payload Sell 100000 8500)\", \"\")"
 
 echo "🧮 Running clearing..."
-dfx canister call $CANISTER admin_run_clearing
+dfx canister call $CANISTER admin_run_clearing '(0 : nat32)'
 
 echo "✅ DEMO COMPLETE"
@@ -0,0 +1,179 @@
+use crate::queries::MAX_PAGE_SIZE;
+use crate::types::*;
+use crate::{memory, Memory, AUDIT_LOG_MEMORY_ID, ROLES_MEMORY_ID};
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// ============================================================================
+// ACCESS CONTROL
+// ============================================================================
+//
+// Privileged endpoints first check the caller's role (`require_operator`,
+// `require_admin`), failing with `VeilError::Unauthorized`, and record
+// themselves in the audit log with `audit` before doing any work.
+//
+// Controllers hold `Controller` implicitly. Controllers grant and revoke
+// `Admin`; admins grant and revoke `Operator`.
+
+thread_local! {
+    static ROLES: RefCell<StableBTreeMap<Principal, Role, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ROLES_MEMORY_ID))
+    );
+
+    static AUDIT_LOG: RefCell<StableBTreeMap<u64, AuditEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(AUDIT_LOG_MEMORY_ID))
+    );
+}
+
+/// The role `principal` currently holds, if any
+pub fn role_of(principal: &Principal) -> Option<Role> {
+    if ic_cdk::api::is_controller(principal) {
+        return Some(Role::Controller);
+    }
+    ROLES.with(|roles| roles.borrow().get(principal))
+}
+
+fn require(min: Role) -> Result<(), VeilError> {
+    match role_of(&ic_cdk::api::msg_caller()) {
+        Some(role) if role >= min => Ok(()),
+        _ => Err(VeilError::Unauthorized { required: min }),
+    }
+}
+
+// ============================================================================
+// CALLER CHECKS
+// ============================================================================
+
+/// The caller, unless it is the anonymous principal
+pub fn require_authenticated() -> Result<Principal, VeilError> {
+    let caller = ic_cdk::api::msg_caller();
+    if caller == Principal::anonymous() {
+        return Err(VeilError::AnonymousCaller);
+    }
+    Ok(caller)
+}
+
+pub fn require_operator() -> Result<(), VeilError> {
+    require(Role::Operator)
+}
+
+pub fn require_admin() -> Result<(), VeilError> {
+    require(Role::Admin)
+}
+
+// ============================================================================
+// AUDIT TRAIL
+// ============================================================================
+
+/// Record that the caller invoked a privileged `action`
+pub fn audit(action: &str, detail: String) {
+    let caller = ic_cdk::api::msg_caller();
+    let Some(role) = role_of(&caller) else {
+        return;
+    };
+
+    AUDIT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let id = log.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        log.insert(
+            id,
+            AuditEntry {
+                id,
+                timestamp: ic_cdk::api::time(),
+                caller,
+                role,
+                action: action.to_string(),
+                detail,
+            },
+        );
+    });
+}
+
+/// Page through the audit log, oldest first, starting at entry `cursor`
+#[ic_cdk_macros::query]
+pub fn get_audit_log(cursor: Option<u64>, limit: u32) -> Result<AuditPage, VeilError> {
+    require_admin()?;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let mut entries: Vec<AuditEntry> = log
+            .range(cursor.unwrap_or(0)..)
+            .take(limit + 1)
+            .map(|entry| entry.value())
+            .collect();
+
+        let next_cursor = if entries.len() > limit {
+            entries.pop().map(|e| e.id)
+        } else {
+            None
+        };
+        Ok(AuditPage { entries, next_cursor })
+    })
+}
+
+// ============================================================================
+// ROLE MANAGEMENT
+// ============================================================================
+
+/// Role needed to grant or revoke `role`
+fn manager_of(role: Role) -> Result<Role, VeilError> {
+    match role {
+        Role::Operator => Ok(Role::Admin),
+        Role::Admin => Ok(Role::Controller),
+        Role::Controller => Err(VeilError::InvalidArgument(
+            "The Controller role follows the canister's controllers".to_string(),
+        )),
+    }
+}
+
+#[ic_cdk_macros::update]
+pub fn grant_role(principal: Principal, role: Role) -> Result<(), VeilError> {
+    require_admin()?;
+    require(manager_of(role)?)?;
+    if principal == Principal::anonymous() {
+        return Err(VeilError::InvalidArgument(
+            "Cannot grant a role to the anonymous principal".to_string(),
+        ));
+    }
+    if let Some(current) = ROLES.with(|roles| roles.borrow().get(&principal)) {
+        require(manager_of(current)?)?;
+    }
+
+    audit("grant_role", format!("{} -> {:?}", principal, role));
+    ROLES.with(|roles| roles.borrow_mut().insert(principal, role));
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+pub fn revoke_role(principal: Principal) -> Result<(), VeilError> {
+    require_admin()?;
+    let current = ROLES
+        .with(|roles| roles.borrow().get(&principal))
+        .ok_or_else(|| VeilError::InvalidArgument(format!("{} holds no granted role", principal)))?;
+    require(manager_of(current)?)?;
+
+    audit("revoke_role", format!("{} (was {:?})", principal, current));
+    ROLES.with(|roles| roles.borrow_mut().remove(&principal));
+    Ok(())
+}
+
+/// All explicitly granted roles (controllers are not listed)
+#[ic_cdk_macros::query]
+pub fn get_roles() -> Result<Vec<(Principal, Role)>, VeilError> {
+    require_admin()?;
+    Ok(ROLES.with(|roles| {
+        roles
+            .borrow()
+            .iter()
+            .map(|entry| (*entry.key(), entry.value()))
+            .collect()
+    }))
+}
+
+/// The caller's own role, if any
+#[ic_cdk_macros::query]
+pub fn get_my_role() -> Option<Role> {
+    role_of(&ic_cdk::api::msg_caller())
+}
@@ -0,0 +1,475 @@
+use crate::escrow::{self, MemoKind};
+use crate::journal::{self, Posting};
+use crate::queries::MAX_PAGE_SIZE;
+use crate::types::*;
+use crate::{memory, Memory, CUSTODY_MEMORY_ID, DEMO_BALANCES, TRANSFERS_BY_OWNER_MEMORY_ID, TRANSFERS_MEMORY_ID};
+use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, StableBTreeSet};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+// ============================================================================
+// TRADING ACCOUNTS
+// ============================================================================
+//
+// With the `Accounts` escrow backend, orders lock and settle against the
+// in-canister book (`DEMO_BALANCES`) as on the demo backend, but nobody
+// starts funded: users `deposit` ledger tokens and `withdraw` them again.
+//
+// Each trading account has its own subaccount of the canister, derived from
+// the owner's principal, so the ledger shows what every user put in and
+// took out. Settlement only moves ownership inside the book, not tokens
+// between subaccounts: moving them would take a ledger transfer per fill,
+// each charging the ledger fee and each able to fail halfway through a
+// round that must settle all at once. So the subaccounts together back the
+// book, and `CUSTODY` tracks what each one holds. A withdrawal is paid from
+// the owner's subaccount when it holds enough; trade proceeds are paid
+// from the subaccount holding the most beyond its owner's balance, which is
+// where the counterparties' tokens sit. Each withdrawal records its source,
+// so every subaccount's ledger history is its deposits in and the
+// withdrawals recorded against it out, and its ledger balance equals
+// `get_custody`.
+//
+// A deposit pulls the tokens with `icrc2_transfer_from` (the user approves
+// the canister first) into the owner's subaccount and credits them once
+// the ledger accepted. A
+// withdrawal debits Free up front, holding the amount as pending until the
+// ledger answers: a refusal returns it, an unknown outcome (the call failed)
+// keeps it pending for `retry_withdrawal`, whose identical transfer the
+// ledger deduplicates. Once the ledger can no longer deduplicate it (TooOld,
+// CreatedInFuture) an operator settles it from the ledger's transaction log
+// with `settle_withdrawal`. A user with a pending withdrawal cannot start
+// another until it resolves.
+
+thread_local! {
+    static TRANSFERS: RefCell<StableBTreeMap<u64, AccountTransfer, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(TRANSFERS_MEMORY_ID))
+    );
+
+    static TRANSFERS_BY_OWNER: RefCell<StableBTreeSet<(Principal, u64), Memory>> = RefCell::new(
+        StableBTreeSet::init(memory(TRANSFERS_BY_OWNER_MEMORY_ID))
+    );
+
+    static CUSTODY: RefCell<StableBTreeMap<Principal, Custody, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(CUSTODY_MEMORY_ID))
+    );
+
+    // Withdrawals whose ledger call is awaiting an answer. An upgrade stops
+    // the canister first, which waits for every call to return, so this
+    // starts empty; who has a withdrawal pending comes from TRANSFERS.
+    static WITHDRAWALS_SENDING: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// How long the ledger deduplicates a transfer (its transaction window plus
+// permitted clock drift); past it, a resend is refused as TooOld
+pub(crate) const DEDUP_WINDOW_NANOS: u64 = (24 * 60 + 2) * 60 * 1_000_000_000;
+
+fn require_accounts() -> Result<(), VeilError> {
+    if escrow::backend() != EscrowBackend::Accounts {
+        return Err(VeilError::AccountsDisabled);
+    }
+    Ok(())
+}
+
+/// Subaccount of the canister holding `owner`'s trading account: the
+/// principal's length and bytes, zero padded to 32 bytes
+pub(crate) fn subaccount(owner: Principal) -> Vec<u8> {
+    let bytes = owner.as_slice();
+    let mut subaccount = vec![0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..=bytes.len()].copy_from_slice(bytes);
+    subaccount
+}
+
+fn custody(owner: Principal, asset: &Asset) -> u64 {
+    CUSTODY.with(|c| c.borrow().get(&owner)).and_then(|c| c.assets.get(asset).copied()).unwrap_or(0)
+}
+
+/// Add `delta` to what `owner`'s subaccount holds. Custody going negative
+/// or past u64 is a bug and traps, rolling back the whole message.
+fn adjust_custody(owner: Principal, asset: &Asset, delta: i128) {
+    CUSTODY.with(|c| {
+        let mut custody = c.borrow_mut();
+        let mut held = custody.get(&owner).unwrap_or_default();
+        let amount = held.assets.entry(asset.clone()).or_default();
+        let updated = *amount as i128 + delta;
+        *amount = u64::try_from(updated)
+            .unwrap_or_else(|_| ic_cdk::trap(format!("Custody of {} {:?} would become {}", owner, asset, updated)));
+        custody.insert(owner, held);
+    });
+}
+
+/// Trading account whose subaccount pays out `amount` of `asset`:
+/// `owner`'s own if it holds enough, else the one holding the most beyond
+/// its owner's balance among those that hold enough
+pub(crate) fn payout_source(owner: Option<Principal>, asset: &Asset, amount: u64) -> Result<Principal, VeilError> {
+    if let Some(owner) = owner.filter(|owner| custody(*owner, asset) >= amount) {
+        return Ok(owner);
+    }
+
+    let held: Vec<(Principal, u64)> = CUSTODY.with(|c| {
+        c.borrow()
+            .iter()
+            .filter_map(|entry| entry.value().assets.get(asset).map(|amount| (*entry.key(), *amount)))
+            .collect()
+    });
+    let surplus = |(principal, custody): &(Principal, u64)| {
+        let book = DEMO_BALANCES.with(|b| b.borrow().get(principal)).unwrap_or_default();
+        let (free, locked) = book.slots(asset);
+        *custody as i128 - free as i128 - locked as i128
+    };
+    held.iter()
+        .filter(|(_, custody)| *custody >= amount)
+        .max_by_key(|source| surplus(source))
+        .map(|(principal, _)| *principal)
+        .ok_or_else(|| VeilError::InsufficientCustody {
+            asset: asset.clone(),
+            requested: amount,
+            largest: held.iter().map(|(_, custody)| *custody).max().unwrap_or(0),
+        })
+}
+
+/// Set aside `amount` in `source`'s subaccount for a payout in flight
+pub(crate) fn take_custody(source: Principal, asset: &Asset, amount: u64) {
+    adjust_custody(source, asset, -(amount as i128));
+}
+
+/// Give back what a refused payout had set aside
+pub(crate) fn return_custody(source: Principal, asset: &Asset, amount: u64) {
+    adjust_custody(source, asset, amount as i128);
+}
+
+/// Record a new Pending transfer; its id keeps its ledger memo unique
+fn open_transfer(
+    kind: TransferKind,
+    owner: Principal,
+    asset: &Asset,
+    amount: u64,
+    to: Option<Account>,
+    source: Option<Principal>,
+) -> AccountTransfer {
+    let now = ic_cdk::api::time();
+    let id = TRANSFERS.with(|t| t.borrow().last_key_value().map(|(id, _)| id + 1).unwrap_or(0));
+    let transfer = AccountTransfer {
+        id,
+        kind,
+        owner,
+        asset: asset.clone(),
+        amount,
+        to,
+        source,
+        status: TransferStatus::Pending,
+        created_at: now,
+        updated_at: now,
+    };
+    store_transfer(transfer.clone());
+    transfer
+}
+
+fn store_transfer(transfer: AccountTransfer) {
+    TRANSFERS_BY_OWNER.with(|idx| idx.borrow_mut().insert((transfer.owner, transfer.id)));
+    TRANSFERS.with(|t| t.borrow_mut().insert(transfer.id, transfer));
+}
+
+fn close_transfer(transfer: &AccountTransfer, status: TransferStatus) {
+    store_transfer(AccountTransfer {
+        status,
+        updated_at: ic_cdk::api::time(),
+        ..transfer.clone()
+    });
+}
+
+fn owner_transfers(owner: Principal) -> Vec<AccountTransfer> {
+    let ids: Vec<u64> = TRANSFERS_BY_OWNER.with(|idx| {
+        idx.borrow()
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|(_, id)| id)
+            .collect()
+    });
+    TRANSFERS.with(|t| {
+        let transfers = t.borrow();
+        ids.into_iter().filter_map(|id| transfers.get(&id)).collect()
+    })
+}
+
+fn is_pending_withdrawal(transfer: &AccountTransfer) -> bool {
+    transfer.kind == TransferKind::Withdrawal && transfer.status == TransferStatus::Pending
+}
+
+fn has_pending_withdrawal(owner: Principal) -> bool {
+    owner_transfers(owner).iter().any(is_pending_withdrawal)
+}
+
+/// Send `transfer` unless another call is already awaiting its ledger call
+async fn send_exclusively<F: std::future::Future<Output = Result<(), VeilError>>>(
+    transfer: &AccountTransfer,
+    send: impl FnOnce() -> F,
+) -> Result<(), VeilError> {
+    if !WITHDRAWALS_SENDING.with(|s| s.borrow_mut().insert(transfer.id)) {
+        return Err(VeilError::WithdrawalInFlight);
+    }
+    let result = send().await;
+    WITHDRAWALS_SENDING.with(|s| s.borrow_mut().remove(&transfer.id));
+    result
+}
+
+/// Sum of all pending withdrawals, per asset, for `journal::reconcile`
+pub(crate) fn pending_withdrawals(asset: &Asset) -> u128 {
+    TRANSFERS.with(|t| {
+        t.borrow()
+            .iter()
+            .map(|entry| entry.value())
+            .filter(|transfer| is_pending_withdrawal(transfer) && transfer.asset == *asset)
+            .map(|transfer| transfer.amount as u128)
+            .sum()
+    })
+}
+
+// ============================================================================
+// DEPOSITS
+// ============================================================================
+
+/// Pull `amount` of `asset` from the caller's ledger account into their
+/// trading account's subaccount; returns the transfer id. The caller
+/// approves the canister for `amount` plus the ledger fee first.
+#[ic_cdk_macros::update]
+pub async fn deposit(asset: Asset, amount: u64) -> Result<u64, VeilError> {
+    let caller = crate::access::require_authenticated()?;
+    require_accounts()?;
+    if amount == 0 {
+        return Err(VeilError::InvalidArgument("Deposit amount must be > 0".to_string()));
+    }
+    let ledger = escrow::ledger_for(&asset)?;
+
+    let transfer = open_transfer(TransferKind::Deposit, caller, &asset, amount, None, None);
+    let memo = escrow::memo(MemoKind::Deposit, transfer.id, 0);
+    if let Err(e) = escrow::pull_from_user(&ledger, caller, Some(subaccount(caller)), amount, memo).await {
+        close_transfer(&transfer, TransferStatus::Failed(e.to_string()));
+        return Err(e);
+    }
+    adjust_custody(caller, &asset, amount as i128);
+
+    // The tokens arrived: the credit must not fail, so an overflowing
+    // balance leaves the deposit Pending for an admin to look into
+    escrow::with_demo_balance_mut(&caller, |bal| -> Result<(), VeilError> {
+        let (free, _) = bal.slots_mut(&asset);
+        *free = free.checked_add(amount).ok_or(VeilError::Overflow)?;
+        Ok(())
+    })?;
+    journal::record(vec![Posting::new(JournalReason::Deposit, None)
+        .leg(JournalAccount::External, &asset, -(amount as i128))
+        .leg(JournalAccount::Free(caller), &asset, amount)]);
+    close_transfer(&transfer, TransferStatus::Completed);
+    Ok(transfer.id)
+}
+
+// ============================================================================
+// WITHDRAWALS
+// ============================================================================
+
+/// Send `amount` of `asset` from the caller's free balance to `to`, which
+/// receives it less the ledger fee; returns the transfer id. No single
+/// subaccount holding `amount` is an `InsufficientCustody` error naming the
+/// most that can go out at once.
+#[ic_cdk_macros::update]
+pub async fn withdraw(asset: Asset, amount: u64, to: Account) -> Result<u64, VeilError> {
+    let caller = crate::access::require_authenticated()?;
+    require_accounts()?;
+    let ledger = escrow::ledger_for(&asset)?;
+    if amount <= ledger.fee {
+        return Err(VeilError::BelowLedgerFee { amount, fee: ledger.fee });
+    }
+
+    if has_pending_withdrawal(caller) {
+        return Err(VeilError::WithdrawalInFlight);
+    }
+    let transfer = set_aside(caller, &asset, amount, to)?;
+    send_exclusively(&transfer, || send_withdrawal(&transfer)).await.map(|()| transfer.id)
+}
+
+/// Resend a withdrawal whose outcome is unknown (owner or operator). The
+/// ledger drops it as a duplicate if the first attempt went through.
+#[ic_cdk_macros::update]
+pub async fn retry_withdrawal(transfer_id: u64) -> Result<(), VeilError> {
+    let caller = crate::access::require_authenticated()?;
+    let transfer = TRANSFERS
+        .with(|t| t.borrow().get(&transfer_id))
+        .filter(|t| t.kind == TransferKind::Withdrawal)
+        .ok_or(VeilError::UnknownTransfer(transfer_id))?;
+    if transfer.owner != caller {
+        crate::access::require_operator()?;
+        crate::access::audit("retry_withdrawal", format!("transfer {}", transfer_id));
+    }
+    if transfer.status != TransferStatus::Pending {
+        return Err(VeilError::InvalidArgument(format!("Transfer {} is {:?}", transfer_id, transfer.status)));
+    }
+
+    send_exclusively(&transfer, || send_withdrawal(&transfer)).await
+}
+
+/// Settle a pending withdrawal from the ledger's transaction log (operator):
+/// `Some(block)` completes it if that block is its payout, `None` records
+/// that no block carries its memo and returns the amount to Free. `None` is
+/// only taken once the ledger would refuse any resend as TooOld.
+#[ic_cdk_macros::update]
+pub async fn settle_withdrawal(transfer_id: u64, block_index: Option<u64>) -> Result<(), VeilError> {
+    crate::access::require_operator()?;
+    crate::access::audit("settle_withdrawal", format!("transfer {}: block {:?}", transfer_id, block_index));
+    let transfer = TRANSFERS
+        .with(|t| t.borrow().get(&transfer_id))
+        .filter(is_pending_withdrawal)
+        .ok_or(VeilError::UnknownTransfer(transfer_id))?;
+
+    match block_index {
+        Some(index) => send_exclusively(&transfer, || confirm_payout(&transfer, index)).await,
+        None => {
+            let resendable_until = transfer.created_at.saturating_add(DEDUP_WINDOW_NANOS);
+            if ic_cdk::api::time() <= resendable_until {
+                return Err(VeilError::InvalidArgument(format!(
+                    "Transfer {} can still be resent until {}; retry it instead",
+                    transfer_id, resendable_until
+                )));
+            }
+            if WITHDRAWALS_SENDING.with(|s| s.borrow().contains(&transfer.id)) {
+                return Err(VeilError::WithdrawalInFlight);
+            }
+            refund_withdrawal(&transfer, "Not in the ledger's transaction log".to_string());
+            Ok(())
+        }
+    }
+}
+
+/// Complete `transfer` if ledger block `index` is its payout
+async fn confirm_payout(transfer: &AccountTransfer, index: u64) -> Result<(), VeilError> {
+    let ledger = escrow::ledger_for(&transfer.asset)?;
+    let from = Account {
+        owner: ic_cdk::api::canister_self(),
+        subaccount: transfer.source.map(subaccount),
+    };
+    let memo = escrow::memo(MemoKind::Withdrawal, transfer.id, 0);
+    if !escrow::is_payout_block(&ledger, index, &from, &withdrawal_account(transfer), transfer.amount, &memo).await? {
+        return Err(VeilError::InvalidArgument(format!("Ledger block {} is not the payout of transfer {}", index, transfer.id)));
+    }
+    complete_withdrawal(transfer);
+    Ok(())
+}
+
+/// Move `amount` from Free to pending, set it aside in the subaccount that
+/// pays it and record the withdrawal
+fn set_aside(owner: Principal, asset: &Asset, amount: u64, to: Account) -> Result<AccountTransfer, VeilError> {
+    let source = payout_source(Some(owner), asset, amount)?;
+    escrow::with_demo_balance_mut(&owner, |bal| {
+        let (free, _) = bal.slots_mut(asset);
+        if *free < amount {
+            return Err(VeilError::InsufficientBalance { required: amount, available: *free });
+        }
+        *free -= amount;
+        Ok(())
+    })?;
+
+    take_custody(source, asset, amount);
+
+    let transfer = open_transfer(TransferKind::Withdrawal, owner, asset, amount, Some(to), Some(source));
+    journal::record(vec![Posting::new(JournalReason::WithdrawalRequest, None)
+        .leg(JournalAccount::Free(owner), asset, -(amount as i128))
+        .leg(JournalAccount::Withdrawing(owner), asset, amount)]);
+    Ok(transfer)
+}
+
+fn withdrawal_account(transfer: &AccountTransfer) -> Account {
+    transfer.to.clone().unwrap_or_else(|| escrow::account(transfer.owner))
+}
+
+async fn send_withdrawal(transfer: &AccountTransfer) -> Result<(), VeilError> {
+    let ledger = escrow::ledger_for(&transfer.asset)?;
+    let memo = escrow::memo(MemoKind::Withdrawal, transfer.id, 0);
+    let (from, to) = (transfer.source.map(subaccount), withdrawal_account(transfer));
+    match escrow::pay_out(&ledger, from, &to, transfer.amount, memo, transfer.created_at).await {
+        Ok(()) => {
+            complete_withdrawal(transfer);
+            Ok(())
+        }
+        Err(e @ VeilError::TransferFailed(_)) => {
+            // The ledger refused: nothing left the canister
+            refund_withdrawal(transfer, e.to_string());
+            Err(e)
+        }
+        Err(e) => {
+            ic_cdk::println!("Withdrawal {} outcome unknown, kept pending: {}", transfer.id, e);
+            Err(e)
+        }
+    }
+}
+
+fn complete_withdrawal(transfer: &AccountTransfer) {
+    let (owner, asset, amount) = (transfer.owner, &transfer.asset, transfer.amount);
+    journal::record(vec![Posting::new(JournalReason::Withdrawal, None)
+        .leg(JournalAccount::Withdrawing(owner), asset, -(amount as i128))
+        .leg(JournalAccount::External, asset, amount)]);
+    close_transfer(transfer, TransferStatus::Completed);
+}
+
+fn refund_withdrawal(transfer: &AccountTransfer, reason: String) {
+    let (owner, asset, amount) = (transfer.owner, &transfer.asset, transfer.amount);
+    if let Some(source) = transfer.source {
+        return_custody(source, asset, amount);
+    }
+    escrow::with_demo_balance_mut(&owner, |bal| {
+        let (free, _) = bal.slots_mut(asset);
+        *free = free.saturating_add(amount);
+    });
+    journal::record(vec![Posting::new(JournalReason::WithdrawalRefund, None)
+        .leg(JournalAccount::Withdrawing(owner), asset, -(amount as i128))
+        .leg(JournalAccount::Free(owner), asset, amount)]);
+    close_transfer(transfer, TransferStatus::Failed(reason));
+}
+
+// ============================================================================
+// QUERIES
+// ============================================================================
+
+/// Free, locked and pending-withdrawal amounts of every asset `owner` holds
+#[ic_cdk_macros::query]
+pub fn get_balances(owner: Principal) -> Vec<AssetBalance> {
+    let book = DEMO_BALANCES.with(|b| b.borrow().get(&owner)).unwrap_or_default();
+    let pending: Vec<AccountTransfer> = owner_transfers(owner).into_iter().filter(is_pending_withdrawal).collect();
+
+    Asset::ALL
+        .iter()
+        .map(|asset| {
+            let (free, locked) = book.slots(asset);
+            AssetBalance {
+                asset: asset.clone(),
+                free,
+                locked,
+                pending_withdrawal: pending
+                    .iter()
+                    .filter(|t| t.asset == *asset)
+                    .fold(0u64, |sum, t| sum.saturating_add(t.amount)),
+            }
+        })
+        .collect()
+}
+
+/// Ledger account holding `owner`'s trading account, and what it holds of
+/// each asset. Trades move ownership on the book only, so this differs from
+/// the owner's balances by what they bought and sold.
+#[ic_cdk_macros::query]
+pub fn get_custody(owner: Principal) -> (Account, Vec<(Asset, u64)>) {
+    let account = Account {
+        owner: ic_cdk::api::canister_self(),
+        subaccount: Some(subaccount(owner)),
+    };
+    let held = Asset::ALL.iter().map(|asset| (asset.clone(), custody(owner, asset))).collect();
+    (account, held)
+}
+
+/// The caller's deposits and withdrawals, newest first
+#[ic_cdk_macros::query]
+pub fn get_my_transfers(limit: u32) -> Vec<AccountTransfer> {
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    let mut transfers = owner_transfers(ic_cdk::api::msg_caller());
+    transfers.reverse();
+    transfers.truncate(limit);
+    transfers
+}
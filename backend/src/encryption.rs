@@ -1,4 +1,6 @@
-use candid::Principal;
+use crate::types::{MarketId, Order, OrderId, OrderPayload, OrderType, RoundId, VeilError};
+use candid::{Decode, Encode, Principal};
+use ic_vetkeys::{DerivedPublicKey, EncryptedVetKey, IbeCiphertext, TransportSecretKey, VetKey};
 
 // =====================================
 // FLAGS
@@ -8,26 +10,15 @@ fn is_demo() -> bool {
     cfg!(feature = "demo")
 }
 
-#[inline]
-fn is_vetkeys_enabled() -> bool {
-    cfg!(feature = "vetkeys")
-}
-
-// ============================================================================
-// Global mutable for PocketIC tests
-// ============================================================================
-
-static mut VETKEYS_ENGINE_ID: Option<Principal> = None;
-
-pub fn set_vetkeys_engine_canister_id(id: Principal) {
-    unsafe {
-        VETKEYS_ENGINE_ID = Some(id);
-    }
+fn vetkeys_engine_canister_id() -> Result<Principal, VeilError> {
+    crate::vetkeys_engine_canister_id()
+        .ok_or_else(|| VeilError::KeyUnavailable("vetKD canister not set".to_string()))
 }
 
-fn vetkeys_engine_canister_id() -> Principal {
-    unsafe {
-        VETKEYS_ENGINE_ID.expect("VetKeys engine canister id not set")
+fn call_failed(method: &str, e: impl std::fmt::Debug) -> VeilError {
+    VeilError::CallFailed {
+        method: method.to_string(),
+        reason: format!("{:?}", e),
     }
 }
 
@@ -35,7 +26,7 @@ fn vetkeys_engine_canister_id() -> Principal {
 // CROSS-CANISTER VETKEYS BRIDGE
 // ============================================================================
 
-pub async fn get_encryption_public_key() -> Result<Vec<u8>, String> {
+pub async fn get_encryption_public_key() -> Result<Vec<u8>, VeilError> {
 
     // ============================
     // PocketIC / Demo mode
@@ -50,25 +41,25 @@ pub async fn get_encryption_public_key() -> Result<Vec<u8>, String> {
     // ============================
     // Real live mode (mainnet/local)
     // ============================
-    let canister = vetkeys_engine_canister_id();
+    let canister = vetkeys_engine_canister_id()?;
 
-    let (res,): (Vec<u8>,) = ic_cdk::call(
+    let (res,): (Result<Vec<u8>, String>,) = ic_cdk::call(
         canister,
         "get_public_key",
         (),
     )
     .await
-    .map_err(|e| format!("Call failed: {:?}", e))?;
-
-    Ok(res)
+    .map_err(|e| call_failed("get_public_key", e))?;
 
+    res.map_err(VeilError::KeyUnavailable)
 }
 
 
 pub async fn derive_round_decryption_key(
-    round_id: u64,
-    _transport_public_key: Vec<u8>,
-) -> Result<Vec<u8>, String> {
+    market_id: MarketId,
+    round_id: RoundId,
+    transport_public_key: Vec<u8>,
+) -> Result<Vec<u8>, VeilError> {
 
     // ============================
     // PocketIC / Demo
@@ -82,19 +73,20 @@ pub async fn derive_round_decryption_key(
     // ============================
     // Real vetkeys mode
     // ============================
-    let canister = vetkeys_engine_canister_id();
+    let canister = vetkeys_engine_canister_id()?;
 
     let args = (
+        market_id,
         round_id,
-        _transport_public_key,
+        transport_public_key,
     );
 
     let (res,): (Result<Vec<u8>, String>,) =
         ic_cdk::call(canister, "derive_round_key", args)
             .await
-            .map_err(|e| format!("Call failed: {:?}", e))?;
+            .map_err(|e| call_failed("derive_round_key", e))?;
 
-    res
+    res.map_err(VeilError::KeyUnavailable)
 }
 
 
@@ -102,7 +94,7 @@ pub async fn derive_round_decryption_key(
 pub async fn derive_user_key(
     user: Principal,
     transport_public_key: Vec<u8>,
-) -> Result<Vec<u8>, String> {
+) -> Result<Vec<u8>, VeilError> {
     if is_demo() {
         let mut fake_key = vec![0u8; 32];
         let user_bytes = user.as_slice();
@@ -111,7 +103,7 @@ pub async fn derive_user_key(
         return Ok(fake_key);
     }
 
-    let canister = vetkeys_engine_canister_id();
+    let canister = vetkeys_engine_canister_id()?;
 
     let (res,): (Result<Vec<u8>, String>,) = ic_cdk::call(
         canister,
@@ -119,18 +111,44 @@ pub async fn derive_user_key(
         (user, transport_public_key),
     )
     .await
-    .map_err(|e| format!("Call failed: {:?}", e))?;
+    .map_err(|e| call_failed("derive_user_key", e))?;
 
-    res
+    res.map_err(VeilError::KeyUnavailable)
+}
+
+/// Fetch the round's IBE decryption key from the vetkeys engine.
+///
+/// A one-off transport key is generated from `raw_rand`, so the vetKey only
+/// ever exists decrypted inside this canister. The key is verified against
+/// the engine's public key before use.
+pub async fn fetch_round_vetkey(market_id: MarketId, round_id: RoundId) -> Result<VetKey, VeilError> {
+    let seed = ic_cdk::management_canister::raw_rand()
+        .await
+        .map_err(|e| call_failed("raw_rand", e))?;
+    let tsk = TransportSecretKey::from_seed(seed).map_err(VeilError::Internal)?;
+
+    let encrypted = derive_round_decryption_key(market_id, round_id, tsk.public_key()).await?;
+    let public_key = get_encryption_public_key().await?;
+
+    let dpk = DerivedPublicKey::deserialize(&public_key)
+        .map_err(|e| VeilError::KeyUnavailable(format!("Invalid vetKD public key: {:?}", e)))?;
+
+    EncryptedVetKey::deserialize(&encrypted)
+        .and_then(|key| key.decrypt_and_verify(&tsk, &dpk, &generate_timelock_identity(market_id, round_id)))
+        .map_err(VeilError::KeyUnavailable)
 }
 
 // =====================================
 // TIMELOCK
 // =====================================
 
-pub fn generate_timelock_identity(round_id: u64) -> Vec<u8> {
+/// IBE identity orders of a round are encrypted to. The vetkeys engine only
+/// hands the matching key to the backend, which asks for it after the round
+/// has closed.
+pub fn generate_timelock_identity(market_id: MarketId, round_id: RoundId) -> Vec<u8> {
     let mut identity = Vec::new();
     identity.extend_from_slice(b"ROUND:");
+    identity.extend_from_slice(&market_id.to_be_bytes());
     identity.extend_from_slice(&round_id.to_be_bytes());
     identity
 }
@@ -141,68 +159,210 @@ pub fn generate_timelock_identity(round_id: u64) -> Vec<u8> {
 
 use sha2::{Digest, Sha256};
 
-pub fn generate_commitment_hash(order_data: &str) -> String {
+pub fn generate_commitment_hash(order_data: &[u8]) -> String {
     let mut hasher = Sha256::new();
-    hasher.update(order_data.as_bytes());
+    hasher.update(order_data);
     hex::encode(hasher.finalize())
 }
 
+/// Check a commitment against the canonical payload bytes
 pub fn verify_commitment(
-    decrypted_data: &str,
+    canonical_payload: &[u8],
     commitment_hash: &str,
-) -> Result<bool, String> {
+) -> Result<(), PayloadError> {
     if is_demo() {
-        return Ok(true);
+        return Ok(());
+    }
+
+    if generate_commitment_hash(canonical_payload) != commitment_hash {
+        return Err(PayloadError::CommitmentMismatch);
+    }
+
+    Ok(())
+}
+
+// =====================================
+// ORDER PAYLOAD FORMAT
+// =====================================
+
+/// Current order payload format version (first plaintext byte)
+pub const ORDER_PAYLOAD_VERSION: u8 = 1;
+
+/// Minimum length of the payload nonce
+pub const MIN_NONCE_LEN: usize = 16;
+
+/// Why an order payload was rejected at reveal
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadError {
+    DecryptionFailed(String),
+    Empty,
+    UnsupportedVersion(u8),
+    Malformed(String),
+    NonceTooShort(usize),
+    WrongMarket { expected: MarketId, found: MarketId },
+    WrongRound { expected: RoundId, found: RoundId },
+    WrongOwner { expected: Principal, found: Principal },
+    SideMismatch { submitted: OrderType, revealed: OrderType },
+    ZeroAmount,
+    ZeroPrice,
+    CommitmentMismatch,
+}
+
+impl std::fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadError::DecryptionFailed(e) => write!(f, "Decryption failed: {}", e),
+            PayloadError::Empty => write!(f, "Payload is empty"),
+            PayloadError::UnsupportedVersion(v) => write!(f, "Unsupported payload version {}", v),
+            PayloadError::Malformed(e) => write!(f, "Malformed payload: {}", e),
+            PayloadError::NonceTooShort(len) => {
+                write!(f, "Nonce is {} bytes, need at least {}", len, MIN_NONCE_LEN)
+            }
+            PayloadError::WrongMarket { expected, found } => {
+                write!(f, "Payload is for market {}, order is in market {}", found, expected)
+            }
+            PayloadError::WrongRound { expected, found } => {
+                write!(f, "Payload is for round {}, order is in round {}", found, expected)
+            }
+            PayloadError::WrongOwner { expected, found } => {
+                write!(f, "Payload is signed over to {}, order is owned by {}", found, expected)
+            }
+            PayloadError::SideMismatch { submitted, revealed } => {
+                write!(f, "Payload side {:?} does not match submitted side {:?}", revealed, submitted)
+            }
+            PayloadError::ZeroAmount => write!(f, "Amount must be > 0"),
+            PayloadError::ZeroPrice => write!(f, "Price limit must be > 0"),
+            PayloadError::CommitmentMismatch => {
+                write!(f, "SECURITY VIOLATION: Commitment hash mismatch")
+            }
+        }
+    }
+}
+
+impl PayloadError {
+    /// The public error of order `order_id` rejected with this reason
+    pub fn for_order(self, order_id: OrderId) -> VeilError {
+        match self {
+            PayloadError::DecryptionFailed(_) => VeilError::DecryptionFailed { order_id },
+            PayloadError::CommitmentMismatch => VeilError::CommitmentMismatch { order_id },
+            other => VeilError::InvalidPayload { order_id, reason: other.to_string() },
+        }
+    }
+}
+
+/// Canonical encoding of an order payload: the version byte followed by the
+/// Candid encoding of the `OrderPayload` record. Commitments are computed
+/// over exactly these bytes.
+pub fn encode_order_payload(payload: &OrderPayload) -> Result<Vec<u8>, PayloadError> {
+    let mut bytes = vec![ORDER_PAYLOAD_VERSION];
+    bytes.extend(Encode!(payload).map_err(|e| PayloadError::Malformed(e.to_string()))?);
+    Ok(bytes)
+}
+
+/// Parse a decrypted payload and check it belongs to `order`.
+/// Returns the payload together with its canonical encoding.
+pub fn decode_order_payload(bytes: &[u8], order: &Order) -> Result<(OrderPayload, Vec<u8>), PayloadError> {
+    let (version, body) = bytes.split_first().ok_or(PayloadError::Empty)?;
+    if *version != ORDER_PAYLOAD_VERSION {
+        return Err(PayloadError::UnsupportedVersion(*version));
     }
 
-    let computed = generate_commitment_hash(decrypted_data);
-    if computed != commitment_hash {
-        return Err("SECURITY VIOLATION: Commitment hash mismatch".to_string());
+    let payload = Decode!(body, OrderPayload).map_err(|e| PayloadError::Malformed(e.to_string()))?;
+
+    if payload.nonce.len() < MIN_NONCE_LEN {
+        return Err(PayloadError::NonceTooShort(payload.nonce.len()));
+    }
+    if payload.market_id != order.market_id {
+        return Err(PayloadError::WrongMarket { expected: order.market_id, found: payload.market_id });
+    }
+    if payload.round_id != order.round_id {
+        return Err(PayloadError::WrongRound { expected: order.round_id, found: payload.round_id });
+    }
+    if payload.owner != order.owner {
+        return Err(PayloadError::WrongOwner { expected: order.owner, found: payload.owner });
+    }
+    if payload.side != order.order_type {
+        return Err(PayloadError::SideMismatch {
+            submitted: order.order_type.clone(),
+            revealed: payload.side.clone(),
+        });
+    }
+    if payload.amount == 0 {
+        return Err(PayloadError::ZeroAmount);
+    }
+    if payload.price_limit == 0 {
+        return Err(PayloadError::ZeroPrice);
     }
 
-    Ok(true)
+    let canonical = encode_order_payload(&payload)?;
+    Ok((payload, canonical))
+}
+
+// =====================================
+// REVEAL
+// =====================================
+
+/// Orders of a round after decryption: `revealed` carry their decrypted
+/// amount and price limit, `rejected` could not be opened or failed validation
+pub struct RevealedBatch {
+    pub revealed: Vec<Order>,
+    pub rejected: Vec<(Order, VeilError)>,
 }
 
 pub async fn decrypt_order_batch(
-    orders: Vec<crate::types::Order>,
-) -> Result<Vec<crate::types::Order>, String> {
+    orders: Vec<Order>,
+) -> Result<RevealedBatch, VeilError> {
+    let mut batch = RevealedBatch {
+        revealed: Vec::new(),
+        rejected: Vec::new(),
+    };
+
     if orders.is_empty() {
-        return Ok(Vec::new());
+        return Ok(batch);
     }
 
-    let round_id = orders[0].round_id;
-    ic_cdk::println!("Decrypting {} orders for round {}", orders.len(), round_id);
+    let (market_id, round_id) = (orders[0].market_id, orders[0].round_id);
+    ic_cdk::println!("Decrypting {} orders for market {} round {}", orders.len(), market_id, round_id);
 
-    let mut decrypted_orders = Vec::new();
+    // ============================
+    // DEMO MODE — payloads are submitted in the clear
+    // ============================
+    let vetkey = if is_demo() {
+        ic_cdk::println!("⚠️ DEMO MODE: Treating order payloads as plaintext");
+        None
+    } else {
+        Some(fetch_round_vetkey(market_id, round_id).await?)
+    };
 
     for order in orders {
-        // ============================
-        // DEMO MODE — bypass real crypto
-        // ============================
-        if cfg!(feature = "demo") {
-            ic_cdk::println!(
-                "⚠️ DEMO MODE: Skipping decryption + commitment check for order {}",
-                order.id
-            );
-            decrypted_orders.push(order);
-            continue;
+        match reveal_order(&order, vetkey.as_ref()) {
+            Ok(payload) => batch.revealed.push(Order {
+                amount: payload.amount,
+                price_limit: payload.price_limit,
+                ..order
+            }),
+            Err(e) => {
+                ic_cdk::println!("Rejecting order {}: {}", order.id, e);
+                let error = e.for_order(order.id);
+                batch.rejected.push((order, error));
+            }
         }
+    }
 
-        // ============================
-        // REAL MODE (VetKeys)
-        // ============================
-        let decrypted_data = String::from_utf8(order.encrypted_payload.clone())
-            .map_err(|e| format!("Invalid UTF-8 in order {}: {}", order.id, e))?;
-
-        if !verify_commitment(&decrypted_data, &order.commitment_hash)? {
-            return Err(format!(
-                "SECURITY VIOLATION: Commitment hash mismatch for order {}",
-                order.id
-            ));
-        }
+    Ok(batch)
+}
 
-        decrypted_orders.push(order);
-    }
+/// Decrypt one order, parse it and check it against its commitment
+fn reveal_order(order: &Order, vetkey: Option<&VetKey>) -> Result<OrderPayload, PayloadError> {
+    let plaintext = match vetkey {
+        Some(key) => IbeCiphertext::deserialize(&order.encrypted_payload)
+            .and_then(|ciphertext| ciphertext.decrypt(key))
+            .map_err(PayloadError::DecryptionFailed)?,
+        None => order.encrypted_payload.clone(),
+    };
+
+    let (payload, canonical) = decode_order_payload(&plaintext, order)?;
+    verify_commitment(&canonical, &order.commitment_hash)?;
 
-    Ok(decrypted_orders)
+    Ok(payload)
 }
@@ -0,0 +1,707 @@
+use crate::types::*;
+use crate::{
+    memory, Memory, COMPLETED_PAYOUTS_MEMORY_ID, DEMO_BALANCES, ESCROW_CONFIG_MEMORY_ID, ESCROW_NONCES_MEMORY_ID,
+    FAILED_PAYOUTS_MEMORY_ID, ORDER_SETTLEMENTS_MEMORY_ID,
+};
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::call::Call;
+use serde::Deserialize;
+use std::cell::RefCell;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::collections::btree_map::{BTreeMap, Entry};
+use crate::journal::{self, Posting};
+use veil_core::settlement::{self, Settlement};
+
+pub use veil_core::settlement::{locked_asset, required_lock};
+
+// ============================================================================
+// ESCROW
+// ============================================================================
+//
+// Funds backing an order are locked when it is submitted and paid out when
+// its round settles. Three backends are available:
+//
+// * `Demo`     - `DEMO_BALANCES` in stable memory, every new principal starts
+//   funded. Every movement is written to the settlement journal (`journal`).
+// * `Accounts` - the same in-canister book, but every principal starts empty
+//   and funds it with `deposit` (see `accounts`).
+// * `Ledger`   - real ICRC tokens. Submission pulls the lock into the canister
+//   with `icrc2_transfer_from` (the user approves the canister first) and
+//   settlement pays out with `icrc1_transfer`. Every transfer carries a memo
+//   derived from the order id, so a retried transfer is rejected by the
+//   ledger as a duplicate instead of moving funds twice.
+
+thread_local! {
+    static ESCROW_CONFIG: RefCell<StableCell<EscrowConfig, Memory>> = RefCell::new(
+        StableCell::init(memory(ESCROW_CONFIG_MEMORY_ID), EscrowConfig::default())
+    );
+
+    // Ledger payouts that already went through, keyed by their memo
+    static COMPLETED_PAYOUTS: RefCell<StableBTreeMap<Vec<u8>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(COMPLETED_PAYOUTS_MEMORY_ID))
+    );
+
+    // Settlement of every order that took part in a cleared round
+    static ORDER_SETTLEMENTS: RefCell<StableBTreeMap<OrderId, OrderSettlement, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ORDER_SETTLEMENTS_MEMORY_ID))
+    );
+
+    // Last escrow nonce handed out per order, including those of failed
+    // amendments, so no two locks of an order share a memo
+    static ESCROW_NONCES: RefCell<StableBTreeMap<OrderId, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ESCROW_NONCES_MEMORY_ID))
+    );
+
+    // Settlement payouts and refunds awaiting a retry, keyed by order, memo
+    // kind and escrow nonce
+    static FAILED_PAYOUTS: RefCell<StableBTreeMap<(OrderId, u8, u32), FailedPayout, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(FAILED_PAYOUTS_MEMORY_ID))
+    );
+}
+
+// ============================================================================
+// ICRC-1 / ICRC-2 LEDGER INTERFACE (subset used by escrow)
+// ============================================================================
+
+#[derive(CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetTransactionsRequest {
+    start: Nat,
+    length: Nat,
+}
+
+// Fields of `get_transactions` the settlement of a payout reads
+#[derive(CandidType, Deserialize)]
+struct GetTransactionsResponse {
+    first_index: Nat,
+    transactions: Vec<LedgerTransaction>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LedgerTransaction {
+    transfer: Option<LedgerTransfer>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LedgerTransfer {
+    from: Account,
+    to: Account,
+    amount: Nat,
+    memo: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+/// Replace the escrow configuration (backend and per-asset ledgers)
+#[ic_cdk_macros::update]
+pub fn admin_configure_escrow(config: EscrowConfig) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("admin_configure_escrow", format!("{:?}", config));
+    if config.backend != EscrowBackend::Demo && config.ledgers.is_empty() {
+        return Err(VeilError::InvalidArgument(format!("{:?} escrow needs at least one ledger", config.backend)));
+    }
+
+    ESCROW_CONFIG.with(|c| c.borrow_mut().set(config));
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+pub fn get_escrow_config() -> EscrowConfig {
+    ESCROW_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+pub(crate) fn backend() -> EscrowBackend {
+    ESCROW_CONFIG.with(|c| c.borrow().get().backend.clone())
+}
+
+/// The ledger of one asset. Amounts and the fee are in the asset's unit;
+/// the ledger counts in `scale` times smaller ones (wei for gwei).
+pub(crate) struct Ledger {
+    pub ledger_id: Principal,
+    pub fee: u64,
+    scale: u64,
+}
+
+impl Ledger {
+    fn units(&self, amount: u64) -> Nat {
+        Nat::from(amount as u128 * self.scale as u128)
+    }
+
+    /// `units` of the ledger in the asset's unit, rounded down and capped
+    fn amount_of(&self, units: &Nat) -> u64 {
+        u64::try_from(&(units.0.clone() / self.scale)).unwrap_or(u64::MAX)
+    }
+}
+
+pub(crate) fn ledger_for(asset: &Asset) -> Result<Ledger, VeilError> {
+    let config = ESCROW_CONFIG.with(|c| {
+        c.borrow()
+            .get()
+            .ledgers
+            .iter()
+            .find(|(a, _)| a == asset)
+            .map(|(_, l)| l.clone())
+            .ok_or_else(|| VeilError::LedgerNotConfigured(asset.clone()))
+    })?;
+    let scale = match asset {
+        Asset::ETH => 1_000_000_000,  // ETH ledgers count wei
+        Asset::BTC | Asset::USD => 1,
+    };
+    Ok(Ledger { ledger_id: config.ledger_id, fee: config.fee, scale })
+}
+
+// ============================================================================
+// ESCROW OPERATIONS
+// ============================================================================
+
+/// Lock the collateral backing a new order. Size and limit are still
+/// encrypted at this point, so the owner chooses how much to lock.
+pub async fn lock_funds(order: &Order, market: &Market) -> Result<(), VeilError> {
+    let (asset, required) = (locked_asset(market, &order.order_type), order.collateral);
+
+    match backend() {
+        EscrowBackend::Demo | EscrowBackend::Accounts => lock_demo_funds(order, &asset, required),
+        EscrowBackend::Ledger => {
+            let ledger = ledger_for(&asset)?;
+            pull_from_user(&ledger, order.owner, None, required, memo(MemoKind::Lock, order.id, order.escrow_nonce)).await
+        }
+    }
+}
+
+/// Return an order's whole lock to its owner (the order will not settle).
+/// A ledger refund that fails is kept for `retry_settlement_payout`.
+pub async fn release_funds(order: &Order, market: &Market) -> Result<(), VeilError> {
+    let (asset, reserved) = (locked_asset(market, &order.order_type), order.collateral);
+
+    match backend() {
+        EscrowBackend::Demo | EscrowBackend::Accounts => release_demo_funds(order, &asset, reserved),
+        EscrowBackend::Ledger => {
+            let payout = FailedPayout {
+                order_id: order.id,
+                owner: order.owner,
+                asset,
+                amount: reserved,
+                memo: memo(MemoKind::Refund, order.id, order.escrow_nonce),
+                created_at: ic_cdk::api::time(),
+                error: String::new(),
+            };
+            let result = send_payout(&payout).await;
+            if let Err(e) = &result {
+                keep_failed_payout((order.id, MemoKind::Refund as u8, order.escrow_nonce), payout, e);
+            }
+            result
+        }
+    }
+}
+
+/// Release the locks of a cleared round's orders and deliver their fills at
+/// the clearing price. Every settlement is worked out, checked
+/// (`settlement::settle_round`) and recorded, and on the demo book applied,
+/// before anything moves: if one breaks an invariant the whole round fails
+/// with nothing settled. Ledger payouts then go out one by one; a failed one
+/// is kept for `retry_settlement_payout`, its memo keeping it from being
+/// paid twice.
+pub async fn settle_round(
+    market: &Market,
+    fills: &[(Order, OrderMatch)],
+    clearing_price: u64,
+    settled_at: Timestamp,
+) -> Result<Vec<u64>, VeilError> {
+    let fee_rate = |order: &Order| crate::fees::fee_rate(order.market_id, order.owner);
+    let settlements = settlement::settle_round(market, fills, clearing_price, fee_rate)?;
+    let settled: Vec<(&Order, Settlement)> = fills.iter().map(|(order, _)| order).zip(settlements).collect();
+
+    if backend() != EscrowBackend::Ledger {
+        settle_demo_round(&settled)?;
+    }
+    for ((order, s), (_, fill)) in settled.iter().zip(fills) {
+        record_settlement(order, s, fill, clearing_price, settled_at);
+        crate::fees::collect(&s.received_asset, s.fee);
+    }
+    let fees = settled.iter().map(|(_, s)| s.fee).collect();
+
+    if backend() == EscrowBackend::Ledger {
+        for (order, s) in &settled {
+            pay_out_settlement(order, s, settled_at).await;
+        }
+    }
+    Ok(fees)
+}
+
+fn record_settlement(order: &Order, s: &Settlement, fill: &OrderMatch, clearing_price: u64, settled_at: Timestamp) {
+    let record = OrderSettlement {
+        order_id: order.id,
+        market_id: order.market_id,
+        round_id: order.round_id,
+        owner: order.owner,
+        side: order.order_type.clone(),
+        fill_amount: fill.fill_amount,
+        clearing_price,
+        paid_asset: s.locked_asset.clone(),
+        paid: s.paid(),
+        refunded: s.refund,
+        received_asset: s.received_asset.clone(),
+        received: s.received,
+        fee: s.fee,
+        settled_at,
+    };
+    ORDER_SETTLEMENTS.with(|m| m.borrow_mut().insert(order.id, record));
+}
+
+/// What an order paid, received and got back when its round settled
+#[ic_cdk_macros::query]
+pub fn get_order_settlement(order_id: OrderId) -> Option<OrderSettlement> {
+    ORDER_SETTLEMENTS.with(|m| m.borrow().get(&order_id))
+}
+
+/// Pay an order its fill and refund, keeping whichever fails for a retry
+async fn pay_out_settlement(order: &Order, s: &Settlement, settled_at: Timestamp) {
+    let payouts = [
+        (MemoKind::Fill, &s.received_asset, s.received),
+        (MemoKind::Refund, &s.locked_asset, s.refund),
+    ];
+    for (kind, asset, amount) in payouts {
+        let payout = FailedPayout {
+            order_id: order.id,
+            owner: order.owner,
+            asset: asset.clone(),
+            amount,
+            memo: memo(kind, order.id, order.escrow_nonce),
+            created_at: settled_at,
+            error: String::new(),
+        };
+        if let Err(e) = send_payout(&payout).await {
+            ic_cdk::println!("Failed to pay out order {}: {}", order.id, e);
+            keep_failed_payout((order.id, kind as u8, order.escrow_nonce), payout, &e);
+        }
+    }
+}
+
+fn keep_failed_payout(key: (OrderId, u8, u32), payout: FailedPayout, error: &VeilError) {
+    FAILED_PAYOUTS.with(|f| f.borrow_mut().insert(key, FailedPayout { error: error.to_string(), ..payout }));
+}
+
+async fn send_payout(payout: &FailedPayout) -> Result<(), VeilError> {
+    let ledger = ledger_for(&payout.asset)?;
+    pay_out(&ledger, None, &account(payout.owner), payout.amount, payout.memo.clone(), payout.created_at).await
+}
+
+/// Resend the failed settlement payouts and refunds of an order (operator);
+/// returns how many went through. The ledger only deduplicates within its transaction
+/// window, so retry within a day of settlement.
+#[ic_cdk_macros::update]
+pub async fn retry_settlement_payout(order_id: OrderId) -> Result<u64, VeilError> {
+    crate::access::require_operator()?;
+    crate::access::audit("retry_settlement_payout", format!("order {}", order_id));
+
+    let failed: Vec<((OrderId, u8, u32), FailedPayout)> = FAILED_PAYOUTS.with(|f| {
+        f.borrow()
+            .range((order_id, 0, 0)..=(order_id, u8::MAX, u32::MAX))
+            .map(|entry| (*entry.key(), entry.value()))
+            .collect()
+    });
+    if failed.is_empty() {
+        return Err(VeilError::InvalidArgument(format!("Order {} has no failed payouts", order_id)));
+    }
+
+    let mut paid = 0;
+    let mut last_error = None;
+    for (key, payout) in failed {
+        match send_payout(&payout).await {
+            Ok(()) => {
+                FAILED_PAYOUTS.with(|f| f.borrow_mut().remove(&key));
+                paid += 1;
+            }
+            Err(e) => {
+                keep_failed_payout(key, payout, &e);
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) => Err(e),
+        None => Ok(paid),
+    }
+}
+
+/// Settlement payouts and refunds awaiting a retry (operator)
+#[ic_cdk_macros::query]
+pub fn get_failed_payouts() -> Result<Vec<FailedPayout>, VeilError> {
+    crate::access::require_operator()?;
+    Ok(FAILED_PAYOUTS.with(|f| f.borrow().iter().map(|entry| entry.value()).collect()))
+}
+
+// ============================================================================
+// LEDGER BACKEND
+// ============================================================================
+
+#[derive(Clone, Copy)]
+pub(crate) enum MemoKind {
+    Lock = 1,
+    Fill = 2,
+    Refund = 3,
+    Deposit = 4,
+    Withdrawal = 5,
+    Treasury = 6,
+}
+
+/// "VEIL" | kind | order id | escrow nonce (big endian) - unique per lock
+/// and transfer kind, so every amendment, failed or not, gets fresh memos.
+/// Deposits, withdrawals and treasury withdrawals use their own id and
+/// nonce 0.
+pub(crate) fn memo(kind: MemoKind, order_id: OrderId, nonce: u32) -> Vec<u8> {
+    let mut memo = Vec::with_capacity(17);
+    memo.extend_from_slice(b"VEIL");
+    memo.push(kind as u8);
+    memo.extend_from_slice(&order_id.to_be_bytes());
+    memo.extend_from_slice(&nonce.to_be_bytes());
+    memo
+}
+
+/// Escrow nonce of a new order's first lock
+pub(crate) fn first_escrow_nonce(order_id: OrderId) -> u32 {
+    ESCROW_NONCES.with(|n| n.borrow_mut().insert(order_id, 0));
+    0
+}
+
+/// Escrow nonce of an amendment's lock, recorded before the lock is taken
+/// so a failed amendment still uses it up. Orders from before nonces were
+/// recorded tagged locks with their revision, and a failed amendment may
+/// have used revision + 1.
+pub(crate) fn next_escrow_nonce(order: &Order) -> u32 {
+    ESCROW_NONCES.with(|n| {
+        let mut nonces = n.borrow_mut();
+        let last = nonces.get(&order.id).unwrap_or(order.escrow_nonce + 1);
+        nonces.insert(order.id, last + 1);
+        last + 1
+    })
+}
+
+pub(crate) fn account(owner: Principal) -> Account {
+    Account {
+        owner,
+        subaccount: None,
+    }
+}
+
+/// Pull `amount` from `user` into the canister's `to_subaccount`
+pub(crate) async fn pull_from_user(
+    ledger: &Ledger,
+    user: Principal,
+    to_subaccount: Option<Vec<u8>>,
+    amount: u64,
+    memo: Vec<u8>,
+) -> Result<(), VeilError> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: account(user),
+        to: Account {
+            owner: ic_cdk::api::canister_self(),
+            subaccount: to_subaccount,
+        },
+        amount: ledger.units(amount),
+        fee: None,
+        memo: Some(memo),
+        created_at_time: Some(ic_cdk::api::time()),
+    };
+
+    let res: Result<Nat, TransferFromError> = Call::unbounded_wait(ledger.ledger_id, "icrc2_transfer_from")
+        .with_arg(args)
+        .await
+        .map_err(|e| call_failed("icrc2_transfer_from", e))?
+        .candid()
+        .map_err(|e| call_failed("icrc2_transfer_from", e))?;
+
+    match res {
+        Ok(_) | Err(TransferFromError::Duplicate { .. }) => Ok(()),
+        Err(TransferFromError::InsufficientFunds { balance }) => Err(VeilError::InsufficientBalance {
+            required: amount,
+            available: ledger.amount_of(&balance),
+        }),
+        Err(TransferFromError::InsufficientAllowance { allowance }) => Err(VeilError::InsufficientAllowance {
+            required: amount,
+            approved: ledger.amount_of(&allowance),
+        }),
+        Err(e) => Err(VeilError::TransferFailed(format!("icrc2_transfer_from: {:?}", e))),
+    }
+}
+
+/// Transfer `amount` (minus the ledger fee) from the canister's
+/// `from_subaccount` to `to`. Amounts that do not cover the fee stay in the
+/// canister.
+pub(crate) async fn pay_out(
+    ledger: &Ledger,
+    from_subaccount: Option<Vec<u8>>,
+    to: &Account,
+    amount: u64,
+    memo: Vec<u8>,
+    created_at_time: Timestamp,
+) -> Result<(), VeilError> {
+    if amount <= ledger.fee {
+        return Ok(());
+    }
+    if COMPLETED_PAYOUTS.with(|p| p.borrow().contains_key(&memo)) {
+        return Ok(());
+    }
+
+    let args = TransferArg {
+        from_subaccount,
+        to: to.clone(),
+        amount: ledger.units(amount - ledger.fee),
+        fee: Some(ledger.units(ledger.fee)),
+        memo: Some(memo.clone()),
+        created_at_time: Some(created_at_time),
+    };
+
+    let res: Result<Nat, TransferError> = Call::unbounded_wait(ledger.ledger_id, "icrc1_transfer")
+        .with_arg(args)
+        .await
+        .map_err(|e| call_failed("icrc1_transfer", e))?
+        .candid()
+        .map_err(|e| call_failed("icrc1_transfer", e))?;
+
+    match res {
+        Ok(_) | Err(TransferError::Duplicate { .. }) => {
+            COMPLETED_PAYOUTS.with(|p| p.borrow_mut().insert(memo, ()));
+            Ok(())
+        }
+        // Outside the deduplication window the ledger cannot tell whether an
+        // earlier attempt went through; only its transaction log can
+        Err(e @ (TransferError::TooOld | TransferError::CreatedInFuture { .. })) => {
+            Err(VeilError::TransferOutcomeUnknown(format!("icrc1_transfer: {:?}", e)))
+        }
+        Err(e) => Err(VeilError::TransferFailed(format!("icrc1_transfer: {:?}", e))),
+    }
+}
+
+/// Whether ledger block `index` is a payout of `amount` (fee already
+/// deducted) from `from` to `to` under `memo`. Archived blocks cannot be read.
+pub(crate) async fn is_payout_block(
+    ledger: &Ledger,
+    index: u64,
+    from: &Account,
+    to: &Account,
+    amount: u64,
+    memo: &[u8],
+) -> Result<bool, VeilError> {
+    let args = GetTransactionsRequest {
+        start: Nat::from(index),
+        length: Nat::from(1u64),
+    };
+    let res: GetTransactionsResponse = Call::unbounded_wait(ledger.ledger_id, "get_transactions")
+        .with_arg(args)
+        .await
+        .map_err(|e| call_failed("get_transactions", e))?
+        .candid()
+        .map_err(|e| call_failed("get_transactions", e))?;
+
+    if res.first_index != index || res.transactions.is_empty() {
+        return Err(VeilError::InvalidArgument(format!("Ledger block {} is archived or not written yet", index)));
+    }
+    Ok(res.transactions[0].transfer.as_ref().is_some_and(|t| {
+        t.from == *from
+            && t.to == *to
+            && t.amount == ledger.units(amount.saturating_sub(ledger.fee))
+            && t.memo.as_deref() == Some(memo)
+    }))
+}
+
+fn call_failed(method: &str, e: impl std::fmt::Display) -> VeilError {
+    VeilError::CallFailed {
+        method: method.to_string(),
+        reason: e.to_string(),
+    }
+}
+
+// ============================================================================
+// DEMO BACKEND
+// ============================================================================
+
+/// `user`'s balance, or the one they would start with, without creating it
+pub fn demo_balance(user: Principal) -> DemoUserBalance {
+    DEMO_BALANCES.with(|b| b.borrow().get(&user)).unwrap_or_else(initial_demo_balance)
+}
+
+/// Balance a principal starts with: funded on the demo backend, empty for
+/// trading accounts (funded by `deposit`)
+fn initial_demo_balance() -> DemoUserBalance {
+    if backend() != EscrowBackend::Demo {
+        return DemoUserBalance::default();
+    }
+    DemoUserBalance {
+        btc_free: 1_000_000_000,
+        btc_locked: 0,
+        eth_free: 10_000_000_000,
+        eth_locked: 0,
+        usd_free: 10_000_000_000,
+        usd_locked: 0,
+    }
+}
+
+fn lock_demo_funds(order: &Order, asset: &Asset, required: u64) -> Result<(), VeilError> {
+    with_demo_balance_mut(&order.owner, |bal| {
+        let (free, locked) = bal.slots_mut(asset);
+        if *free < required {
+            return Err(VeilError::InsufficientBalance { required, available: *free });
+        }
+
+        *locked = locked.checked_add(required).ok_or(VeilError::Overflow)?;
+        *free -= required;
+
+        Ok(())
+    })?;
+
+    journal::record(vec![Posting::new(JournalReason::Lock, Some(order))
+        .leg(JournalAccount::Free(order.owner), asset, -(required as i128))
+        .leg(JournalAccount::Locked(order.owner), asset, required)]);
+    Ok(())
+}
+
+fn release_demo_funds(order: &Order, asset: &Asset, reserved: u64) -> Result<(), VeilError> {
+    with_demo_balance_mut(&order.owner, |bal| {
+        let (free, locked) = bal.slots_mut(asset);
+        if *locked < reserved {
+            return Err(short_lock(order, asset, *locked, reserved));
+        }
+
+        *free = free.checked_add(reserved).ok_or(VeilError::Overflow)?;
+        *locked -= reserved;
+
+        Ok(())
+    })?;
+
+    journal::record(vec![Posting::new(JournalReason::Release, Some(order))
+        .leg(JournalAccount::Locked(order.owner), asset, -(reserved as i128))
+        .leg(JournalAccount::Free(order.owner), asset, reserved)]);
+    Ok(())
+}
+
+/// Apply a round's settlements to staged copies of the owners' balances and
+/// write them back, with their journal postings, only if every one applies
+fn settle_demo_round(settlements: &[(&Order, Settlement)]) -> Result<(), VeilError> {
+    let mut staged: BTreeMap<Principal, DemoUserBalance> = BTreeMap::new();
+    let mut postings = Vec::new();
+
+    for (order, s) in settlements {
+        let owner = order.owner;
+        let bal = match staged.entry(owner) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let bal = DEMO_BALANCES
+                    .with(|b| b.borrow().get(&owner))
+                    .ok_or_else(|| VeilError::Internal(format!("Order {} owner {} has no balance", order.id, owner)))?;
+                entry.insert(bal)
+            }
+        };
+
+        let (free, locked) = bal.slots_mut(&s.locked_asset);
+        if *locked < s.reserved {
+            return Err(short_lock(order, &s.locked_asset, *locked, s.reserved));
+        }
+        *locked -= s.reserved;
+        *free = free.checked_add(s.refund).ok_or(VeilError::Overflow)?;
+
+        let (received_free, _) = bal.slots_mut(&s.received_asset);
+        *received_free = received_free.checked_add(s.received).ok_or(VeilError::Overflow)?;
+
+        let clearing = JournalAccount::Clearing(order.market_id);
+        postings.push(
+            Posting::new(JournalReason::FillDebit, Some(order))
+                .leg(JournalAccount::Locked(owner), &s.locked_asset, -(s.reserved as i128))
+                .leg(JournalAccount::Free(owner), &s.locked_asset, s.refund)
+                .leg(clearing.clone(), &s.locked_asset, s.paid()),
+        );
+        postings.push(
+            Posting::new(JournalReason::FillCredit, Some(order))
+                .leg(clearing.clone(), &s.received_asset, -(s.received as i128))
+                .leg(JournalAccount::Free(owner), &s.received_asset, s.received),
+        );
+        postings.push(
+            Posting::new(JournalReason::Fee, Some(order))
+                .leg(clearing, &s.received_asset, -(s.fee as i128))
+                .leg(JournalAccount::Treasury, &s.received_asset, s.fee),
+        );
+    }
+
+    DEMO_BALANCES.with(|b| {
+        let mut balances = b.borrow_mut();
+        for (owner, bal) in staged {
+            balances.insert(owner, bal);
+        }
+    });
+    journal::record(postings);
+    Ok(())
+}
+
+fn short_lock(order: &Order, asset: &Asset, locked: u64, reserved: u64) -> VeilError {
+    VeilError::Internal(format!(
+        "Order {}: {:?} locked {} < reserved {} for user {}",
+        order.id, asset, locked, reserved, order.owner
+    ))
+}
+
+// Helper to get mutable balance for a user; a new user starts with
+// `initial_demo_balance`
+pub(crate) fn with_demo_balance_mut<R>(user: &Principal, f: impl FnOnce(&mut DemoUserBalance) -> R) -> R {
+    DEMO_BALANCES.with(|balances| {
+        let mut map = balances.borrow_mut();
+        let mut bal = match map.get(user) {
+            Some(bal) => bal,
+            None => {
+                let bal = initial_demo_balance();
+                journal::record(vec![journal::deposit(*user, &bal, JournalReason::Deposit)]);
+                bal
+            }
+        };
+        let result = f(&mut bal);
+        map.insert(*user, bal);
+        result
+    })
+}
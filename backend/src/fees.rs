@@ -0,0 +1,341 @@
+use crate::accounts;
+use crate::escrow::{self, MemoKind};
+use crate::journal::{self, Posting};
+use crate::types::*;
+use crate::{memory, Memory, FEE_DISCOUNTS_MEMORY_ID, FEE_RATES_MEMORY_ID, TREASURY_MEMORY_ID};
+use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use veil_core::settlement::MAX_FEE_BPS;
+
+// ============================================================================
+// TRADING FEES
+// ============================================================================
+//
+// Each market charges a rate, in basis points, of what an order receives when
+// it fills: base for a buyer, quote for a seller. Every order in a batch pays
+// the same rate, as a batch auction has no makers or takers. A principal may
+// hold a discount, in basis points off the market rate. Markets without a
+// rate charge nothing.
+//
+// Fees are withheld at settlement (`escrow::settle_round`) and collect in the
+// treasury. On the in-canister book they move from the market's clearing
+// account to `Treasury` in the journal; with ledger escrow they are simply
+// not paid out. The tokens stay with the canister until an admin withdraws
+// them: in its ledger account, or with trading accounts in the subaccounts
+// of the traders who paid them (`accounts::payout_source`). The treasury
+// keeps apart what each escrow backend collected, and only the configured
+// backend's fees can be withdrawn: demo balances are not backed by tokens,
+// so fees collected on the demo backend never can, even after a switch to a
+// token-backed backend.
+
+thread_local! {
+    static FEE_RATES: RefCell<StableBTreeMap<MarketId, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(FEE_RATES_MEMORY_ID))
+    );
+
+    static FEE_DISCOUNTS: RefCell<StableBTreeMap<Principal, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(FEE_DISCOUNTS_MEMORY_ID))
+    );
+
+    static TREASURY: RefCell<StableCell<Treasury, Memory>> = RefCell::new(
+        StableCell::init(memory(TREASURY_MEMORY_ID), Treasury::default())
+    );
+
+    // Fee withdrawals whose ledger call is awaiting an answer; starts empty,
+    // as an upgrade waits for every call to return
+    static TREASURY_SENDING: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+fn check_bps(what: &str, bps: u32) -> Result<(), VeilError> {
+    if bps > MAX_FEE_BPS {
+        return Err(VeilError::InvalidArgument(format!("{} {} bps exceeds {} bps", what, bps, MAX_FEE_BPS)));
+    }
+    Ok(())
+}
+
+fn with_treasury_mut<R>(f: impl FnOnce(&mut Treasury) -> R) -> R {
+    TREASURY.with(|t| {
+        let mut cell = t.borrow_mut();
+        let mut treasury = cell.get().clone();
+        let result = f(&mut treasury);
+        cell.set(treasury);
+        result
+    })
+}
+
+/// Rate `owner` pays in `market_id`: the market rate less their discount,
+/// rounded down
+pub(crate) fn fee_rate(market_id: MarketId, owner: Principal) -> u32 {
+    let rate = FEE_RATES.with(|r| r.borrow().get(&market_id)).unwrap_or(0) as u64;
+    let discount = FEE_DISCOUNTS.with(|d| d.borrow().get(&owner)).unwrap_or(0) as u64;
+    (rate * (MAX_FEE_BPS as u64 - discount) / MAX_FEE_BPS as u64) as u32
+}
+
+/// Add fees withheld at settlement to the treasury
+pub(crate) fn collect(asset: &Asset, fee: u64) {
+    if fee == 0 {
+        return;
+    }
+    let backend = escrow::backend();
+    with_treasury_mut(|t| fee_totals(t, backend, asset).collected += fee as u128);
+}
+
+fn fee_totals<'a>(treasury: &'a mut Treasury, backend: EscrowBackend, asset: &Asset) -> &'a mut FeeTotals {
+    treasury.backends.entry(backend).or_default().entry(asset.clone()).or_default()
+}
+
+/// What is left of `totals` to withdraw, capped to fit a ledger amount
+fn available(totals: &FeeTotals) -> u64 {
+    u64::try_from(totals.collected - totals.withdrawn).unwrap_or(u64::MAX)
+}
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+/// Set the fee rate of a market; 0 charges nothing
+#[ic_cdk_macros::update]
+pub fn admin_set_fee_rate(market_id: MarketId, fee_bps: u32) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("admin_set_fee_rate", format!("market {}: {} bps", market_id, fee_bps));
+    crate::market_state(market_id)?;
+    check_bps("Fee rate", fee_bps)?;
+
+    FEE_RATES.with(|r| {
+        let mut rates = r.borrow_mut();
+        if fee_bps == 0 {
+            rates.remove(&market_id);
+        } else {
+            rates.insert(market_id, fee_bps);
+        }
+    });
+    Ok(())
+}
+
+/// Give `principal` a discount off every market's rate, or take it away
+#[ic_cdk_macros::update]
+pub fn admin_set_fee_discount(principal: Principal, discount_bps: Option<u32>) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("admin_set_fee_discount", format!("{}: {:?} bps", principal, discount_bps));
+
+    match discount_bps {
+        Some(bps) => {
+            check_bps("Discount", bps)?;
+            FEE_DISCOUNTS.with(|d| d.borrow_mut().insert(principal, bps));
+        }
+        None => {
+            FEE_DISCOUNTS.with(|d| d.borrow_mut().remove(&principal));
+        }
+    }
+    Ok(())
+}
+
+/// Rate `owner` pays in `market_id`, discount included
+#[ic_cdk_macros::query]
+pub fn get_fee_rate(market_id: MarketId, owner: Principal) -> u32 {
+    fee_rate(market_id, owner)
+}
+
+#[ic_cdk_macros::query]
+pub fn get_fee_schedule() -> Result<FeeSchedule, VeilError> {
+    crate::access::require_admin()?;
+    Ok(FeeSchedule {
+        rates: FEE_RATES.with(|r| r.borrow().iter().map(|e| (*e.key(), e.value())).collect()),
+        discounts: FEE_DISCOUNTS.with(|d| d.borrow().iter().map(|e| (*e.key(), e.value())).collect()),
+    })
+}
+
+// ============================================================================
+// TREASURY
+// ============================================================================
+
+#[ic_cdk_macros::query]
+pub fn get_treasury() -> Vec<TreasuryBalance> {
+    TREASURY.with(|t| {
+        t.borrow()
+            .get()
+            .backends
+            .iter()
+            .flat_map(|(backend, assets)| {
+                assets.iter().map(|(asset, totals)| TreasuryBalance {
+                    backend: backend.clone(),
+                    asset: asset.clone(),
+                    collected: totals.collected,
+                    withdrawn: totals.withdrawn,
+                    balance: available(totals),
+                })
+            })
+            .collect()
+    })
+}
+
+/// Fee withdrawals whose outcome is not known yet (admin)
+#[ic_cdk_macros::query]
+pub fn get_treasury_withdrawals() -> Result<Vec<TreasuryWithdrawal>, VeilError> {
+    crate::access::require_admin()?;
+    Ok(TREASURY.with(|t| t.borrow().get().pending.values().cloned().collect()))
+}
+
+/// Send `amount` of collected `asset` fees to `to`, which receives it less
+/// the ledger fee; returns the withdrawal id. Only fees the configured
+/// backend collected can be withdrawn. The amount leaves the treasury
+/// before the transfer, so concurrent withdrawals cannot overdraw it. A
+/// rejected transfer puts it back; after an unknown outcome it stays out,
+/// pending in `get_treasury_withdrawals`, until `retry_treasury_withdrawal`
+/// or `settle_treasury_withdrawal` resolves it.
+#[ic_cdk_macros::update]
+pub async fn admin_withdraw_fees(asset: Asset, amount: u64, to: Account) -> Result<u64, VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("admin_withdraw_fees", format!("{} {:?} to {}", amount, asset, to.owner));
+    let backend = escrow::backend();
+    if backend == EscrowBackend::Demo {
+        return Err(VeilError::InvalidArgument("Demo fees are not backed by tokens".to_string()));
+    }
+    let ledger = escrow::ledger_for(&asset)?;
+    if amount <= ledger.fee {
+        return Err(VeilError::BelowLedgerFee { amount, fee: ledger.fee });
+    }
+
+    let available = TREASURY.with(|t| {
+        let treasury = t.borrow();
+        treasury.get().backends.get(&backend).and_then(|assets| assets.get(&asset)).map_or(0, available)
+    });
+    if available < amount {
+        return Err(VeilError::InsufficientTreasury { asset: asset.clone(), requested: amount, available });
+    }
+    // On the book the fees sit in the traders' subaccounts
+    let source = match backend {
+        EscrowBackend::Accounts => Some(accounts::payout_source(None, &asset, amount)?),
+        _ => None,
+    };
+
+    let withdrawal = with_treasury_mut(|t| {
+        fee_totals(t, backend.clone(), &asset).withdrawn += amount as u128;
+        let withdrawal = TreasuryWithdrawal {
+            id: t.next_withdrawal,
+            backend,
+            asset: asset.clone(),
+            amount,
+            to,
+            source,
+            created_at: ic_cdk::api::time(),
+        };
+        t.next_withdrawal += 1;
+        t.pending.insert(withdrawal.id, withdrawal.clone());
+        withdrawal
+    });
+    if let Some(source) = source {
+        accounts::take_custody(source, &asset, amount);
+    }
+
+    send_exclusively(&withdrawal).await.map(|()| withdrawal.id)
+}
+
+/// Resend a fee withdrawal whose outcome is unknown (admin). The ledger
+/// drops it as a duplicate if the first attempt went through.
+#[ic_cdk_macros::update]
+pub async fn retry_treasury_withdrawal(id: u64) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("retry_treasury_withdrawal", format!("withdrawal {}", id));
+    let withdrawal = pending_withdrawal(id)?;
+    send_exclusively(&withdrawal).await
+}
+
+/// Settle a pending fee withdrawal from the ledger's transaction log
+/// (admin): `Some(block)` completes it if that block is its payout, `None`
+/// records that no block carries its memo and returns the amount to the
+/// treasury. `None` is only taken once the ledger would refuse any resend.
+#[ic_cdk_macros::update]
+pub async fn settle_treasury_withdrawal(id: u64, block_index: Option<u64>) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("settle_treasury_withdrawal", format!("withdrawal {}: block {:?}", id, block_index));
+    let withdrawal = pending_withdrawal(id)?;
+    if TREASURY_SENDING.with(|s| s.borrow().contains(&id)) {
+        return Err(VeilError::WithdrawalInFlight);
+    }
+
+    match block_index {
+        Some(index) => {
+            let ledger = escrow::ledger_for(&withdrawal.asset)?;
+            let from = Account {
+                owner: ic_cdk::api::canister_self(),
+                subaccount: withdrawal.source.map(accounts::subaccount),
+            };
+            let memo = escrow::memo(MemoKind::Treasury, id, 0);
+            if !escrow::is_payout_block(&ledger, index, &from, &withdrawal.to, withdrawal.amount, &memo).await? {
+                return Err(VeilError::InvalidArgument(format!("Ledger block {} is not the payout of withdrawal {}", index, id)));
+            }
+            // Another settle may have resolved it while the ledger answered
+            complete_withdrawal(&pending_withdrawal(id)?);
+        }
+        None => {
+            let resendable_until = withdrawal.created_at.saturating_add(accounts::DEDUP_WINDOW_NANOS);
+            if ic_cdk::api::time() <= resendable_until {
+                return Err(VeilError::InvalidArgument(format!(
+                    "Withdrawal {} can still be resent until {}; retry it instead",
+                    id, resendable_until
+                )));
+            }
+            return_withdrawal(&withdrawal);
+        }
+    }
+    Ok(())
+}
+
+fn pending_withdrawal(id: u64) -> Result<TreasuryWithdrawal, VeilError> {
+    TREASURY
+        .with(|t| t.borrow().get().pending.get(&id).cloned())
+        .ok_or(VeilError::UnknownTransfer(id))
+}
+
+/// Send `withdrawal` unless another call is already awaiting its ledger call
+async fn send_exclusively(withdrawal: &TreasuryWithdrawal) -> Result<(), VeilError> {
+    if !TREASURY_SENDING.with(|s| s.borrow_mut().insert(withdrawal.id)) {
+        return Err(VeilError::WithdrawalInFlight);
+    }
+    let result = send_withdrawal(withdrawal).await;
+    TREASURY_SENDING.with(|s| s.borrow_mut().remove(&withdrawal.id));
+    result
+}
+
+async fn send_withdrawal(withdrawal: &TreasuryWithdrawal) -> Result<(), VeilError> {
+    let ledger = escrow::ledger_for(&withdrawal.asset)?;
+    let memo = escrow::memo(MemoKind::Treasury, withdrawal.id, 0);
+    let from = withdrawal.source.map(accounts::subaccount);
+    match escrow::pay_out(&ledger, from, &withdrawal.to, withdrawal.amount, memo, withdrawal.created_at).await {
+        Ok(()) => {
+            complete_withdrawal(withdrawal);
+            Ok(())
+        }
+        Err(e @ VeilError::TransferFailed(_)) => {
+            // The ledger refused: nothing left the canister
+            return_withdrawal(withdrawal);
+            Err(e)
+        }
+        // Unknown outcome: it stays pending
+        Err(e) => Err(e),
+    }
+}
+
+/// The fees left: record them leaving the book
+fn complete_withdrawal(withdrawal: &TreasuryWithdrawal) {
+    with_treasury_mut(|t| t.pending.remove(&withdrawal.id));
+    if withdrawal.backend != EscrowBackend::Ledger {
+        journal::record(vec![Posting::new(JournalReason::TreasuryWithdrawal, None)
+            .leg(JournalAccount::Treasury, &withdrawal.asset, -(withdrawal.amount as i128))
+            .leg(JournalAccount::External, &withdrawal.asset, withdrawal.amount)]);
+    }
+}
+
+/// Nothing left: put the amount back in the treasury and the subaccount
+fn return_withdrawal(withdrawal: &TreasuryWithdrawal) {
+    with_treasury_mut(|t| {
+        t.pending.remove(&withdrawal.id);
+        fee_totals(t, withdrawal.backend.clone(), &withdrawal.asset).withdrawn -= withdrawal.amount as u128;
+    });
+    if let Some(source) = withdrawal.source {
+        accounts::return_custody(source, &withdrawal.asset, withdrawal.amount);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::queries::MAX_PAGE_SIZE;
+use crate::types::*;
+use crate::{memory, Memory, DEMO_BALANCES, JOURNAL_MEMORY_ID, JOURNAL_TOTALS_MEMORY_ID};
+use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use std::cell::RefCell;
+
+// ============================================================================
+// SETTLEMENT JOURNAL
+// ============================================================================
+//
+// Double-entry record of every movement on the in-canister balance book
+// (`DEMO_BALANCES`). A movement is one posting with a leg per account it
+// touches, and the legs of a posting sum to zero per asset:
+//
+//   Deposit      External         -> Free(user)
+//   Lock         Free(user)       -> Locked(user)
+//   Release      Locked(user)     -> Free(user)
+//   FillDebit    Locked(user)     -> Clearing(market), unspent part -> Free(user)
+//   FillCredit   Clearing(market) -> Free(user)
+//   WithdrawalRequest  Free(user)        -> Withdrawing(user)
+//   Withdrawal         Withdrawing(user) -> External
+//   WithdrawalRefund   Withdrawing(user) -> Free(user)
+//   Fee                Clearing(market)  -> Treasury
+//   TreasuryWithdrawal Treasury          -> External
+//
+// A market's clearing account passes value from one side of a round to the
+// other; what stays in it is the rounding dust settlement keeps. Running
+// totals kept next to the journal let `reconcile` check the balances
+// without replaying it.
+//
+// Ledger escrow moves tokens on the ICRC ledgers, whose transaction logs
+// (with a memo per order) are its record; it is not journaled here.
+
+thread_local! {
+    static JOURNAL: RefCell<StableBTreeMap<u64, JournalEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(JOURNAL_MEMORY_ID))
+    );
+
+    static JOURNAL_TOTALS: RefCell<StableCell<JournalTotals, Memory>> = RefCell::new(
+        StableCell::init(memory(JOURNAL_TOTALS_MEMORY_ID), JournalTotals::default())
+    );
+}
+
+/// A balanced set of legs about to be journaled
+pub(crate) struct Posting {
+    reason: JournalReason,
+    market_id: Option<MarketId>,
+    round_id: Option<RoundId>,
+    order_id: Option<OrderId>,
+    legs: Vec<(JournalAccount, Asset, i128)>,
+}
+
+impl Posting {
+    pub(crate) fn new(reason: JournalReason, order: Option<&Order>) -> Self {
+        Posting {
+            reason,
+            market_id: order.map(|o| o.market_id),
+            round_id: order.map(|o| o.round_id),
+            order_id: order.map(|o| o.id),
+            legs: Vec::new(),
+        }
+    }
+
+    /// Add a leg; zero deltas are left out
+    pub(crate) fn leg(mut self, account: JournalAccount, asset: &Asset, delta: impl Into<i128>) -> Self {
+        let delta = delta.into();
+        if delta != 0 {
+            self.legs.push((account, asset.clone(), delta));
+        }
+        self
+    }
+
+    fn is_balanced(&self) -> bool {
+        Asset::ALL.iter().all(|asset| {
+            self.legs.iter().filter(|(_, a, _)| a == asset).map(|(_, _, d)| d).sum::<i128>() == 0
+        })
+    }
+}
+
+/// Append postings to the journal. Callers apply the balance changes they
+/// describe in the same message, so both commit or neither does; an
+/// unbalanced posting is a bug and traps, rolling back the whole message.
+pub(crate) fn record(postings: Vec<Posting>) {
+    let now = ic_cdk::api::time();
+    let mut totals = JOURNAL_TOTALS.with(|t| t.borrow().get().clone());
+
+    JOURNAL.with(|j| {
+        let mut journal = j.borrow_mut();
+        for posting in postings.into_iter().filter(|p| !p.legs.is_empty()) {
+            if !posting.is_balanced() {
+                ic_cdk::trap(format!("Unbalanced {:?} posting: {:?}", posting.reason, posting.legs));
+            }
+            let id = totals.next_posting;
+            totals.next_posting += 1;
+
+            for (account, asset, delta) in posting.legs {
+                let sums = totals.assets.entry(asset.clone()).or_default();
+                match account {
+                    JournalAccount::External if delta < 0 => sums.deposits += delta.unsigned_abs(),
+                    JournalAccount::External => sums.withdrawals += delta as u128,
+                    JournalAccount::Clearing(_) => sums.clearing += delta,
+                    JournalAccount::Treasury => sums.treasury += delta,
+                    JournalAccount::Free(_) | JournalAccount::Locked(_) | JournalAccount::Withdrawing(_) => {}
+                }
+
+                let entry = JournalEntry {
+                    id: totals.next_entry,
+                    posting: id,
+                    timestamp: now,
+                    market_id: posting.market_id,
+                    round_id: posting.round_id,
+                    order_id: posting.order_id,
+                    account,
+                    asset,
+                    delta,
+                    reason: posting.reason.clone(),
+                };
+                journal.insert(entry.id, entry);
+                totals.next_entry += 1;
+            }
+        }
+    });
+
+    JOURNAL_TOTALS.with(|t| t.borrow_mut().set(totals));
+}
+
+/// Postings that bring a new user's starting balance into the book
+pub(crate) fn deposit(user: Principal, balance: &DemoUserBalance, reason: JournalReason) -> Posting {
+    Asset::ALL.iter().fold(Posting::new(reason, None), |posting, asset| {
+        let (free, locked) = balance.slots(asset);
+        posting
+            .leg(JournalAccount::External, asset, -(free as i128 + locked as i128))
+            .leg(JournalAccount::Free(user), asset, free)
+            .leg(JournalAccount::Locked(user), asset, locked)
+    })
+}
+
+/// Journal the balances of a canister upgraded from before the journal
+/// existed, once, as opening balances
+pub(crate) fn open_existing_balances() {
+    if JOURNAL.with(|j| !j.borrow().is_empty()) {
+        return;
+    }
+    let postings: Vec<Posting> = DEMO_BALANCES.with(|b| {
+        b.borrow()
+            .iter()
+            .map(|entry| deposit(*entry.key(), &entry.value(), JournalReason::OpeningBalance))
+            .collect()
+    });
+    if !postings.is_empty() {
+        ic_cdk::println!("Journaling opening balances of {} users", postings.len());
+        record(postings);
+    }
+}
+
+// ============================================================================
+// ENDPOINTS
+// ============================================================================
+
+/// Check, per asset, that the free, locked and pending-withdrawal balances of
+/// all users plus the clearing dust and the treasury add up to what was
+/// deposited minus what was withdrawn
+#[ic_cdk_macros::query]
+pub fn reconcile() -> Reconciliation {
+    let totals = JOURNAL_TOTALS.with(|t| t.borrow().get().clone());
+
+    let mut held = [(0u128, 0u128); Asset::ALL.len()];
+    DEMO_BALANCES.with(|b| {
+        for entry in b.borrow().iter() {
+            let balance = entry.value();
+            for (i, asset) in Asset::ALL.iter().enumerate() {
+                let (free, locked) = balance.slots(asset);
+                held[i].0 += free as u128;
+                held[i].1 += locked as u128;
+            }
+        }
+    });
+
+    let assets: Vec<AssetReconciliation> = Asset::ALL
+        .iter()
+        .zip(held)
+        .map(|(asset, (free, locked))| {
+            let sums = totals.assets.get(asset).cloned().unwrap_or_default();
+            let pending_withdrawals = crate::accounts::pending_withdrawals(asset);
+            let net = sums.deposits as i128 - sums.withdrawals as i128;
+            let held = free as i128 + locked as i128 + pending_withdrawals as i128;
+            AssetReconciliation {
+                asset: asset.clone(),
+                deposits: sums.deposits,
+                withdrawals: sums.withdrawals,
+                free,
+                locked,
+                pending_withdrawals,
+                clearing: sums.clearing,
+                treasury: sums.treasury,
+                balanced: held + sums.clearing + sums.treasury == net,
+            }
+        })
+        .collect();
+
+    Reconciliation {
+        balanced: assets.iter().all(|a| a.balanced),
+        assets,
+        entries: totals.next_entry,
+    }
+}
+
+/// Page through the journal, oldest first, starting at entry `cursor`
+#[ic_cdk_macros::query]
+pub fn get_journal(cursor: Option<u64>, limit: u32) -> Result<JournalPage, VeilError> {
+    crate::access::require_admin()?;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+
+    JOURNAL.with(|j| {
+        let journal = j.borrow();
+        let mut entries: Vec<JournalEntry> = journal
+            .range(cursor.unwrap_or(0)..)
+            .take(limit + 1)
+            .map(|entry| entry.value())
+            .collect();
+
+        let next_cursor = if entries.len() > limit {
+            entries.pop().map(|e| e.id)
+        } else {
+            None
+        };
+        Ok(JournalPage { entries, next_cursor })
+    })
+}
@@ -2,40 +2,70 @@ use candid::Principal;
 use ic_cdk::api::{time, caller};
 use ic_cdk_macros::*;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableBTreeSet, StableCell};
 use std::cell::RefCell;
 use std::collections::HashMap;
-use crate::types::{ DemoUserBalance, ResultOrder};
+use std::ops::{Bound, RangeInclusive};
+use crate::types::DemoUserBalance;
 use serde::{Deserialize, Serialize};
 
-// ==============================
-// Common Result Types
-// ==============================
-
-#[derive(candid::CandidType, serde::Serialize, serde::Deserialize, Debug)]
-pub enum ResultBytes {
-    Ok(Vec<u8>),
-    Err(String),
-}
-
 // Import our modules
 mod types;
-mod auction;
+mod access;
+mod accounts;
 mod encryption;
+mod escrow;
+mod fees;
+mod journal;
+mod limits;
 mod queries;
+mod rules;
+mod rounds;
 mod timers;
 
 use types::*;
+use veil_core::{find_clearing_price_and_match, ClearingParams};
 // Import the types needed for Candid export
 use queries::{OrderBookSummary, PlatformStats};
 
 // Memory setup
-type Memory = VirtualMemory<DefaultMemoryImpl>;
+pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
 
 type OrderId = u64;
 
+const STATE_MEMORY_ID: MemoryId = MemoryId::new(0);
 const ORDERS_MEMORY_ID: MemoryId = MemoryId::new(1);
 const RESULTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const USER_STATS_MEMORY_ID: MemoryId = MemoryId::new(3);
+const DEMO_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub(crate) const ESCROW_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub(crate) const COMPLETED_PAYOUTS_MEMORY_ID: MemoryId = MemoryId::new(6);
+const VETKD_ID_MEMORY_ID: MemoryId = MemoryId::new(7);
+const ORDERS_BY_ROUND_MEMORY_ID: MemoryId = MemoryId::new(8);
+const ORDERS_BY_OWNER_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub(crate) const ROLES_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub(crate) const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub(crate) const SUBMISSION_LIMITS_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub(crate) const TRADING_RULES_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub(crate) const PRICE_RULES_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub(crate) const ROUND_TIMELINES_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub(crate) const JOURNAL_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub(crate) const JOURNAL_TOTALS_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub(crate) const ORDER_SETTLEMENTS_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub(crate) const TRANSFERS_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub(crate) const TRANSFERS_BY_OWNER_MEMORY_ID: MemoryId = MemoryId::new(20);
+pub(crate) const FEE_RATES_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub(crate) const FEE_DISCOUNTS_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub(crate) const TREASURY_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub(crate) const FAILED_PAYOUTS_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub(crate) const ESCROW_NONCES_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub(crate) const ORDER_REJECTIONS_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub(crate) const CUSTODY_MEMORY_ID: MemoryId = MemoryId::new(27);
+const LEADERBOARD_MEMORY_ID: MemoryId = MemoryId::new(28);
+
+// Index keys: ((market, round), order) and (owner, (market, round), order)
+type RoundIndex = StableBTreeSet<((MarketId, RoundId), OrderId), Memory>;
+type OwnerIndex = StableBTreeSet<(Principal, (MarketId, RoundId), OrderId), Memory>;
 const INITIAL_DEMO_BALANCE: u64 = 1_000_000_000; // 1.0 demo ckBTC in satoshis
 
 const DEMO_USERS: [&str; 4] = [
@@ -52,33 +82,70 @@ thread_local! {
     // test-storage
     pub static STORAGE: RefCell<Vec<(u64, Vec<u8>, String)>> = RefCell::new(vec![]);
 
-    static VETKD_ID: RefCell<Option<Principal>> = RefCell::new(None);
+    static VETKD_ID: RefCell<StableCell<Option<Principal>, Memory>> = RefCell::new(
+        StableCell::init(memory(VETKD_ID_MEMORY_ID), None)
+    );
 
-    // balance setup for demo purposes
-    static DEMO_BALANCES: std::cell::RefCell<HashMap<Principal, DemoUserBalance>> =
-        std::cell::RefCell::new(HashMap::new());
+    // Orders with an amendment awaiting its escrow lock
+    static ORDERS_IN_FLIGHT: RefCell<std::collections::BTreeSet<OrderId>> =
+        const { RefCell::new(std::collections::BTreeSet::new()) };
 
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-    static STATE: RefCell<State> = RefCell::new(State::default());
+    // balance setup for demo purposes
+    pub static DEMO_BALANCES: RefCell<StableBTreeMap<Principal, DemoUserBalance, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(DEMO_BALANCES_MEMORY_ID))
+    );
+
+    // Markets, round lifecycle and id counters; use `with_state` / `with_state_mut`
+    static STATE: RefCell<StableCell<State, Memory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY_ID), State::default())
+    );
 
     // Orders storage - all orders across all rounds
     pub static ORDERS: RefCell<StableBTreeMap<OrderId, Order, Memory>> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(ORDERS_MEMORY_ID))
+            memory(ORDERS_MEMORY_ID)
         )
     );
 
-    // Results storage - clearing results per round
-    pub static RESULTS: RefCell<StableBTreeMap<RoundId, ClearingResult, Memory>> = RefCell::new(
+    // Index: orders of a (market, round)
+    static ORDERS_BY_ROUND: RefCell<RoundIndex> = RefCell::new(
+        StableBTreeSet::init(memory(ORDERS_BY_ROUND_MEMORY_ID))
+    );
+
+    // Index: orders of an owner, by (market, round)
+    static ORDERS_BY_OWNER: RefCell<OwnerIndex> = RefCell::new(
+        StableBTreeSet::init(memory(ORDERS_BY_OWNER_MEMORY_ID))
+    );
+
+    // Results storage - clearing results per (market, round)
+    pub static RESULTS: RefCell<StableBTreeMap<(MarketId, RoundId), ClearingResult, Memory>> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(RESULTS_MEMORY_ID))
+            memory(RESULTS_MEMORY_ID)
         )
     );
 
-    // User stats - in-memory cache
-    static USER_STATS: RefCell<HashMap<Principal, UserStats>> = RefCell::new(HashMap::new());
+    // User stats
+    static USER_STATS: RefCell<StableBTreeMap<Principal, UserStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(USER_STATS_MEMORY_ID))
+    );
+
+    // Every user with stats, highest total surplus first: keys are
+    // (u128::MAX - total_surplus, user), see `LeaderboardCursor::key`
+    pub static LEADERBOARD: RefCell<StableBTreeSet<(u128, Principal), Memory>> = RefCell::new(
+        StableBTreeSet::init(memory(LEADERBOARD_MEMORY_ID))
+    );
+
+    // Why each order rejected at reveal was rejected
+    static ORDER_REJECTIONS: RefCell<StableBTreeMap<OrderId, VeilError, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ORDER_REJECTIONS_MEMORY_ID))
+    );
+}
+
+pub(crate) fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
 }
 
 
@@ -90,15 +157,9 @@ thread_local! {
 fn init() {
     ic_cdk::println!("Initializing Mempool Chess canister");
     
-    STATE.with(|s| {
-        let mut state = s.borrow_mut();
-        state.round_id = 0;
-        state.round_state = RoundState::Pending;
-        state.round_start_time = 0;
-        state.round_duration_ns = 60_000_000_000; // 60 seconds
-        state.next_order_id = 0;
-        state.clearing_price_history = Vec::new();
-    });
+    // Seeds the default BTC/USD, ETH/USD and ETH/BTC markets
+    with_state_mut(|state| *state = State::default());
+    rules::seed_trading_rules();
     
     ic_cdk::println!("Canister initialized successfully");
 }
@@ -106,7 +167,12 @@ fn init() {
 #[post_upgrade]
 fn post_upgrade() {
     ic_cdk::println!("Post-upgrade: Restoring state");
-    // State is automatically restored from stable memory
+    discard_single_market_history();
+    // All canister state lives in stable structures and survives as is
+    rebuild_order_indexes();
+    rebuild_leaderboard();
+    journal::open_existing_balances();
+    rules::seed_trading_rules();
     // Start the timer for automatic round progression
     timers::start_round_timer();
 }
@@ -115,35 +181,37 @@ fn post_upgrade() {
 // ORDER SUBMISSION
 // ============================================================================
 
+/// Lock `collateral` and enter an encrypted order in the market's current
+/// round. The side and collateral go in the clear, as escrow needs them
+/// before decryption: the collateral caps the order's notional (Buy) or size
+/// (Sell), and locking more than needed hides the exact figure.
 #[update]
 async fn submit_order(
+    market_id: MarketId,
     order_type: OrderType,
-    asset: Asset,
-    amount: u64,
-    price_limit: u64,
+    collateral: u64,
     encrypted_payload: Vec<u8>,
     commitment_hash: String,
-) -> ResultOrder {
-    let caller = ic_cdk::caller();
+) -> Result<OrderId, VeilError> {
+    let caller = access::require_authenticated()?;
 
     // 1) Basic round checks
-    let state = STATE.with(|s| s.borrow().clone());
-    if state.round_state != RoundState::Active {
-        return ResultOrder::Err("Round is not active".to_string());
+    let market = market_state(market_id)?;
+    if market.round_state != RoundState::Active {
+        return Err(VeilError::RoundNotActive { market_id });
     }
 
-    if amount == 0 {
-        return ResultOrder::Err("Amount must be > 0".to_string());
+    if collateral == 0 {
+        return Err(VeilError::ZeroCollateral);
     }
-
-    // 2) Escrow: lock demo funds for this user
-    if let Err(e) = lock_demo_funds(caller, &order_type, amount, price_limit) {
-        return ResultOrder::Err(e);
+    if encrypted_payload.is_empty() {
+        return Err(VeilError::EmptyPayload);
     }
+    rules::check_collateral(&market, &order_type, collateral)?;
+    limits::check_submission(caller, &market, &order_type, collateral, None)?;
 
-    // 3) Generate new OrderId
-    let order_id = STATE.with(|s| {
-        let mut st = s.borrow_mut();
+    // 2) Generate new OrderId
+    let order_id = with_state_mut(|st| {
         let id = st.next_order_id;
         st.next_order_id += 1;
         id
@@ -153,403 +221,609 @@ async fn submit_order(
 
     let order = Order {
         id: order_id,
-        round_id: state.round_id,
+        market_id,
+        round_id: market.round_id,
         owner: caller,
         order_type: order_type.clone(),
-        asset,
-        amount,
-        price_limit,
+        collateral,
+        amount: 0,
+        price_limit: 0,
         created_at: now,
         encrypted_payload,
         commitment_hash,
+        status: OrderStatus::Open,
+        revision: 0,
+        updated_at: now,
+        escrow_nonce: escrow::first_escrow_nonce(order_id),
     };
 
-    // 4) Store order in ORDERS or ORDERS_BY_ROUND (depending on your structure)
-    ORDERS.with(|orders| {
-        orders.borrow_mut().insert(order_id, order);
+    // 3) Escrow: lock funds for this user
+    escrow::lock_funds(&order, &market.market).await?;
+
+    // 4) The ledger lock is asynchronous: if the round closed, or the
+    //    caller's other submissions used up the quota meanwhile, hand the
+    //    funds back instead of storing the order
+    let admitted = match market_state(market_id) {
+        Ok(m) if m.round_state == RoundState::Active && m.round_id == order.round_id => {
+            limits::check_submission(caller, &m, &order_type, collateral, None)
+        }
+        _ => Err(VeilError::RoundNotActive { market_id }),
+    };
+    if let Err(e) = admitted {
+        if let Err(release_err) = escrow::release_funds(&order, &market.market).await {
+            ic_cdk::println!("Failed to release escrow for order {}: {}", order_id, release_err);
+        }
+        return Err(e);
+    }
+
+    // 5) Store order and index it by round and owner
+    store_order(order);
+
+    Ok(order_id)
+}
+
+// ============================================================================
+// ORDER CANCELLATION & AMENDMENT
+// ============================================================================
+
+/// Withdraw an open order and release its escrow (owner only, Active round).
+/// If the ledger refund fails the order stays cancelled and the refund waits
+/// in `get_failed_payouts` for an operator's `retry_settlement_payout`.
+#[update]
+async fn cancel_order(order_id: OrderId) -> Result<(), VeilError> {
+    let caller = access::require_authenticated()?;
+    let (order, market) = load_amendable_order(order_id, caller)?;
+
+    // Mark cancelled before releasing, so a clearing that starts while the
+    // release is in flight no longer sees the order
+    let now = time();
+    store_order(Order {
+        status: OrderStatus::Cancelled,
+        updated_at: now,
+        ..order.clone()
     });
 
-    ResultOrder::Ok(order_id)
+    with_user_stats_mut(caller, |stats| stats.cancelled_orders += 1);
+
+    escrow::release_funds(&order, &market.market).await
+}
+
+/// Replace the collateral and ciphertext of an open order (owner only,
+/// Active round). The new lock is taken before the old one is released, so a
+/// failed amendment leaves the original order untouched.
+#[update]
+async fn amend_order(
+    order_id: OrderId,
+    collateral: u64,
+    encrypted_payload: Vec<u8>,
+    commitment_hash: String,
+) -> Result<(), VeilError> {
+    let caller = access::require_authenticated()?;
+    let (order, market) = load_amendable_order(order_id, caller)?;
+
+    if collateral == 0 {
+        return Err(VeilError::ZeroCollateral);
+    }
+    if encrypted_payload.is_empty() {
+        return Err(VeilError::EmptyPayload);
+    }
+    rules::check_collateral(&market, &order.order_type, collateral)?;
+    limits::check_submission(caller, &market, &order.order_type, collateral, Some(&order))?;
+
+    if !ORDERS_IN_FLIGHT.with(|f| f.borrow_mut().insert(order_id)) {
+        return Err(VeilError::OrderBusy(order_id));
+    }
+
+    let amended = Order {
+        collateral,
+        encrypted_payload,
+        commitment_hash,
+        revision: order.revision + 1,
+        updated_at: time(),
+        escrow_nonce: escrow::next_escrow_nonce(&order),
+        ..order.clone()
+    };
+    let result = match escrow::lock_funds(&amended, &market.market).await {
+        Err(e) => Err(e),
+        Ok(()) => {
+            // Round may have closed, the order been cancelled, or the quota
+            // used up, while locking
+            let still_amendable = load_amendable_order(order_id, caller).and_then(|(_, m)| {
+                limits::check_submission(caller, &m, &order.order_type, collateral, Some(&order))
+            });
+            match still_amendable {
+                Ok(()) => {
+                    store_order(amended);
+                    escrow::release_funds(&order, &market.market).await
+                }
+                Err(e) => {
+                    if let Err(release_err) = escrow::release_funds(&amended, &market.market).await {
+                        ic_cdk::println!("Failed to release amended escrow for order {}: {}", order_id, release_err);
+                    }
+                    Err(e)
+                }
+            }
+        }
+    };
+
+    ORDERS_IN_FLIGHT.with(|f| f.borrow_mut().remove(&order_id));
+    result
+}
+
+/// An order the caller may still cancel or amend, with its market
+fn load_amendable_order(order_id: OrderId, caller: Principal) -> Result<(Order, MarketState), VeilError> {
+    let order = ORDERS
+        .with(|orders| orders.borrow().get(&order_id))
+        .ok_or(VeilError::UnknownOrder(order_id))?;
+
+    if order.owner != caller {
+        return Err(VeilError::NotOrderOwner(order_id));
+    }
+    if order.status != OrderStatus::Open {
+        return Err(VeilError::OrderNotOpen { order_id, status: order.status });
+    }
+
+    let market = market_state(order.market_id)?;
+    if market.round_state != RoundState::Active || market.round_id != order.round_id {
+        return Err(VeilError::RoundNotActive { market_id: order.market_id });
+    }
+
+    Ok((order, market))
 }
 
 // ============================================================================
 // ROUND MANAGEMENT (Admin Functions)
 // ============================================================================
+/// Open the next round of a market for orders; returns its id
 #[update]
-fn admin_start_round() -> String {
-    STATE.with(|s| {
-        let mut state = s.borrow_mut();
-        
-        // Only start if pending
-        if state.round_state != RoundState::Pending {
-            return format!(
-                "Cannot start round: currently in {:?} state",
-                state.round_state
-            );
+fn admin_start_round(market_id: MarketId) -> Result<RoundId, VeilError> {
+    access::require_operator()?;
+    access::audit("admin_start_round", format!("market {}", market_id));
+    let round_id = rounds::transition(market_id, RoundState::Active)?;
+
+    let state = market_state(market_id)?;
+    ic_cdk::println!(
+        "{} round {} started at {}. Duration: {}s",
+        state.market.symbol,
+        round_id,
+        state.round_start_time,
+        state.round_duration_ns / 1_000_000_000
+    );
+    Ok(round_id)
+}
+
+#[update]
+fn admin_create_market(base: Asset, quote: Asset) -> Result<MarketId, VeilError> {
+    access::require_admin()?;
+    access::audit("admin_create_market", format!("{:?}/{:?}", base, quote));
+    if base == quote {
+        return Err(VeilError::InvalidArgument("Base and quote asset must differ".to_string()));
+    }
+
+    let id = with_state_mut(|state| {
+        if state
+            .markets
+            .values()
+            .any(|m| m.market.base == base && m.market.quote == quote)
+        {
+            return Err(VeilError::MarketExists { base, quote });
         }
-        
-        state.round_id += 1;
-        state.round_state = RoundState::Active;
-        state.round_start_time = time();
-        
-        ic_cdk::println!(
-            "Round {} started at {}. Duration: {}s",
-            state.round_id,
-            state.round_start_time,
-            state.round_duration_ns / 1_000_000_000
-        );
-        
-        format!(
-            "Round {} started. Accepting orders for 60 seconds.",
-            state.round_id
-        )
-    })
+
+        let id = state.add_market(base, quote);
+        ic_cdk::println!("Created market {} ({})", id, state.markets[&id].market.symbol);
+        Ok(id)
+    })?;
+    rules::seed_trading_rules();
+    Ok(id)
 }
 
 #[update]
-async fn admin_run_clearing() -> String {
-    let current_round = STATE.with(|s| s.borrow().round_id);
-    
-    ic_cdk::println!("Admin triggered clearing for round {}", current_round);
+async fn admin_run_clearing(market_id: MarketId) -> Result<ClearingResult, VeilError> {
+    access::require_operator()?;
+    access::audit("admin_run_clearing", format!("market {}", market_id));
+    run_clearing(market_id).await
+}
+
+/// Reveal, clear and settle a market's Active round (also run by the
+/// timer). A round that cannot clear ends Failed, refunding its orders.
+pub(crate) async fn run_clearing(market_id: MarketId) -> Result<ClearingResult, VeilError> {
+    // Change state to Revealing; only an Active round can start clearing
+    let current_round = rounds::transition(market_id, RoundState::Revealing)?;
     
-    // Change state to Revealing
-    STATE.with(|s| {
-        s.borrow_mut().round_state = RoundState::Revealing;
-    });
+    ic_cdk::println!("Clearing market {} round {}", market_id, current_round);
     
     // Get all orders for current round
-    let round_orders: Vec<Order> = ORDERS.with(|orders| {
-        orders
-            .borrow()
-            .iter()
-            .filter_map(|entry| {
-                let order = entry.value();  // ✅ CORRECT
-                if order.round_id == current_round {
-                    Some(order.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    });
+    let round_orders: Vec<Order> = round_orders(market_id, current_round)
+        .into_iter()
+        .filter(|order| order.status == OrderStatus::Open)
+        .collect();
     
     if round_orders.is_empty() {
-        STATE.with(|s| {
-            s.borrow_mut().round_state = RoundState::Pending;
-        });
-        return format!("No orders to clear in round {}", current_round);
+        rounds::unwind(market_id, RoundState::Failed).await?;
+        return Err(VeilError::NothingToClear { market_id, round_id: current_round });
     }
     
     ic_cdk::println!("Decrypting {} orders...", round_orders.len());
     
-    // Decrypt orders (in production, this would use vetKeys)
-    let decrypted_orders = match encryption::decrypt_order_batch(round_orders).await {
-        Ok(orders) => orders,
+    // Decrypt orders with the round's vetKD key
+    let batch = match encryption::decrypt_order_batch(round_orders).await {
+        Ok(batch) => batch,
         Err(e) => {
-            STATE.with(|s| {
-                s.borrow_mut().round_state = RoundState::Pending;
-            });
-            return format!("Decryption failed: {}", e);
+            rounds::unwind(market_id, RoundState::Failed).await?;
+            return Err(e);
         }
     };
+
+    let market = market_state(market_id)?;
+    let decrypted_orders = record_revealed_orders(batch, &market).await;
     
-    ic_cdk::println!("Orders decrypted. Running auction...");
+    ic_cdk::println!("{} orders revealed. Running auction...", decrypted_orders.len());
     
     // Change state to Clearing
-    STATE.with(|s| {
-        s.borrow_mut().round_state = RoundState::Clearing;
-    });
+    rounds::transition(market_id, RoundState::Clearing)?;
     
     // Run auction
-    match auction::find_clearing_price_and_match(decrypted_orders, current_round) {
-        Ok(result) => {
+    let params = ClearingParams {
+        market_id,
+        round_id: current_round,
+        rule: rules::get_price_rule(market_id),
+        tick_size: rules::get_trading_rules(market_id).tick_size,
+        base_decimals: market.market.base.decimals(),
+        reference_price: market.clearing_price_history.last().copied(),
+        timestamp: time(),
+    };
+    match find_clearing_price_and_match(decrypted_orders, &params, |line| ic_cdk::println!("{}", line)) {
+        Ok(mut result) => {
             ic_cdk::println!(
-                "Clearing successful! Price: ${}, Volume: {}, Surplus: ${}",
-                result.clearing_price as f64 / 100.0,
+                "Clearing successful! Price: {}, Volume: {}, Notional: {}, Surplus: {}",
+                result.clearing_price,
                 result.total_volume,
-                result.total_surplus as f64 / 100.0
+                result.total_notional,
+                result.total_surplus
             );
-            
+            rounds::transition(market_id, RoundState::Executing)?;
+
+            // ESCROW: release locks and pay out fills, or fail the round
+            // with nothing settled
+            if let Err(e) = apply_settlement_for_round(&mut result).await {
+                ic_cdk::println!("Settlement aborted: {}", e);
+                rounds::unwind(market_id, RoundState::Failed).await?;
+                return Err(e);
+            }
+
             // Store result
             RESULTS.with(|results| {
-                results.borrow_mut().insert(current_round, result.clone());
+                results.borrow_mut().insert((market_id, current_round), result.clone());
             });
 
             // Update price history
-            STATE.with(|s| {
-                let mut state = s.borrow_mut();
-                state.clearing_price_history.push(result.clearing_price);
-                state.round_state = RoundState::Executing;
-            });
+            with_market_mut(market_id, |state| state.record_clearing_price(result.clearing_price))?;
             
             // Update user stats
             update_user_stats(&result);
-
-            // DEMO ESCROW: apply clearing to demo balances
-            if let Err(e) = apply_settlement_for_round(&result) {
-                ic_cdk::println!("Settlement error: {}", e);
-            }
             
             // In production, this would trigger cross-chain settlement
             // For now, we'll just mark as completed
-            STATE.with(|s| {
-                s.borrow_mut().round_state = RoundState::Completed;
-            });
-            
-            format!(
-                "Round {} cleared! Price: ${:.2}, Volume: {}, Surplus: ${:.2}",
-                current_round,
-                result.clearing_price as f64 / 100.0,
-                result.total_volume,
-                result.total_surplus as f64 / 100.0
-            )
+            rounds::transition(market_id, RoundState::Completed)?;
+
+            Ok(result)
         }
         Err(e) => {
             ic_cdk::println!("Clearing failed: {}", e);
-            STATE.with(|s| {
-                s.borrow_mut().round_state = RoundState::Pending;
-            });
-            format!("Clearing failed: {}", e)
+            rounds::unwind(market_id, RoundState::Failed).await?;
+            Err(e.into())
+        }
+    }
+}
+
+/// Store the decrypted size and limit of revealed orders. Orders that could
+/// not be opened, break the market's trading rules, or whose collateral does
+/// not cover what they reveal, are marked Rejected and their escrow released.
+async fn record_revealed_orders(batch: encryption::RevealedBatch, market_state: &MarketState) -> Vec<Order> {
+    let market = &market_state.market;
+    let mut revealed = Vec::new();
+    let mut rejected = batch.rejected;
+
+    for order in batch.revealed {
+        if let Err(e) = rules::check_order(market_state, order.amount, order.price_limit) {
+            rejected.push((order, e));
+            continue;
+        }
+        match escrow::required_lock(market, &order.order_type, order.amount, order.price_limit) {
+            Ok((_, required)) if required <= order.collateral => revealed.push(order),
+            Ok((_, required)) => {
+                let error = VeilError::InsufficientCollateral { collateral: order.collateral, required };
+                rejected.push((order, error));
+            }
+            Err(e) => rejected.push((order, e.into())),
+        }
+    }
+
+    let now = time();
+    for order in &revealed {
+        store_order(order.clone());
+    }
+    for (order, reason) in &rejected {
+        store_order(Order {
+            status: OrderStatus::Rejected,
+            updated_at: now,
+            ..order.clone()
+        });
+        ORDER_REJECTIONS.with(|r| r.borrow_mut().insert(order.id, reason.clone()));
+    }
+
+    for (order, reason) in rejected {
+        ic_cdk::println!("Order {} rejected at reveal: {}", order.id, reason);
+        if let Err(e) = escrow::release_funds(&order, market).await {
+            ic_cdk::println!("Failed to release escrow for order {}: {}", order.id, e);
         }
     }
+
+    revealed
+}
+
+/// Why an order was rejected at reveal, if it was
+#[query]
+fn get_order_rejection(order_id: OrderId) -> Option<VeilError> {
+    ORDER_REJECTIONS.with(|r| r.borrow().get(&order_id))
 }
 
+/// Park an ended round in Pending (use `admin_abort_round` to stop a live one)
 #[update]
-fn admin_reset_round() -> String {
-    STATE.with(|s| {
-        let mut state = s.borrow_mut();
-        state.round_state = RoundState::Pending;
-        format!("Round {} reset to Pending state", state.round_id)
-    })
+fn admin_reset_round(market_id: MarketId) -> Result<(), VeilError> {
+    access::require_operator()?;
+    access::audit("admin_reset_round", format!("market {}", market_id));
+    rounds::transition(market_id, RoundState::Pending).map(|_| ())
 }
 
 // ============================================================================
-// HELPER FUNCTIONS
+// ORDER STORAGE & INDEXES
 // ============================================================================
 
-fn update_user_stats(result: &ClearingResult) {
-    USER_STATS.with(|stats| {
-        let mut stats_map = stats.borrow_mut();
-        
-        // Get all orders for this round to know who participated
-        let round_orders: HashMap<OrderId, Principal> = ORDERS.with(|orders| {
-            orders
-                .borrow()
-                .iter()
-                .filter_map(|entry| {
-                    let order = entry.value();  // ✅ CORRECT
-                    if order.round_id == result.round_id {
-                        Some((*entry.key(), order.owner))
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        });
-        
-        // Update stats for each matched order
-        for order_match in &result.matches {
-            if let Some(user) = round_orders.get(&order_match.order_id) {
-                let user_stat = stats_map.entry(*user).or_insert(UserStats {
-                    user: *user,
-                    total_orders: 0,
-                    filled_orders: 0,
-                    total_surplus: 0,
-                    rounds_participated: 0,
-                });
-                
-                user_stat.total_orders += 1;
-                
-                if order_match.filled {
-                    user_stat.filled_orders += 1;
-                    user_stat.total_surplus += order_match.surplus;
-                }
-            }
-        }
-        
-        // Mark round participation for all unique users
-        let unique_users: std::collections::HashSet<_> = round_orders.values().collect();
-        for user in unique_users {
-            if let Some(user_stat) = stats_map.get_mut(user) {
-                user_stat.rounds_participated += 1;
-            }
+/// Write an order and keep the round / owner indexes in step. The indexed
+/// fields never change after submission, so re-storing an order is safe.
+pub(crate) fn store_order(order: Order) {
+    let round_key = (order.market_id, order.round_id);
+    ORDERS_BY_ROUND.with(|idx| idx.borrow_mut().insert((round_key, order.id)));
+    ORDERS_BY_OWNER.with(|idx| idx.borrow_mut().insert((order.owner, round_key, order.id)));
+    ORDERS.with(|orders| orders.borrow_mut().insert(order.id, order));
+}
+
+/// Ids of a market's round after `after`, ascending, at most `limit`
+pub(crate) fn round_order_ids(
+    market_id: MarketId,
+    round_id: RoundId,
+    after: Option<OrderId>,
+    limit: usize,
+) -> Vec<OrderId> {
+    let round_key = (market_id, round_id);
+    let start = match after {
+        Some(id) => Bound::Excluded((round_key, id)),
+        None => Bound::Included((round_key, 0)),
+    };
+    ORDERS_BY_ROUND.with(|idx| {
+        idx.borrow()
+            .range((start, Bound::Included((round_key, OrderId::MAX))))
+            .take(limit)
+            .map(|(_, id)| id)
+            .collect()
+    })
+}
+
+/// All orders of a market's round, ascending by id
+pub(crate) fn round_orders(market_id: MarketId, round_id: RoundId) -> Vec<Order> {
+    load_orders(round_order_ids(market_id, round_id, None, usize::MAX))
+}
+
+/// An owner's orders within `rounds` (inclusive), ascending by
+/// (market, round, id), starting after `after`, at most `limit`
+pub(crate) fn owner_orders(
+    owner: Principal,
+    rounds: RangeInclusive<(MarketId, RoundId)>,
+    after: Option<OrderCursor>,
+    limit: usize,
+) -> Vec<Order> {
+    let (first, last) = rounds.into_inner();
+    let start = match after {
+        Some(c) if (c.market_id, c.round_id) >= first => {
+            Bound::Excluded((owner, (c.market_id, c.round_id), c.order_id))
         }
+        _ => Bound::Included((owner, first, 0)),
+    };
+    let ids: Vec<OrderId> = ORDERS_BY_OWNER.with(|idx| {
+        idx.borrow()
+            .range((start, Bound::Included((owner, last, OrderId::MAX))))
+            .take(limit)
+            .map(|(_, _, id)| id)
+            .collect()
     });
+    load_orders(ids)
 }
 
-fn get_or_create_demo_balance(user: Principal) -> DemoUserBalance {
-    DEMO_BALANCES.with(|b| {
-        let mut map = b.borrow_mut();
-        map.entry(user)
-            .or_insert(DemoUserBalance {
-                btc_free: 1_000_000_000,
-                btc_locked: 0,
-                usd_free: 10_000_000_000,
-                usd_locked: 0,
-            })
-            .clone()
+pub(crate) fn load_orders(ids: Vec<OrderId>) -> Vec<Order> {
+    ORDERS.with(|orders| {
+        let orders = orders.borrow();
+        ids.into_iter().filter_map(|id| orders.get(&id)).collect()
     })
 }
 
-fn set_demo_balance(user: Principal, balance: DemoUserBalance) {
-    DEMO_BALANCES.with(|b| {
-        b.borrow_mut().insert(user, balance);
-    });
+/// Index orders stored before the indexes existed (runs once, on upgrade)
+fn rebuild_order_indexes() {
+    let indexed = ORDERS_BY_ROUND.with(|idx| idx.borrow().len());
+    let stored = ORDERS.with(|orders| orders.borrow().len());
+    if indexed == stored {
+        return;
+    }
+
+    ic_cdk::println!("Indexing {} stored orders", stored);
+    let orders: Vec<Order> = ORDERS.with(|orders| orders.borrow().values().collect());
+    for order in orders {
+        store_order(order);
+    }
 }
 
-/// Lock funds when the user submits an order
-/// For demo: we lock *amount* units, regardless of price
-fn lock_demo_funds(user: Principal, order_type: &OrderType, amount: u64, price_limit: u64,) -> Result<(), String> {
-    with_demo_balance_mut(&user, |bal| {
-        match order_type {
-            OrderType::Buy => {
-                // For BUY: lock USD = amount * price_limit
-                let required = amount
-                    .checked_mul(price_limit)
-                    .ok_or_else(|| "Overflow in required funds".to_string())?;
-
-                if bal.usd_free < required {
-                    return Err(format!(
-                        "Insufficient USD balance: required {}, available {}",
-                        required, bal.usd_free
-                    ));
-                }
+/// The single-market release kept its state on the heap, so STATE's memory
+/// is still unallocated when upgrading from it. Its ORDERS and RESULTS use a
+/// layout and price units this version cannot read: they are dropped, and
+/// the canister starts out as a reinstall would (runs once, on upgrade).
+fn discard_single_market_history() {
+    if ic_stable_structures::Memory::size(&memory(STATE_MEMORY_ID)) > 0 {
+        return;
+    }
 
-                bal.usd_free -= required;
-                bal.usd_locked += required;
-            }
-            OrderType::Sell => {
-                // For SELL: lock BTC = amount
-                if bal.btc_free < amount {
-                    return Err(format!(
-                        "Insufficient BTC balance: required {}, available {}",
-                        amount, bal.btc_free
-                    ));
-                }
+    let orders = ORDERS.with(|orders| orders.borrow().len());
+    let results = RESULTS.with(|results| results.borrow().len());
+    ic_cdk::println!("Discarding {} orders and {} results of the single-market release", orders, results);
+    ORDERS.with(|o| *o.borrow_mut() = StableBTreeMap::new(memory(ORDERS_MEMORY_ID)));
+    RESULTS.with(|r| *r.borrow_mut() = StableBTreeMap::new(memory(RESULTS_MEMORY_ID)));
+    access::audit(
+        "post_upgrade",
+        format!("discarded {} orders and {} results of the single-market release", orders, results),
+    );
+}
 
-                bal.btc_free -= amount;
-                bal.btc_locked += amount;
-            }
+/// Rank users stored before the leaderboard index existed (runs once, on
+/// upgrade)
+fn rebuild_leaderboard() {
+    let ranked = LEADERBOARD.with(|idx| idx.borrow().len());
+    let stored = USER_STATS.with(|stats| stats.borrow().len());
+    if ranked == stored {
+        return;
+    }
+
+    ic_cdk::println!("Ranking {} users", stored);
+    LEADERBOARD.with(|idx| {
+        let mut idx = idx.borrow_mut();
+        for stats in USER_STATS.with(|stats| stats.borrow().values().collect::<Vec<_>>()) {
+            idx.insert(leaderboard_key(&stats));
         }
+    });
+}
 
-        Ok(())
+fn leaderboard_key(stats: &UserStats) -> (u128, Principal) {
+    LeaderboardCursor { surplus: stats.total_surplus, user: stats.user }.key()
+}
+
+// ============================================================================
+// HELPER FUNCTIONS
+// ============================================================================
+
+/// Read the canister state
+pub(crate) fn with_state<R>(f: impl FnOnce(&State) -> R) -> R {
+    STATE.with(|s| f(s.borrow().get()))
+}
+
+/// Modify the canister state and write it back to stable memory
+pub(crate) fn with_state_mut<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    STATE.with(|s| {
+        let mut cell = s.borrow_mut();
+        let mut state = cell.get().clone();
+        let result = f(&mut state);
+        cell.set(state);
+        result
     })
 }
 
-// Helper to get mutable balance for a user
-fn with_demo_balance_mut<R>(user: &Principal, f: impl FnOnce(&mut DemoUserBalance) -> R) -> R {
-    DEMO_BALANCES.with(|balances| {
-        let mut map = balances.borrow_mut();
-        let bal = map.entry(*user).or_insert_with(|| DemoUserBalance {
-            btc_free: 1_000_000_000,
-            btc_locked: 0,
-            usd_free: 10_000_000_000,
-            usd_locked: 0,
+fn with_user_stats_mut<R>(user: Principal, f: impl FnOnce(&mut UserStats) -> R) -> R {
+    USER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        let mut user_stats = stats.get(&user).unwrap_or_else(|| UserStats::new(user));
+        let old_key = leaderboard_key(&user_stats);
+        let result = f(&mut user_stats);
+        LEADERBOARD.with(|idx| {
+            let mut idx = idx.borrow_mut();
+            idx.remove(&old_key);
+            idx.insert(leaderboard_key(&user_stats));
         });
-        f(bal)
+        stats.insert(user, user_stats);
+        result
     })
 }
 
-fn apply_settlement_for_round(clearing: &ClearingResult) -> Result<(), String> {
-    // Build a map from order_id -> order to avoid repeated lookups
-    use std::collections::HashMap;
-
-    let orders_by_id: HashMap<OrderId, Order> = ORDERS.with(|orders| {
-        orders
-            .borrow()
-            .iter()
-            .map(|entry| {
-                let id = *entry.key();
-                let order = entry.value().clone();
-                (id, order)
-            })
-            .collect()
-    });
+pub(crate) fn market_state(market_id: MarketId) -> Result<MarketState, VeilError> {
+    with_state(|s| s.markets.get(&market_id).cloned()).ok_or(VeilError::UnknownMarket(market_id))
+}
 
-    for m in &clearing.matches {
-        let order = match orders_by_id.get(&m.order_id) {
-            Some(o) => o,
-            None => {
-                ic_cdk::println!("Settlement: unknown order id {}", m.order_id);
-                continue;
-            }
-        };
+pub(crate) fn with_market_mut<R>(market_id: MarketId, f: impl FnOnce(&mut MarketState) -> R) -> Result<R, VeilError> {
+    with_state_mut(|state| {
+        state
+            .markets
+            .get_mut(&market_id)
+            .map(f)
+            .ok_or(VeilError::UnknownMarket(market_id))
+    })
+}
 
-        let user = order.owner;
-        let fill_amount = m.fill_amount;
-        let clearing_price = clearing.clearing_price;
-
-        match order.order_type {
-            OrderType::Buy => {
-                // reserved = amount * price_limit (at submission)
-                let reserved = order
-                    .amount
-                    .checked_mul(order.price_limit)
-                    .ok_or_else(|| "Overflow in reserved funds".to_string())?;
-
-                let cost = fill_amount
-                    .checked_mul(clearing_price)
-                    .ok_or_else(|| "Overflow in settlement cost".to_string())?;
-
-                if cost > reserved {
-                    return Err(format!(
-                        "Settlement invariant violated for BUY order {}: cost {} > reserved {}",
-                        order.id, cost, reserved
-                    ));
+fn update_user_stats(result: &ClearingResult) {
+    // Get all orders for this round to know who participated
+    let round_orders: HashMap<OrderId, Principal> = round_orders(result.market_id, result.round_id)
+        .into_iter()
+        .map(|order| (order.id, order.owner))
+        .collect();
+    
+    // Update stats for each matched order
+    for order_match in &result.matches {
+        if let Some(user) = round_orders.get(&order_match.order_id) {
+            with_user_stats_mut(*user, |user_stat| {
+                user_stat.total_orders += 1;
+                
+                if order_match.filled {
+                    user_stat.filled_orders += 1;
+                    user_stat.total_surplus = user_stat.total_surplus.saturating_add(order_match.surplus);
                 }
-
-                let refund = reserved - cost;
-
-                with_demo_balance_mut(&user, |bal| {
-                    // We expect usd_locked >= reserved, but be defensive
-                    if bal.usd_locked < reserved {
-                        ic_cdk::println!(
-                            "Warning: usd_locked {} < reserved {} for user {:?}",
-                            bal.usd_locked,
-                            reserved,
-                            user
-                        );
-                    } else {
-                        bal.usd_locked -= reserved;
-                    }
-
-                    // Buyer pays 'cost' and gets BTC
-                    bal.btc_free = bal.btc_free.saturating_add(fill_amount);
-                    // Any leftover reserved funds are refunded as free USD
-                    bal.usd_free = bal.usd_free.saturating_add(refund);
-                });
+            });
+        }
+    }
+    
+    // Mark round participation for all unique users
+    let unique_users: std::collections::HashSet<_> = round_orders.values().collect();
+    USER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        for user in unique_users {
+            if let Some(mut user_stat) = stats.get(user) {
+                user_stat.rounds_participated += 1;
+                stats.insert(*user, user_stat);
             }
+        }
+    });
+}
 
-            OrderType::Sell => {
-                // reserved BTC = amount (at submission)
-                let reserved_btc = order.amount;
-                let unsold_btc = reserved_btc.saturating_sub(fill_amount);
-
-                let proceeds = fill_amount
-                    .checked_mul(clearing_price)
-                    .ok_or_else(|| "Overflow in seller proceeds".to_string())?;
-
-                with_demo_balance_mut(&user, |bal| {
-                    if bal.btc_locked < reserved_btc {
-                        ic_cdk::println!(
-                            "Warning: btc_locked {} < reserved_btc {} for user {:?}",
-                            bal.btc_locked,
-                            reserved_btc,
-                            user
-                        );
-                    } else {
-                        bal.btc_locked -= reserved_btc;
-                    }
+/// Settle a cleared round, record the fee each match paid and close its
+/// orders as filled, partially filled or unfilled
+async fn apply_settlement_for_round(clearing: &mut ClearingResult) -> Result<(), VeilError> {
+    // Build a map from order_id -> order to avoid repeated lookups
+    use std::collections::HashMap;
 
-                    // Unsold BTC is returned
-                    bal.btc_free = bal.btc_free.saturating_add(unsold_btc);
-                    // Proceeds in USD are credited
-                    bal.usd_free = bal.usd_free.saturating_add(proceeds);
-                });
-            }
-        }
+    let market = market_state(clearing.market_id)?.market;
+
+    let mut orders_by_id: HashMap<OrderId, Order> = round_orders(clearing.market_id, clearing.round_id)
+        .into_iter()
+        .map(|order| (order.id, order))
+        .collect();
+
+    let fills = clearing
+        .matches
+        .iter()
+        .map(|m| {
+            let order = orders_by_id.remove(&m.order_id).ok_or(VeilError::UnknownOrder(m.order_id))?;
+            Ok((order, m.clone()))
+        })
+        .collect::<Result<Vec<_>, VeilError>>()?;
+
+    let fees = escrow::settle_round(&market, &fills, clearing.clearing_price, clearing.timestamp).await?;
+    for (m, fee) in clearing.matches.iter_mut().zip(fees) {
+        m.fee = fee;
     }
 
+    let now = time();
+    for (order, m) in fills {
+        let status = match m.fill_amount {
+            0 => OrderStatus::Unfilled,
+            fill if fill < m.requested_amount => OrderStatus::PartiallyFilled,
+            _ => OrderStatus::Filled,
+        };
+        store_order(Order { status, updated_at: now, ..order });
+    }
     Ok(())
 }
 
@@ -558,8 +832,20 @@ fn apply_settlement_for_round(clearing: &ClearingResult) -> Result<(), String> {
 // ============================================================================
 
 #[query]
-fn get_round_state() -> State {
-    STATE.with(|s| s.borrow().clone())
+fn get_markets() -> Vec<Market> {
+    with_state(|s| s.markets.values().map(|m| m.market.clone()).collect())
+}
+
+/// Decimal places of each asset's smallest unit. Amounts are in smallest
+/// units, prices in smallest quote units per whole base unit.
+#[query]
+fn get_asset_decimals() -> Vec<(Asset, u8)> {
+    Asset::ALL.iter().map(|asset| (asset.clone(), asset.decimals())).collect()
+}
+
+#[query]
+fn get_round_state(market_id: MarketId) -> Option<MarketState> {
+    with_state(|s| s.markets.get(&market_id).cloned())
 }
 
 #[query]
@@ -568,22 +854,22 @@ fn get_order_count() -> u64 {
 }
 
 #[query]
-fn get_current_round_orders() -> u64 {
-    let current_round = STATE.with(|s| s.borrow().round_id);
+fn get_current_round_orders(market_id: MarketId) -> u64 {
+    let current_round = match market_state(market_id) {
+        Ok(m) => m.round_id,
+        Err(_) => return 0,
+    };
     
-    ORDERS.with(|orders| {
-        orders
-            .borrow()
-            .iter()
-            .filter(|entry| entry.value().round_id == current_round)
-            .count() as u64
-    })
+    round_order_ids(market_id, current_round, None, usize::MAX).len() as u64
 }
 
 #[query]
-fn get_time_remaining() -> u64 {
-    STATE.with(|s| {
-        let state = s.borrow();
+fn get_time_remaining(market_id: MarketId) -> u64 {
+    with_state(|s| {
+        let state = match s.markets.get(&market_id) {
+            Some(state) => state,
+            None => return 0,
+        };
         
         if state.round_state != RoundState::Active {
             return 0;
@@ -604,12 +890,16 @@ fn get_time_remaining() -> u64 {
 // ENCRYPTION PUBLIC KEY (for frontend)
 // ============================================================================
 
+/// vetKD public key orders are IBE-encrypted under
+#[update]
+async fn get_encryption_public_key() -> Result<Vec<u8>, VeilError> {
+    encryption::get_encryption_public_key().await
+}
+
+/// IBE identity to encrypt orders for a market's round to
 #[ic_cdk_macros::query]
-fn get_encryption_public_key() -> Vec<u8> {
-    // Demo key for PocketIC
-    let mut fake_key = vec![0u8; 32];
-    fake_key[..4].copy_from_slice(b"DEMO");
-    fake_key
+fn get_round_timelock_identity(market_id: MarketId, round_id: RoundId) -> Vec<u8> {
+    encryption::generate_timelock_identity(market_id, round_id)
 }
 
 
@@ -635,12 +925,12 @@ fn getrandom(_buf: *mut u8, _len: usize) -> i32 {
 #[ic_cdk_macros::query]
 pub fn get_my_demo_balance() -> DemoUserBalance {
     let user = ic_cdk::caller();
-    get_or_create_demo_balance(user)
+    escrow::demo_balance(user)
 }
 
 #[ic_cdk_macros::query]
 pub fn get_demo_balance_of(user: Principal) -> DemoUserBalance {
-    get_or_create_demo_balance(user)
+    escrow::demo_balance(user)
 }
 
 // ============================================================================
@@ -651,22 +941,27 @@ fn pocketic_submit_order(
     _round_id: u64,
     encrypted_payload: Vec<u8>,
     _commitment_hash: String
-) -> Result<u64, String> {
+) -> Result<u64, VeilError> {
+    access::require_admin()?;
     LAST_ORDER.with(|o| *o.borrow_mut() = encrypted_payload);
     Ok(1)
 }
 
 
 #[ic_cdk_macros::query]
-fn pocketic_get_order_ciphertext() -> Vec<u8> {
-    LAST_ORDER.with(|o| o.borrow().clone())
+fn pocketic_get_order_ciphertext() -> Result<Vec<u8>, VeilError> {
+    access::require_admin()?;
+    Ok(LAST_ORDER.with(|o| o.borrow().clone()))
 }
 
 #[ic_cdk_macros::update]
-pub fn set_vetkd_canister(id: Principal) {
-    VETKD_ID.with(|v| *v.borrow_mut() = Some(id));
+pub fn set_vetkd_canister(id: Principal) -> Result<(), VeilError> {
+    access::require_admin()?;
+    access::audit("set_vetkd_canister", id.to_string());
+    VETKD_ID.with(|v| v.borrow_mut().set(Some(id)));
+    Ok(())
 }
 
-pub fn vetkeys_engine_canister_id() -> Principal {
-    VETKD_ID.with(|v| v.borrow().expect("VETKD canister not set"))
+pub fn vetkeys_engine_canister_id() -> Option<Principal> {
+    VETKD_ID.with(|v| *v.borrow().get())
 }
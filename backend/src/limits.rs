@@ -0,0 +1,152 @@
+use crate::types::*;
+use crate::{memory, Memory, SUBMISSION_LIMITS_MEMORY_ID};
+use candid::{Decode, Principal};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use veil_core::pricing::{quote_value, Rounding};
+
+// ============================================================================
+// SUBMISSION LIMITS
+// ============================================================================
+//
+// Each market caps what one principal may submit to a single round: a
+// number of orders (cancelled ones included, so submit/cancel loops still
+// run out) and a notional summed over the principal's open orders.
+//
+// Size and limit price are encrypted until reveal, so notional is judged by
+// collateral, in the market's quote asset: a Buy locks quote and counts as
+// is, a Sell's base collateral is valued at the last clearing price, or
+// before the first clearing at the limits' reference price. A market with a
+// notional cap and neither price refuses sells, as it cannot value them.
+
+thread_local! {
+    static SUBMISSION_LIMITS: RefCell<StableBTreeMap<MarketId, SubmissionLimits, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(SUBMISSION_LIMITS_MEMORY_ID))
+    );
+}
+
+#[ic_cdk_macros::update]
+pub fn admin_set_submission_limits(market_id: MarketId, limits: SubmissionLimits) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("admin_set_submission_limits", format!("market {}: {:?}", market_id, limits));
+    crate::market_state(market_id)?;
+    if limits.max_orders_per_round == 0 || limits.max_notional_per_round == 0 || limits.reference_price == Some(0) {
+        return Err(VeilError::InvalidArgument("Limits must be > 0".to_string()));
+    }
+
+    SUBMISSION_LIMITS.with(|l| l.borrow_mut().insert(market_id, limits));
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+pub fn get_submission_limits(market_id: MarketId) -> SubmissionLimits {
+    SUBMISSION_LIMITS.with(|l| l.borrow().get(&market_id).unwrap_or_default())
+}
+
+/// Quote value of the collateral an order locks
+fn notional(
+    market: &MarketState,
+    limits: &SubmissionLimits,
+    order_type: &OrderType,
+    collateral: u64,
+) -> Result<u128, VeilError> {
+    match order_type {
+        OrderType::Buy => Ok(collateral as u128),
+        OrderType::Sell => market
+            .clearing_price_history
+            .last()
+            .copied()
+            .or(limits.reference_price)
+            .map(|price| quote_value(market.market.base.decimals(), collateral, price, Rounding::Down))
+            .ok_or(VeilError::NoReferencePrice { market_id: market.market.id }),
+    }
+}
+
+/// Check that `owner` may place an order locking `collateral` in the
+/// market's current round. When amending, `replacing` is the order's
+/// current version: it already counts toward the order limit, and its old
+/// collateral gives way to the new one.
+pub fn check_submission(
+    owner: Principal,
+    market: &MarketState,
+    order_type: &OrderType,
+    collateral: u64,
+    replacing: Option<&Order>,
+) -> Result<(), VeilError> {
+    let limits = get_submission_limits(market.market.id);
+    let round_key = (market.market.id, market.round_id);
+    let placed = crate::owner_orders(owner, round_key..=round_key, None, usize::MAX);
+
+    if replacing.is_none() && placed.len() >= limits.max_orders_per_round as usize {
+        return Err(VeilError::OrderLimitReached {
+            max_orders_per_round: limits.max_orders_per_round,
+        });
+    }
+
+    if limits.max_notional_per_round == u64::MAX {
+        return Ok(());
+    }
+    let mut total = notional(market, &limits, order_type, collateral)?;
+    for o in placed
+        .iter()
+        .filter(|o| o.status == OrderStatus::Open)
+        .filter(|o| replacing.is_none_or(|r| r.id != o.id))
+    {
+        total = total.saturating_add(notional(market, &limits, &o.order_type, o.collateral)?);
+    }
+    if total > limits.max_notional_per_round as u128 {
+        return Err(VeilError::NotionalLimitExceeded {
+            notional: total,
+            max: limits.max_notional_per_round,
+        });
+    }
+
+    Ok(())
+}
+
+// ============================================================================
+// INGRESS FILTER
+// ============================================================================
+//
+// Drops order messages that would fail anyway before they are executed and
+// charged. Only ingress passes through here; the endpoints repeat every
+// check, since calls from other canisters skip inspection.
+
+#[ic_cdk_macros::inspect_message]
+fn inspect_message() {
+    if admit(&ic_cdk::api::msg_method_name()).is_ok() {
+        ic_cdk::api::accept_message();
+    }
+}
+
+fn admit(method: &str) -> Result<(), VeilError> {
+    match method {
+        "submit_order" => {
+            let caller = crate::access::require_authenticated()?;
+            let (market_id, order_type, collateral, encrypted_payload, _commitment_hash) = Decode!(
+                &ic_cdk::api::msg_arg_data(),
+                MarketId,
+                OrderType,
+                u64,
+                Vec<u8>,
+                String
+            )
+            .map_err(|e| VeilError::InvalidArgument(e.to_string()))?;
+
+            if collateral == 0 {
+                return Err(VeilError::ZeroCollateral);
+            }
+            if encrypted_payload.is_empty() {
+                return Err(VeilError::EmptyPayload);
+            }
+            let market = crate::market_state(market_id)?;
+            if market.round_state != RoundState::Active {
+                return Err(VeilError::RoundNotActive { market_id });
+            }
+            crate::rules::check_collateral(&market, &order_type, collateral)?;
+            check_submission(caller, &market, &order_type, collateral, None)
+        }
+        "amend_order" | "cancel_order" => crate::access::require_authenticated().map(|_| ()),
+        _ => Ok(()),
+    }
+}
@@ -1,84 +1,88 @@
 use crate::types::*;
-use crate::{ORDERS, RESULTS, USER_STATS, STATE};
+use crate::{load_orders, owner_orders, round_order_ids, round_orders, with_state, LEADERBOARD, ORDERS, RESULTS, USER_STATS};
 use candid::Principal;
 use std::collections::HashMap;
+use std::ops::Bound;
+
+fn current_round(market_id: MarketId) -> Option<RoundId> {
+    with_state(|s| s.markets.get(&market_id).map(|m| m.round_id))
+}
+
+// ============================================================================
+// PAGINATION
+// ============================================================================
+
+/// Largest page any list query returns
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+fn page_limit(limit: u32) -> usize {
+    limit.clamp(1, MAX_PAGE_SIZE) as usize
+}
+
+/// `orders` holds up to `limit + 1` entries; the extra one only tells us
+/// there is another page
+fn order_page(mut orders: Vec<Order>, limit: usize) -> OrderPage {
+    let next_cursor = if orders.len() > limit {
+        orders.truncate(limit);
+        orders.last().map(OrderCursor::from)
+    } else {
+        None
+    };
+    OrderPage { orders, next_cursor }
+}
 
 // ============================================================================
 // USER QUERIES
 // ============================================================================
 
-/// Get all orders for a specific user
+/// Get a page of a user's orders across all markets and rounds
 #[ic_cdk_macros::query]
-pub fn get_user_orders(user: Principal) -> Vec<Order> {
-    ORDERS.with(|orders| {
-        orders
-            .borrow()
-            .iter()
-            .filter_map(|entry| {
-                let order = entry.value();
-                if order.owner == user {
-                    Some(order.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    })
+pub fn get_user_orders(user: Principal, cursor: Option<OrderCursor>, limit: u32) -> OrderPage {
+    let limit = page_limit(limit);
+    let all_rounds = (MarketId::MIN, RoundId::MIN)..=(MarketId::MAX, RoundId::MAX);
+    order_page(owner_orders(user, all_rounds, cursor, limit + 1), limit)
 }
 
-/// Get user's orders for current round only
+/// Get a page of a user's orders for a market's current round only
 #[ic_cdk_macros::query]
-pub fn get_user_current_round_orders(user: Principal) -> Vec<Order> {
-    let current_round = STATE.with(|s| s.borrow().round_id);
+pub fn get_user_current_round_orders(
+    user: Principal,
+    market_id: MarketId,
+    cursor: Option<OrderCursor>,
+    limit: u32,
+) -> OrderPage {
+    let limit = page_limit(limit);
+    let current_round = match current_round(market_id) {
+        Some(round_id) => round_id,
+        None => return order_page(Vec::new(), limit),
+    };
     
-    ORDERS.with(|orders| {
-        orders
-            .borrow()
-            .iter()
-            .filter_map(|entry| {
-                let order = entry.value();
-                if order.owner == user && order.round_id == current_round {
-                    Some(order.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    })
+    let round = (market_id, current_round)..=(market_id, current_round);
+    order_page(owner_orders(user, round, cursor, limit + 1), limit)
 }
 
 /// Get user's statistics
 #[ic_cdk_macros::query]
 pub fn get_user_stats(user: Principal) -> Option<UserStats> {
     USER_STATS.with(|stats| {
-        stats.borrow().get(&user).cloned()
+        stats.borrow().get(&user)
     })
 }
 
-/// Get user's surplus for a specific round
+/// Get user's surplus for a specific round of a market
 #[ic_cdk_macros::query]
-pub fn get_user_round_surplus(user: Principal, round_id: RoundId) -> u64 {
+pub fn get_user_round_surplus(user: Principal, market_id: MarketId, round_id: RoundId) -> u128 {
     // Get the clearing result
     let result = RESULTS.with(|results| {
-        results.borrow().get(&round_id).map(|r| r.clone())
+        results.borrow().get(&(market_id, round_id)).map(|r| r.clone())
     });
     
     if let Some(result) = result {
         // Get user's orders for this round
-        let user_order_ids: Vec<OrderId> = ORDERS.with(|orders| {
-            orders
-                .borrow()
-                .iter()
-                .filter_map(|entry| {
-                    let (id, order) = (entry.key(), entry.value());
-                    if order.owner == user && order.round_id == round_id {
-                        Some(*id)
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        });
+        let user_order_ids: Vec<OrderId> = owner_orders(user, (market_id, round_id)..=(market_id, round_id), None, usize::MAX)
+            .into_iter()
+            .map(|order| order.id)
+            .collect();
         
         // Sum up surplus from matches
         result
@@ -96,92 +100,100 @@ pub fn get_user_round_surplus(user: Principal, round_id: RoundId) -> u64 {
 // ROUND QUERIES
 // ============================================================================
 
-/// Get clearing result for a specific round
+/// Get clearing result for a specific round of a market
 #[ic_cdk_macros::query]
-pub fn get_round_result(round_id: RoundId) -> Option<ClearingResult> {
+pub fn get_round_result(market_id: MarketId, round_id: RoundId) -> Option<ClearingResult> {
     RESULTS.with(|results| {
-        results.borrow().get(&round_id).map(|r| r.clone())
+        results.borrow().get(&(market_id, round_id)).map(|r| r.clone())
     })
 }
 
-/// Get current round result (if available)
+/// Get a market's current round result (if available)
 #[ic_cdk_macros::query]
-pub fn get_current_round_result() -> Option<ClearingResult> {
-    let current_round = STATE.with(|s| s.borrow().round_id);
-    get_round_result(current_round)
+pub fn get_current_round_result(market_id: MarketId) -> Option<ClearingResult> {
+    let current_round = current_round(market_id)?;
+    get_round_result(market_id, current_round)
 }
 
-/// Get all orders for a specific round
+/// Get a page of the orders of a market's round
 #[ic_cdk_macros::query]
-pub fn get_round_orders(round_id: RoundId) -> Vec<Order> {
-    ORDERS.with(|orders| {
-        orders
-            .borrow()
-            .iter()
-            .filter_map(|entry| {
-                let order = entry.value();
-                if order.round_id == round_id {
-                    Some(order.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    })
+pub fn get_round_orders(
+    market_id: MarketId,
+    round_id: RoundId,
+    cursor: Option<OrderCursor>,
+    limit: u32,
+) -> OrderPage {
+    let limit = page_limit(limit);
+    let after = cursor.map(|c| c.order_id);
+    let ids = round_order_ids(market_id, round_id, after, limit + 1);
+    order_page(load_orders(ids), limit)
 }
 
-/// Get clearing price history
+/// Get a page of a market's clearing price history, oldest first, read off
+/// the stored results
 #[ic_cdk_macros::query]
-pub fn get_price_history() -> Vec<u64> {
-    STATE.with(|s| s.borrow().clearing_price_history.clone())
+pub fn get_price_history(market_id: MarketId, cursor: Option<RoundId>, limit: u32) -> PricePage {
+    let limit = page_limit(limit);
+    let mut rounds: Vec<(RoundId, u64)> = RESULTS.with(|results| {
+        results
+            .borrow()
+            .range((market_id, cursor.unwrap_or(0))..=(market_id, RoundId::MAX))
+            .take(limit + 1)
+            .map(|entry| (entry.key().1, entry.value().clearing_price))
+            .collect()
+    });
+    let next_cursor = if rounds.len() > limit { rounds.pop().map(|(round_id, _)| round_id) } else { None };
+    PricePage { prices: rounds.into_iter().map(|(_, price)| price).collect(), next_cursor }
 }
 
-/// Get last N clearing prices
+/// Get a market's last N clearing prices (at most `MAX_PAGE_SIZE`)
 #[ic_cdk_macros::query]
-pub fn get_recent_prices(count: usize) -> Vec<u64> {
-    STATE.with(|s| {
-        let history = &s.borrow().clearing_price_history;
-        let start = history.len().saturating_sub(count);
-        history[start..].to_vec()
-    })
+pub fn get_recent_prices(market_id: MarketId, count: usize) -> Vec<u64> {
+    let history = with_state(|s| s.markets.get(&market_id).map(|m| m.clearing_price_history.clone()))
+        .unwrap_or_default();
+    let start = history.len().saturating_sub(count.min(MAX_PAGE_SIZE as usize));
+    history[start..].to_vec()
 }
 
 // ============================================================================
 // LEADERBOARD QUERIES
 // ============================================================================
 
-/// Get leaderboard for a specific round
+/// Get a page of the leaderboard for a specific round of a market
 #[ic_cdk_macros::query]
-pub fn get_round_leaderboard(round_id: RoundId) -> Vec<LeaderboardEntry> {
+pub fn get_round_leaderboard(
+    market_id: MarketId,
+    round_id: RoundId,
+    cursor: Option<LeaderboardCursor>,
+    limit: u32,
+) -> LeaderboardPage {
+    // A settled round's results never change, so neither does its ranking
+    let ranked = round_leaderboard(market_id, round_id);
+    let start = cursor.map_or(0, |c| ranked.partition_point(|e| LeaderboardCursor::from(e).key() <= c.key()));
+    leaderboard_page(ranked.into_iter().skip(start).take(page_limit(limit) + 1).collect(), page_limit(limit))
+}
+
+fn round_leaderboard(market_id: MarketId, round_id: RoundId) -> Vec<LeaderboardEntry> {
     // Get clearing result
-    let result = match RESULTS.with(|results| results.borrow().get(&round_id).map(|r| r.clone())) {
+    let result = match RESULTS.with(|results| results.borrow().get(&(market_id, round_id)).map(|r| r.clone())) {
         Some(r) => r,
         None => return Vec::new(),
     };
     
     // Map order IDs to users
-    let order_owners: HashMap<OrderId, Principal> = ORDERS.with(|orders| {
-        orders
-            .borrow()
-            .iter()
-            .filter_map(|entry| {
-                let (id, order) = (entry.key(), entry.value());
-                if order.round_id == round_id {
-                    Some((*id, order.owner))
-                } else {
-                    None
-                }
-            })
-            .collect()
-    });
+    let order_owners: HashMap<OrderId, Principal> = round_orders(market_id, round_id)
+        .into_iter()
+        .map(|order| (order.id, order.owner))
+        .collect();
     
     // Calculate surplus per user
-    let mut user_surplus: HashMap<Principal, u64> = HashMap::new();
+    let mut user_surplus: HashMap<Principal, u128> = HashMap::new();
     let mut user_orders: HashMap<Principal, (u64, u64)> = HashMap::new(); // (total, filled)
     
     for order_match in &result.matches {
         if let Some(user) = order_owners.get(&order_match.order_id) {
-            *user_surplus.entry(*user).or_insert(0) += order_match.surplus;
+            let surplus = user_surplus.entry(*user).or_insert(0);
+            *surplus = surplus.saturating_add(order_match.surplus);
             
             let (total, filled) = user_orders.entry(*user).or_insert((0, 0));
             *total += 1;
@@ -196,127 +208,126 @@ pub fn get_round_leaderboard(round_id: RoundId) -> Vec<LeaderboardEntry> {
         .into_iter()
         .map(|(user, surplus)| {
             let (total, filled) = user_orders.get(&user).unwrap_or(&(0, 0));
-            let fill_rate = if *total > 0 {
-                (*filled * 100) / *total
-            } else {
-                0
-            };
-            
             LeaderboardEntry {
                 user,
                 surplus,
-                fill_rate,
-                rank: 0, // Will be set below
+                fill_rate: fill_rate(*filled, *total),
+                rank: 0,
             }
         })
         .collect();
-    
-    // Sort by surplus (descending)
-    leaderboard.sort_by(|a, b| b.surplus.cmp(&a.surplus));
-    
-    // Assign ranks
+
+    leaderboard.sort_by_key(|entry| LeaderboardCursor::from(entry).key());
     for (i, entry) in leaderboard.iter_mut().enumerate() {
         entry.rank = (i + 1) as u64;
     }
-    
     leaderboard
 }
 
-/// Get global leaderboard (all-time)
+/// Get a page of the global leaderboard (all-time)
 #[ic_cdk_macros::query]
-pub fn get_global_leaderboard() -> Vec<LeaderboardEntry> {
-    USER_STATS.with(|stats| {
-        let stats_map = stats.borrow();
-        
-        let mut leaderboard: Vec<LeaderboardEntry> = stats_map
-            .values()
-            .map(|user_stat| {
-                let fill_rate = if user_stat.total_orders > 0 {
-                    (user_stat.filled_orders * 100) / user_stat.total_orders
-                } else {
-                    0
-                };
-                
-                LeaderboardEntry {
+pub fn get_global_leaderboard(cursor: Option<LeaderboardCursor>, limit: u32) -> LeaderboardPage {
+    let limit = page_limit(limit);
+    leaderboard_page(global_leaderboard(cursor, limit + 1), limit)
+}
+
+/// Up to `limit` entries of the global leaderboard after `cursor`, read off
+/// the `LEADERBOARD` index
+fn global_leaderboard(cursor: Option<LeaderboardCursor>, limit: usize) -> Vec<LeaderboardEntry> {
+    let start = cursor.map_or(Bound::Unbounded, |c| Bound::Excluded(c.key()));
+    LEADERBOARD.with(|idx| {
+        let idx = idx.borrow();
+        let ahead = match &start {
+            Bound::Excluded(key) => idx.range(..=*key).count() as u64,
+            _ => 0,
+        };
+        let users: Vec<Principal> = idx.range((start, Bound::Unbounded)).take(limit).map(|(_, user)| user).collect();
+
+        USER_STATS.with(|stats| {
+            let stats = stats.borrow();
+            users
+                .into_iter()
+                .filter_map(|user| stats.get(&user))
+                .zip(ahead + 1..)
+                .map(|(user_stat, rank)| LeaderboardEntry {
                     user: user_stat.user,
                     surplus: user_stat.total_surplus,
-                    fill_rate,
-                    rank: 0,
-                }
-            })
-            .collect();
-        
-        // Sort by total surplus
-        leaderboard.sort_by(|a, b| b.surplus.cmp(&a.surplus));
-        
-        // Assign ranks
-        for (i, entry) in leaderboard.iter_mut().enumerate() {
-            entry.rank = (i + 1) as u64;
-        }
-        
-        leaderboard
+                    fill_rate: fill_rate(user_stat.filled_orders, user_stat.total_orders),
+                    rank,
+                })
+                .collect()
+        })
     })
 }
 
-/// Get top N players by surplus
+fn fill_rate(filled: u64, total: u64) -> u64 {
+    if total > 0 {
+        (filled * 100) / total
+    } else {
+        0
+    }
+}
+
+/// `entries` holds up to `limit + 1` entries; the extra one only tells us
+/// there is another page
+fn leaderboard_page(mut entries: Vec<LeaderboardEntry>, limit: usize) -> LeaderboardPage {
+    let next_cursor = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(LeaderboardCursor::from)
+    } else {
+        None
+    };
+    LeaderboardPage { entries, next_cursor }
+}
+
+/// Get top N players by surplus (at most `MAX_PAGE_SIZE`)
 #[ic_cdk_macros::query]
 pub fn get_top_players(count: usize) -> Vec<LeaderboardEntry> {
-    let mut leaderboard = get_global_leaderboard();
-    leaderboard.truncate(count);
-    leaderboard
+    global_leaderboard(None, count.min(MAX_PAGE_SIZE as usize))
 }
 
 // ============================================================================
 // ORDER BOOK QUERIES (for frontend display)
 // ============================================================================
 
-/// Get aggregated order book for current round (without revealing identities)
+/// Get aggregated order book for a market's current round (without revealing
+/// identities). Sizes are only known once revealed, so the volumes sum the
+/// revealed orders.
 #[ic_cdk_macros::query]
-pub fn get_order_book_summary() -> OrderBookSummary {
-    let current_round = STATE.with(|s| s.borrow().round_id);
-    
-    let (buy_count, sell_count, total_buy_volume, total_sell_volume) = ORDERS.with(|orders| {
-        let mut buy_count = 0u64;
-        let mut sell_count = 0u64;
-        let mut total_buy = 0u64;
-        let mut total_sell = 0u64;
-        
-        for entry in orders.borrow().iter() {
-            let order = entry.value();
-            if order.round_id == current_round {
-                match order.order_type {
-                    OrderType::Buy => {
-                        buy_count += 1;
-                        total_buy += order.amount;
-                    }
-                    OrderType::Sell => {
-                        sell_count += 1;
-                        total_sell += order.amount;
-                    }
-                }
-            }
-        }
-        
-        (buy_count, sell_count, total_buy, total_sell)
-    });
-    
-    OrderBookSummary {
+pub fn get_order_book_summary(market_id: MarketId) -> OrderBookSummary {
+    let current_round = current_round(market_id).unwrap_or_default();
+    let mut summary = OrderBookSummary {
+        market_id,
         round_id: current_round,
-        buy_orders: buy_count,
-        sell_orders: sell_count,
-        total_buy_volume,
-        total_sell_volume,
+        buy_orders: 0,
+        sell_orders: 0,
+        total_buy_volume: 0,
+        total_sell_volume: 0,
+    };
+
+    for order in round_orders(market_id, current_round) {
+        if order.status != OrderStatus::Open {
+            continue;
+        }
+        let (count, volume) = match order.order_type {
+            OrderType::Buy => (&mut summary.buy_orders, &mut summary.total_buy_volume),
+            OrderType::Sell => (&mut summary.sell_orders, &mut summary.total_sell_volume),
+        };
+        *count += 1;
+        *volume += order.amount as u128;
     }
+    summary
 }
 
 // Helper struct for order book summary
 #[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
 pub struct OrderBookSummary {
+    pub market_id: MarketId,
     pub round_id: RoundId,
     pub buy_orders: u64,
     pub sell_orders: u64,
-    pub total_buy_volume: u64,
-    pub total_sell_volume: u64,
+    pub total_buy_volume: u128,   // Revealed orders only
+    pub total_sell_volume: u128,
 }
 
 // ============================================================================
@@ -327,17 +338,17 @@ pub struct OrderBookSummary {
 #[ic_cdk_macros::query]
 pub fn get_platform_stats() -> PlatformStats {
     let total_orders = ORDERS.with(|orders| orders.borrow().len());
-    let total_rounds = STATE.with(|s| s.borrow().round_id);
-    let total_users = USER_STATS.with(|stats| stats.borrow().len() as u64);
+    let total_rounds = with_state(|s| s.markets.values().map(|m| m.round_id).sum());
+    let total_users = USER_STATS.with(|stats| stats.borrow().len());
     
     let (total_volume, total_surplus) = RESULTS.with(|results| {
         let mut volume = 0u64;
-        let mut surplus = 0u64;
+        let mut surplus = 0u128;
         
         for entry in results.borrow().iter() {
             let result = entry.value();
-            volume += result.total_volume;
-            surplus += result.total_surplus;
+            volume = volume.saturating_add(result.total_volume);
+            surplus = surplus.saturating_add(result.total_surplus);
         }
         
         (volume, surplus)
@@ -359,5 +370,5 @@ pub struct PlatformStats {
     pub total_rounds: u64,
     pub total_users: u64,
     pub total_volume: u64,
-    pub total_surplus: u64,
+    pub total_surplus: u128,
 }
\ No newline at end of file
@@ -0,0 +1,144 @@
+use crate::types::*;
+use crate::{memory, Memory, ROUND_TIMELINES_MEMORY_ID};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// ============================================================================
+// ROUND LIFECYCLE
+// ============================================================================
+//
+//   Pending ─> Active ─> Revealing ─> Clearing ─> Executing ─> Completed
+//                │           │            │            │
+//                │           └────────────┴────────────┴─> Failed
+//                └─> Aborted
+//
+// Completed, Failed and Aborted end a round. The next round starts from any
+// of them or from Pending, and `admin_reset_round` parks an ended round in
+// Pending. Failed and Aborted rounds release the escrow of every order still
+// open in them and mark it Expired.
+//
+// Every change goes through `transition`, which rejects anything not drawn
+// above and records when the round entered each phase.
+
+thread_local! {
+    static ROUND_TIMELINES: RefCell<StableBTreeMap<(MarketId, RoundId), RoundTimeline, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(ROUND_TIMELINES_MEMORY_ID))
+    );
+}
+
+fn allowed(from: &RoundState, to: &RoundState) -> bool {
+    use RoundState::*;
+    match to {
+        Active => *from == Pending || from.is_terminal(),
+        Revealing | Aborted => *from == Active,
+        Clearing => *from == Revealing,
+        Executing => *from == Clearing,
+        Completed => *from == Executing,
+        Failed => matches!(from, Revealing | Clearing | Executing),
+        Pending => from.is_terminal(),
+    }
+}
+
+/// Move a market's round to `to` and return the round it applies to.
+/// Moving to Active opens the next round.
+pub(crate) fn transition(market_id: MarketId, to: RoundState) -> Result<RoundId, VeilError> {
+    let now = ic_cdk::api::time();
+    let round_id = crate::with_market_mut(market_id, |state| {
+        if !allowed(&state.round_state, &to) {
+            return Err(VeilError::InvalidTransition {
+                market_id,
+                from: state.round_state.clone(),
+                to: to.clone(),
+            });
+        }
+        if to == RoundState::Active {
+            state.round_id += 1;
+            state.round_start_time = now;
+        }
+        state.round_state = to.clone();
+        Ok(state.round_id)
+    })??;
+
+    ic_cdk::println!("Market {} round {} is {:?}", market_id, round_id, to);
+    ROUND_TIMELINES.with(|t| {
+        let mut timelines = t.borrow_mut();
+        let mut timeline = timelines.get(&(market_id, round_id)).unwrap_or_default();
+        timeline.phases.push(RoundPhase { state: to, entered_at: now });
+        timelines.insert((market_id, round_id), timeline);
+    });
+    Ok(round_id)
+}
+
+/// End the current round as Failed or Aborted and refund its open orders
+pub(crate) async fn unwind(market_id: MarketId, to: RoundState) -> Result<RoundId, VeilError> {
+    let round_id = transition(market_id, to)?;
+    expire_open_orders(market_id, round_id).await?;
+    Ok(round_id)
+}
+
+/// Whether a round ended Failed or Aborted (it may have been reset since)
+fn unwound(market_id: MarketId, round_id: RoundId) -> bool {
+    get_round_timeline(market_id, round_id)
+        .iter()
+        .any(|phase| matches!(phase.state, RoundState::Failed | RoundState::Aborted))
+}
+
+/// Release the escrow of every order still open in an unwound round and mark
+/// it Expired; returns how many were. An order is only marked once its
+/// release went through, so a failed release is retried by the next call.
+/// Releases are idempotent (ledger refunds carry a per-order memo), and the
+/// orders of an ended round can no longer be cancelled, amended or settled,
+/// so calling this again never pays anything twice.
+async fn expire_open_orders(market_id: MarketId, round_id: RoundId) -> Result<u64, VeilError> {
+    let market = crate::market_state(market_id)?.market;
+    let open: Vec<Order> = crate::round_orders(market_id, round_id)
+        .into_iter()
+        .filter(|order| order.status == OrderStatus::Open)
+        .collect();
+    ic_cdk::println!("Refunding {} open orders of market {} round {}", open.len(), market_id, round_id);
+
+    let mut expired = 0;
+    for order in open {
+        if let Err(e) = crate::escrow::release_funds(&order, &market).await {
+            ic_cdk::println!("Failed to release escrow for order {}: {}", order.id, e);
+            continue;
+        }
+        crate::store_order(Order {
+            status: OrderStatus::Expired,
+            updated_at: ic_cdk::api::time(),
+            ..order
+        });
+        expired += 1;
+    }
+    Ok(expired)
+}
+
+// ============================================================================
+// ENDPOINTS
+// ============================================================================
+
+/// Stop a market's Active round and refund its open orders; returns its id
+#[ic_cdk_macros::update]
+pub async fn admin_abort_round(market_id: MarketId) -> Result<RoundId, VeilError> {
+    crate::access::require_operator()?;
+    crate::access::audit("admin_abort_round", format!("market {}", market_id));
+    unwind(market_id, RoundState::Aborted).await
+}
+
+/// Retry the refunds of a Failed or Aborted round; returns how many orders
+/// were refunded. Safe to call any number of times.
+#[ic_cdk_macros::update]
+pub async fn admin_refund_round(market_id: MarketId, round_id: RoundId) -> Result<u64, VeilError> {
+    crate::access::require_operator()?;
+    crate::access::audit("admin_refund_round", format!("market {} round {}", market_id, round_id));
+    if !unwound(market_id, round_id) {
+        return Err(VeilError::RoundNotUnwound { market_id, round_id });
+    }
+    expire_open_orders(market_id, round_id).await
+}
+
+/// Phases a round went through, with the time it entered each
+#[ic_cdk_macros::query]
+pub fn get_round_timeline(market_id: MarketId, round_id: RoundId) -> Vec<RoundPhase> {
+    ROUND_TIMELINES.with(|t| t.borrow().get(&(market_id, round_id)).map(|t| t.phases).unwrap_or_default())
+}
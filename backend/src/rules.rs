@@ -0,0 +1,170 @@
+use crate::types::*;
+use veil_core::pricing::{quote_value, Rounding};
+use crate::{memory, Memory, PRICE_RULES_MEMORY_ID, TRADING_RULES_MEMORY_ID};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// ============================================================================
+// TRADING RULES
+// ============================================================================
+//
+// Per-market order parameters: price limits move in ticks, sizes in lots
+// above a minimum, and limits must stay within a band around the last
+// clearing price. Every market starts with defaults for its base asset
+// (`default_trading_rules`) that keep out dust orders.
+//
+// Size and limit are encrypted at submission, so the full check runs at
+// reveal, where breaking orders are rejected and their escrow released.
+// Submission only turns away collateral that cannot back the smallest
+// valid order. Clients can run the full check on their plaintext with
+// `validate_order` before encrypting.
+//
+// Each market also picks the rule that settles clearing-price ties, see
+// `veil_core::find_clearing_price_and_match`.
+
+thread_local! {
+    static TRADING_RULES: RefCell<StableBTreeMap<MarketId, TradingRules, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(TRADING_RULES_MEMORY_ID))
+    );
+
+    static PRICE_RULES: RefCell<StableBTreeMap<MarketId, PriceRule, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory(PRICE_RULES_MEMORY_ID))
+    );
+}
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+#[ic_cdk_macros::update]
+pub fn admin_set_trading_rules(market_id: MarketId, rules: TradingRules) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("admin_set_trading_rules", format!("market {}: {:?}", market_id, rules));
+    crate::market_state(market_id)?;
+    if rules.tick_size == 0 || rules.lot_size == 0 || rules.min_size == 0 {
+        return Err(VeilError::InvalidArgument("Tick, lot and minimum size must be > 0".to_string()));
+    }
+
+    TRADING_RULES.with(|r| r.borrow_mut().insert(market_id, rules));
+    Ok(())
+}
+
+/// Starting rules of a market trading `base`: lots and a minimum size worth
+/// a few cents and a few dollars at recent prices, any quote tick
+pub(crate) fn default_trading_rules(base: &Asset) -> TradingRules {
+    let (lot_size, min_size) = match base {
+        // 0.00001 BTC lots, 0.0001 BTC minimum
+        Asset::BTC => (1_000, 10_000),
+        // 0.000001 ETH lots, 0.001 ETH minimum
+        Asset::ETH => (1_000, 1_000_000),
+        // $0.01 lots, $1.00 minimum
+        Asset::USD => (1, 100),
+    };
+    TradingRules { tick_size: 1, lot_size, min_size, max_price_deviation_bps: 0 }
+}
+
+/// Give markets without rules their defaults; run at init, on upgrade and
+/// when a market is created
+pub(crate) fn seed_trading_rules() {
+    let markets: Vec<Market> = crate::with_state(|state| state.markets.values().map(|m| m.market.clone()).collect());
+    TRADING_RULES.with(|r| {
+        let mut rules = r.borrow_mut();
+        for market in markets {
+            if !rules.contains_key(&market.id) {
+                rules.insert(market.id, default_trading_rules(&market.base));
+            }
+        }
+    });
+}
+
+#[ic_cdk_macros::query]
+pub fn get_trading_rules(market_id: MarketId) -> TradingRules {
+    TRADING_RULES.with(|r| r.borrow().get(&market_id).unwrap_or_default())
+}
+
+#[ic_cdk_macros::update]
+pub fn admin_set_price_rule(market_id: MarketId, rule: PriceRule) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("admin_set_price_rule", format!("market {}: {:?}", market_id, rule));
+    crate::market_state(market_id)?;
+
+    PRICE_RULES.with(|r| r.borrow_mut().insert(market_id, rule));
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+pub fn get_price_rule(market_id: MarketId) -> PriceRule {
+    PRICE_RULES.with(|r| r.borrow().get(&market_id).unwrap_or_default())
+}
+
+/// Run the reveal-time checks on a plaintext order
+#[ic_cdk_macros::query]
+pub fn validate_order(market_id: MarketId, amount: u64, price_limit: u64) -> Result<(), VeilError> {
+    let market = crate::market_state(market_id)?;
+    check_order(&market, amount, price_limit)
+}
+
+// ============================================================================
+// CHECKS
+// ============================================================================
+
+/// Limits a price may take: within `max_price_deviation_bps` of the last
+/// clearing price, or unbounded before the market first clears
+fn price_band(market: &MarketState, rules: &TradingRules) -> Option<(u64, u64)> {
+    if rules.max_price_deviation_bps == 0 {
+        return None;
+    }
+    let last = *market.clearing_price_history.last()? as u128;
+    let bps = rules.max_price_deviation_bps as u128;
+    let min = last * 10_000u128.saturating_sub(bps) / 10_000;
+    let max = last * (10_000 + bps) / 10_000;
+    Some((min as u64, max.min(u64::MAX as u128) as u64))
+}
+
+/// Check a revealed order against its market's rules
+pub fn check_order(market: &MarketState, amount: u64, price_limit: u64) -> Result<(), VeilError> {
+    let rules = get_trading_rules(market.market.id);
+
+    if amount < rules.min_size {
+        return Err(VeilError::BelowMinimumSize { amount, min_size: rules.min_size });
+    }
+    if !amount.is_multiple_of(rules.lot_size) {
+        return Err(VeilError::InvalidLot { amount, lot_size: rules.lot_size });
+    }
+    if price_limit == 0 || !price_limit.is_multiple_of(rules.tick_size) {
+        return Err(VeilError::InvalidTick { price_limit, tick_size: rules.tick_size });
+    }
+    if let Some((min, max)) = price_band(market, &rules) {
+        if price_limit < min || price_limit > max {
+            return Err(VeilError::OutsidePriceBand { price_limit, min, max });
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that `collateral` can back at least the smallest valid order:
+/// `min_size` base for a Sell, `min_size` at the lowest allowed price for a Buy
+pub fn check_collateral(
+    market: &MarketState,
+    order_type: &OrderType,
+    collateral: u64,
+) -> Result<(), VeilError> {
+    let rules = get_trading_rules(market.market.id);
+    let min = match order_type {
+        OrderType::Sell => rules.min_size,
+        OrderType::Buy => {
+            let lowest_price = price_band(market, &rules)
+                .map(|(min, _)| min.div_ceil(rules.tick_size) * rules.tick_size)
+                .unwrap_or(0)
+                .max(rules.tick_size);
+            let value = quote_value(market.market.base.decimals(), rules.min_size, lowest_price, Rounding::Up);
+            u64::try_from(value).unwrap_or(u64::MAX)
+        }
+    };
+
+    if collateral < min {
+        return Err(VeilError::InsufficientCollateral { collateral, required: min });
+    }
+    Ok(())
+}
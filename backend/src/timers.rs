@@ -1,4 +1,4 @@
-use crate::{STATE, types::RoundState};
+use crate::{with_state, types::{MarketId, RoundState, VeilError}};
 use ic_cdk_timers::{set_timer, set_timer_interval, TimerId};
 use std::time::Duration;
 use std::cell::RefCell;
@@ -13,7 +13,7 @@ pub fn start_round_timer() {
     
     // Check every 5 seconds if round should progress
     let timer_id = set_timer_interval(Duration::from_secs(5), || {
-        ic_cdk::spawn(check_and_progress_round());
+        ic_cdk::spawn(check_and_progress_rounds());
     });
     
     ROUND_TIMER.with(|timer| {
@@ -23,95 +23,114 @@ pub fn start_round_timer() {
     ic_cdk::println!("Round timer started");
 }
 
-/// Check if round should progress and do so if needed
-async fn check_and_progress_round() {
-    let (should_progress, current_state, round_id) = STATE.with(|s| {
-        let state = s.borrow();
+/// Check every market and progress its round if needed
+async fn check_and_progress_rounds() {
+    let market_ids: Vec<MarketId> = with_state(|s| s.markets.keys().copied().collect());
+
+    for market_id in market_ids {
+        check_and_progress_round(market_id).await;
+    }
+}
+
+/// Check if a market's round should progress and do so if needed
+async fn check_and_progress_round(market_id: MarketId) {
+    let Some((should_progress, current_state, round_id)) = with_state(|s| {
+        let markets = &s.markets;
+        let state = markets.get(&market_id)?;
         let current_time = ic_cdk::api::time();
         let elapsed = current_time.saturating_sub(state.round_start_time);
         
         let should_progress = state.round_state == RoundState::Active
             && elapsed >= state.round_duration_ns;
         
-        (should_progress, state.round_state.clone(), state.round_id)
-    });
+        Some((should_progress, state.round_state.clone(), state.round_id))
+    }) else {
+        return;
+    };
     
     if should_progress {
         ic_cdk::println!(
-            "Round {} time expired. Auto-progressing to clearing...",
+            "Market {} round {} time expired. Auto-progressing to clearing...",
+            market_id,
             round_id
         );
         
         // Trigger clearing
-        let result = crate::admin_run_clearing().await;
-        ic_cdk::println!("Auto-clearing result: {}", result);
+        match crate::run_clearing(market_id).await {
+            Ok(result) => ic_cdk::println!(
+                "Auto-cleared market {} round {} at {}",
+                market_id,
+                round_id,
+                result.clearing_price
+            ),
+            Err(e) => ic_cdk::println!("Auto-clearing of market {} failed: {}", market_id, e),
+        }
         
         // After completion, wait 10 seconds then start new round
-        set_timer(Duration::from_secs(10), || {
-            ic_cdk::spawn(auto_start_next_round());
+        set_timer(Duration::from_secs(10), move || {
+            ic_cdk::spawn(auto_start_next_round(market_id));
         });
     }
     
-    // Auto-start if in Completed state for too long
-    if current_state == RoundState::Completed {
-        let time_since_completion = STATE.with(|s| {
-            let state = s.borrow();
+    // Auto-start if the round ended a while ago
+    if current_state.is_terminal() {
+        let time_since_completion = with_state(|s| {
+            let markets = &s.markets;
+            let state = &markets[&market_id];
             ic_cdk::api::time().saturating_sub(state.round_start_time + state.round_duration_ns)
         });
         
         // If 15 seconds have passed since completion, start new round
         if time_since_completion >= 15_000_000_000 {
-            ic_cdk::println!("Auto-starting next round after completion delay");
-            ic_cdk::spawn(auto_start_next_round());
+            ic_cdk::println!("Auto-starting next round for market {} after completion delay", market_id);
+            ic_cdk::spawn(auto_start_next_round(market_id));
         }
     }
 }
 
-/// Automatically start the next round
-async fn auto_start_next_round() {
-    STATE.with(|s| {
-        let mut state = s.borrow_mut();
-        
-        if state.round_state == RoundState::Completed || state.round_state == RoundState::Pending {
-            state.round_id += 1;
-            state.round_state = RoundState::Active;
-            state.round_start_time = ic_cdk::api::time();
-            
-            ic_cdk::println!(
-                "Auto-started round {}. Duration: {}s",
-                state.round_id,
-                state.round_duration_ns / 1_000_000_000
-            );
-        }
-    });
+/// Automatically start the next round of a market, unless one is running
+async fn auto_start_next_round(market_id: MarketId) {
+    if let Ok(round_id) = crate::rounds::transition(market_id, RoundState::Active) {
+        ic_cdk::println!("Auto-started market {} round {}", market_id, round_id);
+    }
 }
 
-/// Stop the automatic round timer (for testing/admin)
+/// Stop the automatic round timer (for testing/admin).
+/// Returns whether a timer was running.
 #[ic_cdk_macros::update]
-pub fn stop_round_timer() -> String {
+pub fn stop_round_timer() -> Result<bool, VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("stop_round_timer", String::new());
     ROUND_TIMER.with(|timer| {
-        if let Some(timer_id) = timer.borrow_mut().take() {
+        let stopped = timer.borrow_mut().take();
+        if let Some(timer_id) = stopped {
             ic_cdk_timers::clear_timer(timer_id);
-            "Round timer stopped".to_string()
-        } else {
-            "No active timer to stop".to_string()
         }
+        Ok(stopped.is_some())
     })
 }
 
 /// Manually trigger round progression (for testing)
 #[ic_cdk_macros::update]
-pub async fn force_progress_round() -> String {
-    check_and_progress_round().await;
-    "Round progression triggered".to_string()
+pub async fn force_progress_round() -> Result<(), VeilError> {
+    crate::access::require_operator()?;
+    crate::access::audit("force_progress_round", String::new());
+    check_and_progress_rounds().await;
+    Ok(())
 }
 
-/// Set custom round duration (for testing)
+/// Set custom round duration for a market (for testing)
 #[ic_cdk_macros::update]
-pub fn set_round_duration(seconds: u64) -> String {
-    STATE.with(|s| {
-        let mut state = s.borrow_mut();
-        state.round_duration_ns = seconds * 1_000_000_000;
-        format!("Round duration set to {} seconds", seconds)
+pub fn set_round_duration(market_id: MarketId, seconds: u64) -> Result<(), VeilError> {
+    crate::access::require_admin()?;
+    crate::access::audit("set_round_duration", format!("market {}: {}s", market_id, seconds));
+    let duration_ns = seconds
+        .checked_mul(1_000_000_000)
+        .filter(|ns| *ns > 0)
+        .ok_or_else(|| VeilError::InvalidArgument(format!("Invalid round duration {}s", seconds)))?;
+
+    crate::with_market_mut(market_id, |state| {
+        state.round_duration_ns = duration_ns;
+        ic_cdk::println!("{} round duration set to {} seconds", state.market.symbol, seconds);
     })
 }
\ No newline at end of file
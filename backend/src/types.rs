@@ -3,23 +3,25 @@ use serde::{Deserialize, Serialize};
 use ic_stable_structures::storable::Bound;
 use ic_stable_structures::Storable;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 
-pub type OrderId = u64;
-pub type RoundId = u64;
-pub type Timestamp = u64;
+pub use veil_core::types::*;
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Asset {
-    BTC,
-    ETH,
-}
-
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum OrderType {
-    Buy,
-    Sell,
+// Plaintext of an encrypted order (payload format version 1). On the wire it
+// is a version byte followed by the Candid encoding of this record; see
+// `encryption::encode_order_payload`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OrderPayload {
+    pub market_id: MarketId,
+    pub round_id: RoundId,
+    pub owner: Principal,
+    pub side: OrderType,
+    pub amount: u64,
+    pub price_limit: u64,
+    pub nonce: Vec<u8>,  // Random salt (>= 16 bytes) so equal orders have distinct commitments
 }
 
+// Phase of a market's round; changes go through `rounds::transition`
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum RoundState {
     Pending,      // Waiting to start
@@ -28,76 +30,106 @@ pub enum RoundState {
     Clearing,     // Finding price
     Executing,    // Settling on-chain
     Completed,    // Done
+    Failed,       // Clearing failed, open orders refunded
+    Aborted,      // Stopped by an operator, open orders refunded
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone)]
-pub struct Order {
-    pub id: OrderId,
-    pub round_id: RoundId,
-    pub owner: Principal,
-    pub order_type: OrderType,
-    pub asset: Asset,
-    pub amount: u64,           // Amount in smallest unit (satoshis/wei)
-    pub price_limit: u64,      // Price in USD cents (e.g., 67500 = $675.00)
-    pub created_at: Timestamp,
-    pub encrypted_payload: Vec<u8>,
-    pub commitment_hash: String,  // Hash of unencrypted order for verification
-}
-
-#[derive(CandidType, Deserialize, Serialize)]
-pub enum ResultOrder {
-    Ok(u64),
-    Err(String),
+impl RoundState {
+    /// The round is over; the next one may start
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, RoundState::Completed | RoundState::Failed | RoundState::Aborted)
+    }
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct OrderMatch {
-    pub order_id: OrderId,
-    pub filled: bool,
-    pub fill_amount: u64,
-    pub fill_price: u64,
-    pub surplus: u64,  // Savings for buyer or extra earnings for seller
+// When a round entered one of its phases
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoundPhase {
+    pub state: RoundState,
+    pub entered_at: Timestamp,
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct ClearingResult {
-    pub round_id: RoundId,
-    pub clearing_price: u64,
-    pub total_volume: u64,
-    pub total_surplus: u64,
-    pub matches: Vec<OrderMatch>,
-    pub timestamp: Timestamp,
+// Phases of one round, in order
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RoundTimeline {
+    pub phases: Vec<RoundPhase>,
 }
 
+// Round lifecycle of a single market
 #[derive(CandidType, Serialize, Deserialize, Clone)]
-pub struct State {
+pub struct MarketState {
+    pub market: Market,
     pub round_id: RoundId,
     pub round_state: RoundState,
     pub round_start_time: Timestamp,
     pub round_duration_ns: u64,  // 60 seconds = 60_000_000_000 nanoseconds
-    pub next_order_id: OrderId,
-    pub clearing_price_history: Vec<u64>,
+    pub clearing_price_history: Vec<u64>,  // The last `RECENT_PRICES`, oldest first
 }
 
-impl Default for State {
-    fn default() -> Self {
-        State {
+impl MarketState {
+    pub fn new(id: MarketId, base: Asset, quote: Asset) -> Self {
+        MarketState {
+            market: Market {
+                id,
+                symbol: format!("{:?}/{:?}", base, quote),
+                base,
+                quote,
+            },
             round_id: 0,
             round_state: RoundState::Pending,
             round_start_time: 0,
             round_duration_ns: 60_000_000_000,  // 60 seconds
-            next_order_id: 0,
             clearing_price_history: Vec::new(),
         }
     }
+
+    /// The state is copied on every write, so it only keeps as many recent
+    /// clearing prices as `get_recent_prices` returns; `RESULTS` holds every
+    /// round's
+    pub const RECENT_PRICES: usize = crate::queries::MAX_PAGE_SIZE as usize;
+
+    pub fn record_clearing_price(&mut self, price: u64) {
+        self.clearing_price_history.push(price);
+        let excess = self.clearing_price_history.len().saturating_sub(Self::RECENT_PRICES);
+        self.clearing_price_history.drain(..excess);
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct State {
+    pub markets: BTreeMap<MarketId, MarketState>,
+    pub next_market_id: MarketId,
+    pub next_order_id: OrderId,
+}
+
+impl State {
+    pub fn add_market(&mut self, base: Asset, quote: Asset) -> MarketId {
+        let id = self.next_market_id;
+        self.next_market_id += 1;
+        self.markets.insert(id, MarketState::new(id, base, quote));
+        id
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let mut state = State {
+            markets: BTreeMap::new(),
+            next_market_id: 0,
+            next_order_id: 0,
+        };
+        state.add_market(Asset::BTC, Asset::USD);
+        state.add_market(Asset::ETH, Asset::USD);
+        state.add_market(Asset::ETH, Asset::BTC);
+        state
+    }
 }
 
 // Storable implementations for stable memory
-impl Storable for Order {
+impl Storable for State {
     fn into_bytes(self) -> Vec<u8> {
         Encode!(&self).unwrap()
     }
-    
+
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
@@ -109,20 +141,73 @@ impl Storable for Order {
     const BOUND: Bound = Bound::Unbounded;
 }
 
-impl Storable for ClearingResult {
-    fn into_bytes(self) -> Vec<u8> {
-        Encode!(&self).unwrap()
+// ============================================================================
+// PAGINATION
+// ============================================================================
+
+// Position in an order listing: the last order of the previous page.
+// Listings are ordered by (market_id, round_id, order_id).
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OrderCursor {
+    pub market_id: MarketId,
+    pub round_id: RoundId,
+    pub order_id: OrderId,
+}
+
+impl From<&Order> for OrderCursor {
+    fn from(order: &Order) -> Self {
+        OrderCursor {
+            market_id: order.market_id,
+            round_id: order.round_id,
+            order_id: order.id,
+        }
     }
+}
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct OrderPage {
+    pub orders: Vec<Order>,
+    pub next_cursor: Option<OrderCursor>,  // None on the last page
+}
+
+// Clearing prices of consecutive settled rounds, oldest first; the cursor is
+// the round the next page starts at
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PricePage {
+    pub prices: Vec<u64>,
+    pub next_cursor: Option<RoundId>,
+}
+
+// Position in a leaderboard: the last entry of the previous page.
+// Leaderboards are ordered by surplus (highest first), then principal, so a
+// page starts after its cursor even if the ranking changed in between.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LeaderboardCursor {
+    pub surplus: u128,
+    pub user: Principal,
+}
+
+impl From<&LeaderboardEntry> for LeaderboardCursor {
+    fn from(entry: &LeaderboardEntry) -> Self {
+        LeaderboardCursor {
+            surplus: entry.surplus,
+            user: entry.user,
+        }
     }
+}
 
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+impl LeaderboardCursor {
+    /// Key of the position in `LEADERBOARD`, which iterates highest surplus
+    /// first
+    pub fn key(&self) -> (u128, Principal) {
+        (u128::MAX - self.surplus, self.user)
     }
+}
 
-    const BOUND: Bound = Bound::Unbounded;
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LeaderboardPage {
+    pub entries: Vec<LeaderboardEntry>,
+    pub next_cursor: Option<LeaderboardCursor>,  // None on the last page
 }
 
 // User statistics
@@ -131,15 +216,29 @@ pub struct UserStats {
     pub user: Principal,
     pub total_orders: u64,
     pub filled_orders: u64,
-    pub total_surplus: u64,
+    pub cancelled_orders: u64,
+    pub total_surplus: u128,
     pub rounds_participated: u64,
 }
 
+impl UserStats {
+    pub fn new(user: Principal) -> Self {
+        UserStats {
+            user,
+            total_orders: 0,
+            filled_orders: 0,
+            cancelled_orders: 0,
+            total_surplus: 0,
+            rounds_participated: 0,
+        }
+    }
+}
+
 // Leaderboard entry
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct LeaderboardEntry {
     pub user: Principal,
-    pub surplus: u64,
+    pub surplus: u128,
     pub fill_rate: u64,  // Percentage (0-100)
     pub rank: u64,
 }
@@ -149,6 +248,784 @@ pub struct LeaderboardEntry {
 pub struct DemoUserBalance {
     pub btc_free: u64,    // available ckBTC units
     pub btc_locked: u64,  // ckBTC locked in open sell orders
+    pub eth_free: u64,    // available ckETH units
+    pub eth_locked: u64,  // ckETH locked in open orders
     pub usd_free: u64,    // available "USD" demo units for buys
     pub usd_locked: u64,  // USD locked in open buy orders
-}
\ No newline at end of file
+}
+
+impl DemoUserBalance {
+    /// (free, locked) slots for one asset
+    pub fn slots_mut(&mut self, asset: &Asset) -> (&mut u64, &mut u64) {
+        match asset {
+            Asset::BTC => (&mut self.btc_free, &mut self.btc_locked),
+            Asset::ETH => (&mut self.eth_free, &mut self.eth_locked),
+            Asset::USD => (&mut self.usd_free, &mut self.usd_locked),
+        }
+    }
+
+    /// (free, locked) amounts of one asset
+    pub fn slots(&self, asset: &Asset) -> (u64, u64) {
+        match asset {
+            Asset::BTC => (self.btc_free, self.btc_locked),
+            Asset::ETH => (self.eth_free, self.eth_locked),
+            Asset::USD => (self.usd_free, self.usd_locked),
+        }
+    }
+}
+
+
+// Where order escrow lives
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum EscrowBackend {
+    #[default]
+    Demo,      // In-canister demo balances
+    Ledger,    // ICRC-1/ICRC-2 ledgers (ckBTC, ckETH, ckUSDC)
+    Accounts,  // In-canister trading accounts funded by ledger deposits
+}
+
+// ICRC-1 account
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LedgerConfig {
+    pub ledger_id: Principal,
+    pub fee: u64,  // Ledger transfer fee, deducted from payouts; in the asset's unit (gwei for ETH)
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EscrowConfig {
+    pub backend: EscrowBackend,
+    pub ledgers: Vec<(Asset, LedgerConfig)>,
+}
+
+// ============================================================================
+// TRADING ACCOUNTS
+// ============================================================================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TransferKind {
+    Deposit,
+    Withdrawal,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TransferStatus {
+    Pending,         // Awaiting the ledger, or its outcome is unknown
+    Completed,
+    Failed(String),  // Refused by the ledger, or not in its log; nothing moved
+}
+
+// A deposit into or withdrawal out of a trading account
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AccountTransfer {
+    pub id: u64,
+    pub kind: TransferKind,
+    pub owner: Principal,
+    pub asset: Asset,
+    pub amount: u64,                // Credited or debited; a withdrawal arrives less the ledger fee
+    pub to: Option<Account>,        // Destination of a withdrawal
+    pub source: Option<Principal>,  // Trading account whose subaccount pays a withdrawal
+    pub status: TransferStatus,
+    pub created_at: Timestamp,      // Also the ledger's created_at_time
+    pub updated_at: Timestamp,
+}
+
+impl Storable for AccountTransfer {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Tokens the canister holds in one trading account's subaccount. Trades
+// settle on the book only, so this drifts from the owner's balance.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Custody {
+    pub assets: BTreeMap<Asset, u64>,
+}
+
+impl Storable for Custody {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A settlement payout the ledger did not confirm, kept for
+// `retry_settlement_payout`. The retry resends the same memo and
+// created_at, so the ledger drops it if the first attempt went through.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FailedPayout {
+    pub order_id: OrderId,
+    pub owner: Principal,
+    pub asset: Asset,
+    pub amount: u64,
+    pub memo: Vec<u8>,
+    pub created_at: Timestamp,
+    pub error: String,  // Of the latest attempt
+}
+
+impl Storable for FailedPayout {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// What a user holds of one asset
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AssetBalance {
+    pub asset: Asset,
+    pub free: u64,                // Available to trade or withdraw
+    pub locked: u64,              // Backing open orders
+    pub pending_withdrawal: u64,  // On its way out
+}
+
+// How one order settled. `paid` left its lock for the other side of the
+// round and `refunded` went back to the owner, both in `paid_asset`; the
+// owner received `received` net of `fee`, both in `received_asset`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OrderSettlement {
+    pub order_id: OrderId,
+    pub market_id: MarketId,
+    pub round_id: RoundId,
+    pub owner: Principal,
+    pub side: OrderType,
+    pub fill_amount: u64,
+    pub clearing_price: u64,
+    pub paid_asset: Asset,
+    pub paid: u64,
+    pub refunded: u64,
+    pub received_asset: Asset,
+    pub received: u64,
+    pub fee: u64,
+    pub settled_at: Timestamp,
+}
+
+impl Storable for OrderSettlement {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for RoundTimeline {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for UserStats {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for DemoUserBalance {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for EscrowConfig {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// SETTLEMENT JOURNAL
+// ============================================================================
+
+// Account of the in-canister balance book a journal leg moves value on
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum JournalAccount {
+    External,                // Outside the book: deposits come from here, withdrawals go here
+    Free(Principal),         // A user's spendable balance
+    Locked(Principal),       // A user's balance locked behind open orders
+    Clearing(MarketId),      // Passes value between the two sides of a market's rounds
+    Withdrawing(Principal),  // A user's withdrawals awaiting the ledger
+    Treasury,                // Protocol fees collected at settlement
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum JournalReason {
+    OpeningBalance,     // Balance that predates the journal
+    Deposit,            // Funds entering the book
+    Lock,               // Escrow taken for an order
+    Release,            // Escrow returned unused
+    FillDebit,          // Escrow spent on a fill; the unspent part returns to Free
+    FillCredit,         // Proceeds of a fill
+    WithdrawalRequest,  // Funds set aside for a withdrawal
+    Withdrawal,         // Funds leaving the book
+    WithdrawalRefund,   // A rejected withdrawal returned to Free
+    Fee,                // Protocol fee withheld from a fill's proceeds
+    TreasuryWithdrawal, // Protocol fees leaving the book
+}
+
+// One leg of a posting. The legs of a posting share its id and their deltas
+// sum to zero per asset.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub posting: u64,
+    pub timestamp: Timestamp,
+    pub market_id: Option<MarketId>,
+    pub round_id: Option<RoundId>,
+    pub order_id: Option<OrderId>,
+    pub account: JournalAccount,
+    pub asset: Asset,
+    pub delta: i128,
+    pub reason: JournalReason,
+}
+
+impl Storable for JournalEntry {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct JournalPage {
+    pub entries: Vec<JournalEntry>,
+    pub next_cursor: Option<u64>,  // Id of the first entry of the next page
+}
+
+// Running sums of the journal for one asset
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AssetTotals {
+    pub deposits: u128,
+    pub withdrawals: u128,
+    pub clearing: i128,  // Held by the clearing accounts of all markets
+    pub treasury: i128,  // Fees collected less fees withdrawn
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct JournalTotals {
+    pub next_entry: u64,
+    pub next_posting: u64,
+    pub assets: BTreeMap<Asset, AssetTotals>,
+}
+
+impl Storable for JournalTotals {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Balances of one asset checked against the journal
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AssetReconciliation {
+    pub asset: Asset,
+    pub deposits: u128,
+    pub withdrawals: u128,
+    pub free: u128,      // Summed over all users
+    pub locked: u128,    // Summed over all users
+    pub pending_withdrawals: u128,
+    pub clearing: i128,  // Rounding dust kept from fills
+    pub treasury: i128,  // Protocol fees held
+    pub balanced: bool,  // free + locked + pending_withdrawals + clearing + treasury == deposits - withdrawals
+}
+
+// ============================================================================
+// FEES
+// ============================================================================
+
+// Protocol fees of one asset
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FeeTotals {
+    pub collected: u128,  // Withheld at settlement, all time
+    pub withdrawn: u128,  // Sent out by admins, including withdrawals in flight
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Treasury {
+    pub backends: BTreeMap<EscrowBackend, BTreeMap<Asset, FeeTotals>>,  // By the backend that withheld them
+    pub next_withdrawal: u64,  // Id of the next withdrawal, for its ledger memo
+    pub pending: BTreeMap<u64, TreasuryWithdrawal>,  // Withdrawals not known to be sent or refused, by id
+}
+
+// A fee withdrawal awaiting its ledger outcome. It counts as withdrawn until
+// the ledger refuses it or an admin settles it as never sent.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TreasuryWithdrawal {
+    pub id: u64,
+    pub backend: EscrowBackend,     // Whose fees it draws on
+    pub asset: Asset,
+    pub amount: u64,
+    pub to: Account,
+    pub source: Option<Principal>,  // Trading account whose subaccount pays it
+    pub created_at: Timestamp,      // Also the ledger's created_at_time
+}
+
+impl Storable for Treasury {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// What the treasury holds of one asset, collected on one backend
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TreasuryBalance {
+    pub backend: EscrowBackend,
+    pub asset: Asset,
+    pub collected: u128,
+    pub withdrawn: u128,
+    pub balance: u64,  // Available to withdraw while `backend` is configured
+}
+
+// Fee rate of every market that charges one, and every principal's discount
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FeeSchedule {
+    pub rates: Vec<(MarketId, u32)>,       // Basis points of what an order receives
+    pub discounts: Vec<(Principal, u32)>,  // Basis points off the market rate
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Reconciliation {
+    pub assets: Vec<AssetReconciliation>,
+    pub balanced: bool,  // Every asset is
+    pub entries: u64,    // Journal entries so far
+}
+
+// ============================================================================
+// ACCESS CONTROL
+// ============================================================================
+
+// Privilege levels, lowest first: each role may do everything the ones
+// before it may. Controllers of the canister always hold `Controller`;
+// `Admin` and `Operator` are granted explicitly.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Operator,    // Runs rounds: start, clear, reset
+    Admin,       // Configures markets, escrow, timers; manages operators
+    Controller,  // Canister controller; manages admins
+}
+
+impl Storable for Role {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// One privileged call, recorded before it runs
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub timestamp: Timestamp,
+    pub caller: Principal,
+    pub role: Role,       // Role the caller held at the time
+    pub action: String,   // Endpoint name
+    pub detail: String,   // Arguments, human readable
+}
+
+impl Storable for AuditEntry {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next_cursor: Option<u64>,  // Id of the first entry of the next page
+}
+
+// ============================================================================
+// SUBMISSION LIMITS
+// ============================================================================
+
+// Caps on what a single principal may submit to one round of a market.
+// Notional is in the market's quote asset.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SubmissionLimits {
+    pub max_orders_per_round: u32,     // Cancelled orders count too
+    pub max_notional_per_round: u64,   // Summed over open orders, u64::MAX = none
+    pub reference_price: Option<u64>,  // Values sells until the market first clears
+}
+
+impl Default for SubmissionLimits {
+    fn default() -> Self {
+        SubmissionLimits {
+            max_orders_per_round: 50,
+            max_notional_per_round: u64::MAX,
+            reference_price: None,
+        }
+    }
+}
+
+impl Storable for SubmissionLimits {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// TRADING RULES
+// ============================================================================
+
+// Order parameters of a market. Sizes are in smallest base units, prices in
+// smallest quote units per whole base unit (see `veil_core::pricing`).
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TradingRules {
+    pub tick_size: u64,                 // price_limit must be a multiple
+    pub lot_size: u64,                  // amount must be a multiple
+    pub min_size: u64,                  // smallest amount
+    pub max_price_deviation_bps: u32,   // band around the last clearing price, 0 = none
+}
+
+impl Default for TradingRules {
+    fn default() -> Self {
+        TradingRules {
+            tick_size: 1,
+            lot_size: 1,
+            min_size: 1,
+            max_price_deviation_bps: 0,
+        }
+    }
+}
+
+impl Storable for TradingRules {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// ============================================================================
+// ERRORS
+// ============================================================================
+
+// Error of every fallible endpoint of the public API
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum VeilError {
+    // Callers
+    AnonymousCaller,
+    Unauthorized { required: Role },
+
+    // Markets and rounds
+    UnknownMarket(MarketId),
+    MarketExists { base: Asset, quote: Asset },
+    RoundNotActive { market_id: MarketId },
+    InvalidRoundState { market_id: MarketId, state: RoundState },
+    InvalidTransition { market_id: MarketId, from: RoundState, to: RoundState },
+    NothingToClear { market_id: MarketId, round_id: RoundId },
+    NoClearingPrice(String),
+    RoundNotUnwound { market_id: MarketId, round_id: RoundId },
+
+    // Orders
+    UnknownOrder(OrderId),
+    NotOrderOwner(OrderId),
+    OrderNotOpen { order_id: OrderId, status: OrderStatus },
+    OrderBusy(OrderId),  // An amendment is awaiting its escrow lock
+    ZeroCollateral,
+    EmptyPayload,
+    InvalidTick { price_limit: u64, tick_size: u64 },
+    InvalidLot { amount: u64, lot_size: u64 },
+    BelowMinimumSize { amount: u64, min_size: u64 },
+    OutsidePriceBand { price_limit: u64, min: u64, max: u64 },
+    InsufficientCollateral { collateral: u64, required: u64 },
+    OrderLimitReached { max_orders_per_round: u32 },
+    NotionalLimitExceeded { notional: u128, max: u64 },
+    NoReferencePrice { market_id: MarketId },  // Sells cannot be valued yet
+
+    // Reveal
+    DecryptionFailed { order_id: OrderId },
+    InvalidPayload { order_id: OrderId, reason: String },
+    CommitmentMismatch { order_id: OrderId },
+    KeyUnavailable(String),
+
+    // Funds
+    InsufficientBalance { required: u64, available: u64 },
+    InsufficientAllowance { required: u64, approved: u64 },
+    AccountsDisabled,  // Deposits and withdrawals need the Accounts escrow backend
+    UnknownTransfer(u64),
+    WithdrawalInFlight,
+    InsufficientCustody { asset: Asset, requested: u64, largest: u64 },  // No subaccount holds `requested`
+    BelowLedgerFee { amount: u64, fee: u64 },
+    InsufficientTreasury { asset: Asset, requested: u64, available: u64 },
+    LedgerNotConfigured(Asset),
+    TransferFailed(String),          // The ledger refused: nothing was sent
+    TransferOutcomeUnknown(String),  // Settled from the ledger's transaction log
+    Overflow,
+
+    // Anything else
+    InvalidArgument(String),
+    CallFailed { method: String, reason: String },
+    Internal(String),
+}
+
+impl From<veil_core::settlement::SettlementError> for VeilError {
+    fn from(e: veil_core::settlement::SettlementError) -> Self {
+        match e {
+            veil_core::settlement::SettlementError::Overflow => VeilError::Overflow,
+            invariant => VeilError::Internal(invariant.to_string()),
+        }
+    }
+}
+
+impl From<veil_core::ClearingError> for VeilError {
+    fn from(e: veil_core::ClearingError) -> Self {
+        match e {
+            veil_core::ClearingError::Overflow => VeilError::Overflow,
+            no_price => VeilError::NoClearingPrice(no_price.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for VeilError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VeilError::AnonymousCaller => write!(f, "Anonymous callers are not allowed"),
+            VeilError::Unauthorized { required } => write!(f, "Caller lacks the {:?} role", required),
+            VeilError::UnknownMarket(id) => write!(f, "Unknown market {}", id),
+            VeilError::MarketExists { base, quote } => write!(f, "Market {:?}/{:?} already exists", base, quote),
+            VeilError::RoundNotActive { market_id } => write!(f, "Round of market {} is not active", market_id),
+            VeilError::InvalidRoundState { market_id, state } => {
+                write!(f, "Round of market {} is {:?}", market_id, state)
+            }
+            VeilError::InvalidTransition { market_id, from, to } => {
+                write!(f, "Round of market {} cannot move from {:?} to {:?}", market_id, from, to)
+            }
+            VeilError::NothingToClear { market_id, round_id } => {
+                write!(f, "No orders to clear in market {} round {}", market_id, round_id)
+            }
+            VeilError::NoClearingPrice(reason) => write!(f, "No clearing price: {}", reason),
+            VeilError::RoundNotUnwound { market_id, round_id } => {
+                write!(f, "Round {} of market {} did not fail or abort", round_id, market_id)
+            }
+            VeilError::UnknownOrder(id) => write!(f, "Unknown order {}", id),
+            VeilError::NotOrderOwner(id) => write!(f, "Order {} is not owned by caller", id),
+            VeilError::OrderNotOpen { order_id, status } => write!(f, "Order {} is {:?}", order_id, status),
+            VeilError::OrderBusy(id) => write!(f, "Order {} is already being amended", id),
+            VeilError::ZeroCollateral => write!(f, "Collateral must be > 0"),
+            VeilError::EmptyPayload => write!(f, "Encrypted payload is empty"),
+            VeilError::InvalidTick { price_limit, tick_size } => {
+                write!(f, "Price limit {} is not a positive multiple of the tick size {}", price_limit, tick_size)
+            }
+            VeilError::InvalidLot { amount, lot_size } => {
+                write!(f, "Amount {} is not a multiple of the lot size {}", amount, lot_size)
+            }
+            VeilError::BelowMinimumSize { amount, min_size } => {
+                write!(f, "Amount {} is below the minimum size {}", amount, min_size)
+            }
+            VeilError::OutsidePriceBand { price_limit, min, max } => {
+                write!(f, "Price limit {} is outside the band [{}, {}]", price_limit, min, max)
+            }
+            VeilError::InsufficientCollateral { collateral, required } => {
+                write!(f, "Collateral {} does not cover {}", collateral, required)
+            }
+            VeilError::OrderLimitReached { max_orders_per_round } => {
+                write!(f, "Order limit reached: {} orders per round", max_orders_per_round)
+            }
+            VeilError::NotionalLimitExceeded { notional, max } => {
+                write!(f, "Notional limit exceeded: {} of {} per round", notional, max)
+            }
+            VeilError::NoReferencePrice { market_id } => {
+                write!(f, "Market {} has no clearing or reference price to value sells by", market_id)
+            }
+            VeilError::DecryptionFailed { order_id } => write!(f, "Order {} could not be decrypted", order_id),
+            VeilError::InvalidPayload { order_id, reason } => {
+                write!(f, "Order {} has an invalid payload: {}", order_id, reason)
+            }
+            VeilError::CommitmentMismatch { order_id } => {
+                write!(f, "SECURITY VIOLATION: Commitment hash mismatch for order {}", order_id)
+            }
+            VeilError::KeyUnavailable(reason) => write!(f, "vetKD key unavailable: {}", reason),
+            VeilError::InsufficientBalance { required, available } => {
+                write!(f, "Insufficient balance: required {}, available {}", required, available)
+            }
+            VeilError::InsufficientAllowance { required, approved } => {
+                write!(f, "Insufficient allowance: required {}, approved {}", required, approved)
+            }
+            VeilError::AccountsDisabled => write!(f, "Deposits and withdrawals need the Accounts escrow backend"),
+            VeilError::UnknownTransfer(id) => write!(f, "Unknown transfer {}", id),
+            VeilError::WithdrawalInFlight => write!(f, "A withdrawal of the account is still pending"),
+            VeilError::InsufficientCustody { asset, requested, largest } => write!(
+                f,
+                "No trading account's subaccount holds {} {:?}; at most {} can go out at once",
+                requested, asset, largest
+            ),
+            VeilError::BelowLedgerFee { amount, fee } => {
+                write!(f, "Amount {} does not cover the ledger fee {}", amount, fee)
+            }
+            VeilError::InsufficientTreasury { asset, requested, available } => {
+                write!(f, "Treasury holds {} {:?}, {} requested", available, asset, requested)
+            }
+            VeilError::LedgerNotConfigured(asset) => write!(f, "No ledger configured for {:?}", asset),
+            VeilError::TransferFailed(reason) => write!(f, "Transfer failed: {}", reason),
+            VeilError::TransferOutcomeUnknown(reason) => write!(f, "Transfer outcome unknown: {}", reason),
+            VeilError::Overflow => write!(f, "Arithmetic overflow"),
+            VeilError::InvalidArgument(reason) => write!(f, "Invalid argument: {}", reason),
+            VeilError::CallFailed { method, reason } => write!(f, "{} failed: {}", method, reason),
+            VeilError::Internal(reason) => write!(f, "Internal error: {}", reason),
+        }
+    }
+}
+
+// Kept as the reason an order was rejected at reveal
+impl Storable for VeilError {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
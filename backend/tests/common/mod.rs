@@ -0,0 +1,232 @@
+// Fixtures shared by the PocketIC tests. Each test crate uses a different
+// subset of them.
+//
+// Unless a test says otherwise, it loads the backend built with the demo
+// feature, which takes order payloads in the clear instead of as IBE
+// ciphertexts:
+//   cargo build --target wasm32-unknown-unknown --release --features demo
+#![allow(dead_code)]
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+pub use veil_core::types::{Asset, OrderType};
+
+pub const BACKEND_WASM: &str = "target/wasm32-unknown-unknown/release/mempool_chess_backend.wasm";
+
+pub const BTC_USD: u32 = 0;
+
+pub fn backend_wasm() -> Vec<u8> {
+    std::fs::read(BACKEND_WASM)
+        .expect("Run cargo build --target wasm32-unknown-unknown --release --features demo first")
+}
+
+pub fn install_backend(ic: &PocketIc) -> Principal {
+    let backend = ic.create_canister();
+    ic.add_cycles(backend, 10_000_000_000_000u128);
+    ic.install_canister(backend, backend_wasm(), vec![], None);
+    backend
+}
+
+// ============ MINIMAL ICRC TYPES ============
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType)]
+struct ArchiveOptions {
+    num_blocks_to_archive: u64,
+    trigger_threshold: u64,
+    controller_id: Principal,
+}
+
+#[derive(CandidType)]
+struct FeatureFlags {
+    icrc2: bool,
+}
+
+#[derive(CandidType)]
+struct InitArgs {
+    minting_account: Account,
+    transfer_fee: Nat,
+    token_symbol: String,
+    token_name: String,
+    metadata: Vec<(String, String)>,
+    initial_balances: Vec<(Account, Nat)>,
+    feature_flags: Option<FeatureFlags>,
+    archive_options: ArchiveOptions,
+}
+
+#[derive(CandidType)]
+enum LedgerArg {
+    Init(InitArgs),
+}
+
+#[derive(CandidType)]
+struct ApproveArgs {
+    spender: Account,
+    amount: Nat,
+}
+
+pub const FEE: u64 = 10;
+
+pub fn account(owner: Principal) -> Account {
+    Account { owner, subaccount: None }
+}
+
+pub fn ledger_wasm() -> Vec<u8> {
+    let path = std::env::var("ICRC1_LEDGER_WASM")
+        .unwrap_or_else(|_| "ic-icrc1-ledger.wasm.gz".to_string());
+    std::fs::read(&path).expect("Download ic-icrc1-ledger.wasm.gz or set ICRC1_LEDGER_WASM")
+}
+
+/// An ICRC-2 ledger charging `FEE`, with 1,000,000 units minted to `holder`
+pub fn install_ledger(ic: &PocketIc, wasm: &[u8], symbol: &str, holder: Principal) -> Principal {
+    let ledger = ic.create_canister();
+    ic.add_cycles(ledger, 10_000_000_000_000u128);
+
+    let args = LedgerArg::Init(InitArgs {
+        minting_account: account(Principal::management_canister()),
+        transfer_fee: Nat::from(FEE),
+        token_symbol: symbol.to_string(),
+        token_name: symbol.to_string(),
+        metadata: vec![],
+        initial_balances: vec![(account(holder), Nat::from(1_000_000u64))],
+        feature_flags: Some(FeatureFlags { icrc2: true }),
+        archive_options: ArchiveOptions {
+            num_blocks_to_archive: 1000,
+            trigger_threshold: 2000,
+            controller_id: Principal::anonymous(),
+        },
+    });
+
+    ic.install_canister(ledger, wasm.to_vec(), Encode!(&args).unwrap(), None);
+    ledger
+}
+
+pub fn balance_of(ic: &PocketIc, ledger: Principal, owner: Principal) -> Nat {
+    let resp = ic.query_call(ledger, owner, "icrc1_balance_of", Encode!(&account(owner)).unwrap()).unwrap();
+    Decode!(&resp, Nat).unwrap()
+}
+
+pub fn approve(ic: &PocketIc, ledger: Principal, owner: Principal, spender: Principal, amount: u64) {
+    let args = ApproveArgs { spender: account(spender), amount: Nat::from(amount) };
+    ic.update_call(ledger, owner, "icrc2_approve", Encode!(&args).unwrap()).unwrap();
+}
+
+// ============ MINIMAL BACKEND TYPES ============
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub enum EscrowBackend {
+    Demo,
+    Ledger,
+    Accounts,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LedgerConfig {
+    pub ledger_id: Principal,
+    pub fee: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct EscrowConfig {
+    pub backend: EscrowBackend,
+    pub ledgers: Vec<(Asset, LedgerConfig)>,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+pub struct TradingRules {
+    pub tick_size: u64,
+    pub lot_size: u64,
+    pub min_size: u64,
+    pub max_price_deviation_bps: u32,
+}
+
+impl TradingRules {
+    /// Toy books trade whole units, below the default dust limits
+    pub fn whole_units() -> Self {
+        TradingRules { tick_size: 1, lot_size: 1, min_size: 1, max_price_deviation_bps: 0 }
+    }
+}
+
+// Order payload format version 1: version byte + Candid record
+#[derive(CandidType)]
+pub struct OrderPayload {
+    pub market_id: u32,
+    pub round_id: u64,
+    pub owner: Principal,
+    pub side: OrderType,
+    pub amount: u64,
+    pub price_limit: u64,
+    pub nonce: Vec<u8>,
+}
+
+impl OrderPayload {
+    pub fn new(owner: Principal, round_id: u64, side: OrderType, amount: u64, price_limit: u64) -> Self {
+        OrderPayload {
+            market_id: BTC_USD,
+            round_id,
+            owner,
+            side,
+            amount,
+            price_limit,
+            nonce: owner.as_slice()[..16].to_vec(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![1u8];
+        payload.extend(Encode!(self).unwrap());
+        payload
+    }
+
+    /// Collateral that covers the order exactly, for toy books priced in
+    /// whole units
+    pub fn exact_collateral(&self) -> u64 {
+        match self.side {
+            OrderType::Buy => self.amount * self.price_limit,
+            OrderType::Sell => self.amount,
+        }
+    }
+}
+
+pub fn commitment(payload: &[u8]) -> String {
+    hex::encode(Sha256::digest(payload))
+}
+
+pub fn set_trading_rules(ic: &PocketIc, backend: Principal, rules: &TradingRules) {
+    let resp = ic.update_call(
+        backend,
+        Principal::anonymous(),
+        "admin_set_trading_rules",
+        Encode!(&BTC_USD, rules).unwrap(),
+    ).unwrap();
+    Decode!(&resp, Result<(), candid::Reserved>).unwrap().expect("admin_set_trading_rules failed");
+}
+
+/// Arguments for `submit_order` carrying `order` in the clear
+pub fn submit_args(order: &OrderPayload, collateral: u64) -> Vec<u8> {
+    let payload = order.encode();
+    Encode!(&order.market_id, &order.side, &collateral, &payload, &commitment(&payload)).unwrap()
+}
+
+/// Submits `order` as its owner; Err carries the VeilError
+pub fn try_submit(ic: &PocketIc, backend: Principal, order: &OrderPayload, collateral: u64) -> Result<u64, candid::Reserved> {
+    let resp = ic.update_call(backend, order.owner, "submit_order", submit_args(order, collateral)).unwrap();
+    Decode!(&resp, Result<u64, candid::Reserved>).unwrap()
+}
+
+pub fn submit(ic: &PocketIc, backend: Principal, order: &OrderPayload, collateral: u64) -> u64 {
+    try_submit(ic, backend, order, collateral).expect("submit_order failed")
+}
+
+/// Submits `order` locking exactly what it needs
+pub fn submit_exact(ic: &PocketIc, backend: Principal, order: &OrderPayload) -> u64 {
+    submit(ic, backend, order, order.exact_collateral())
+}
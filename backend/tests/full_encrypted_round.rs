@@ -39,15 +39,15 @@ fn full_vetkd_encrypted_round() {
 
     // ============ NOW REAL CALL WORKS ============
 
-    let resp = ic.query_call(
+    let resp = ic.update_call(
         backend_id,
         sender,
         "get_encryption_public_key",
         Encode!().unwrap(),
     ).unwrap();
 
-    let res: Result<Vec<u8>, String> =
-        Decode!(&resp, Result<Vec<u8>, String>).unwrap();
+    let res: Result<Vec<u8>, candid::Reserved> =
+        Decode!(&resp, Result<Vec<u8>, candid::Reserved>).unwrap();
 
     let pk = res.unwrap();
     assert!(!pk.is_empty());
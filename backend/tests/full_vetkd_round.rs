@@ -1,93 +1,190 @@
-use pocket_ic::PocketIc;
-use candid::{Encode, Decode, Principal};
-use sha2::{Sha256, Digest};
-use aes_gcm::{Aes256Gcm, Key, Nonce};
-use aes_gcm::aead::{Aead, KeyInit};
-
-fn sha256(data: &[u8]) -> String {
-    let mut h = Sha256::new();
-    h.update(data);
-    hex::encode(h.finalize())
+mod common;
+
+use pocket_ic::{PocketIc, PocketIcBuilder};
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_vetkeys::{DerivedPublicKey, IbeCiphertext, IbeIdentity, IbeSeed};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use common::*;
+
+// Built WITHOUT `--features demo`, so payloads must be real IBE ciphertexts:
+//   cargo build --target wasm32-unknown-unknown --release --target-dir target/vetkd
+const BACKEND_WASM: &str = "target/vetkd/wasm32-unknown-unknown/release/mempool_chess_backend.wasm";
+const VETKD_WASM: &str = "vetkeys_engine/target/wasm32-unknown-unknown/release/vetkeys_engine.wasm";
+
+#[derive(CandidType, Deserialize)]
+struct EngineConfig {
+    backend: Option<Principal>,
+    key_name: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct OrderMatch {
+    order_id: u64,
+    fill_amount: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ClearingResult {
+    clearing_price: u64,
+    total_volume: u64,
+    matches: Vec<OrderMatch>,
+}
+
+/// Must match the backend's `encryption::generate_timelock_identity`
+fn round_identity(market_id: u32, round_id: u64) -> Vec<u8> {
+    let mut identity = b"ROUND:".to_vec();
+    identity.extend_from_slice(&market_id.to_be_bytes());
+    identity.extend_from_slice(&round_id.to_be_bytes());
+    identity
+}
+
+struct Veil {
+    ic: PocketIc,
+    backend: Principal,
+    public_key: DerivedPublicKey,
+}
+
+/// Install the vetKD engine and the backend on a local IC holding the vetKD
+/// test keys, connect them, open a BTC/USD round and fetch the public key
+/// orders are encrypted to
+fn deploy() -> Veil {
+    let ic = PocketIcBuilder::new()
+        .with_application_subnet()
+        .with_ii_subnet()
+        .build();
+
+    let backend_wasm = std::fs::read(BACKEND_WASM)
+        .expect("Build the backend without the demo feature into target/vetkd first");
+    let vetkd_wasm = std::fs::read(VETKD_WASM).expect("Build vetkeys_engine first");
+
+    let backend = ic.create_canister();
+    ic.add_cycles(backend, 100_000_000_000_000);
+    ic.install_canister(backend, backend_wasm, vec![], None);
+
+    let engine = ic.create_canister();
+    ic.add_cycles(engine, 100_000_000_000_000);
+    let config = Some(EngineConfig {
+        backend: Some(backend),
+        key_name: Some("test_key_1".to_string()),
+    });
+    ic.install_canister(engine, vetkd_wasm, Encode!(&config).unwrap(), None);
+
+    let admin = Principal::anonymous();
+    let resp = ic.update_call(backend, admin, "set_vetkd_canister", Encode!(&engine).unwrap()).unwrap();
+    Decode!(&resp, Result<(), candid::Reserved>).unwrap().expect("set_vetkd_canister failed");
+    let resp = ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    Decode!(&resp, Result<candid::Reserved, candid::Reserved>).unwrap().expect("admin_start_round failed");
+
+    let resp = ic.update_call(backend, admin, "get_encryption_public_key", Encode!().unwrap()).unwrap();
+    let bytes = Decode!(&resp, Result<Vec<u8>, candid::Reserved>).unwrap().expect("no public key");
+    let public_key = DerivedPublicKey::deserialize(&bytes).expect("valid derived public key");
+
+    Veil { ic, backend, public_key }
+}
+
+/// Encrypt an order to round 1's identity; returns the ciphertext and the
+/// commitment to its plaintext
+fn seal(veil: &Veil, user: Principal, side: OrderType, amount: u64, price_limit: u64) -> (Vec<u8>, String) {
+    let plaintext = OrderPayload::new(user, 1, side, amount, price_limit).encode();
+
+    let ciphertext = IbeCiphertext::encrypt(
+        &veil.public_key,
+        &IbeIdentity::from_bytes(&round_identity(BTC_USD, 1)),
+        &plaintext,
+        &IbeSeed::from_bytes(&Sha256::digest(user.as_slice())).unwrap(),
+    );
+    (ciphertext.serialize(), commitment(&plaintext))
+}
+
+fn submit(veil: &Veil, user: Principal, side: OrderType, collateral: u64, sealed: (Vec<u8>, String)) -> u64 {
+    let (ciphertext, commitment) = sealed;
+    let args = Encode!(&BTC_USD, &side, &collateral, &ciphertext, &commitment).unwrap();
+    let resp = veil.ic.update_call(veil.backend, user, "submit_order", args).unwrap();
+    Decode!(&resp, Result<u64, candid::Reserved>).unwrap().expect("submit_order failed")
+}
+
+fn run_clearing(veil: &Veil) -> ClearingResult {
+    let resp = veil.ic
+        .update_call(veil.backend, Principal::anonymous(), "admin_run_clearing", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+    Decode!(&resp, Result<ClearingResult, candid::Reserved>).unwrap().expect("clearing failed")
+}
+
+#[test]
+fn ibe_encrypted_orders_reveal_and_clear() {
+    let veil = deploy();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+
+    // ============ SUBMIT: sealed to the round identity ============
+
+    // Buy 0.01 BTC up to $50,000 and sell 0.01 BTC from $40,000
+    let buy_order = seal(&veil, buyer, OrderType::Buy, 1_000_000, 5_000_000);
+    let sell_order = seal(&veil, seller, OrderType::Sell, 1_000_000, 4_000_000);
+    let buy = submit(&veil, buyer, OrderType::Buy, 50_000, buy_order);
+    let sell = submit(&veil, seller, OrderType::Sell, 1_000_000, sell_order);
+
+    // ============ CLEAR: the backend opens them with the round key ============
+
+    let result = run_clearing(&veil);
+    assert_eq!(result.clearing_price, 4_500_000);
+    assert_eq!(result.total_volume, 1_000_000);
+
+    let fill_of = |order_id: u64| result.matches.iter().find(|m| m.order_id == order_id).map(|m| m.fill_amount);
+    assert_eq!(fill_of(buy), Some(1_000_000));
+    assert_eq!(fill_of(sell), Some(1_000_000));
+
+    println!("✅ IBE-encrypted round revealed and cleared");
+}
+
+// Only the rejections this test expects
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum VeilError {
+    DecryptionFailed { order_id: u64 },
+    CommitmentMismatch { order_id: u64 },
+}
+
+fn rejection(veil: &Veil, order_id: u64) -> Option<VeilError> {
+    let resp = veil.ic
+        .query_call(veil.backend, Principal::anonymous(), "get_order_rejection", Encode!(&order_id).unwrap())
+        .unwrap();
+    Decode!(&resp, Option<VeilError>).unwrap()
 }
 
 #[test]
-fn full_vetkd_round() {
-    let ic = PocketIc::new();
-
-    // ---------- Deploy VetKeys Engine ----------
-    let vetkeys_wasm = std::fs::read(
-        "vetkeys_engine/target/wasm32-unknown-unknown/release/vetkeys_engine.wasm"
-    ).unwrap();
-
-    let vetkeys_id = ic.create_canister();
-    ic.install_canister(vetkeys_id, vetkeys_wasm, vec![], None);
-
-    // ---------- Deploy Backend ----------
-    let backend_wasm = std::fs::read(
-        "target/wasm32-unknown-unknown/release/mempool_chess_backend.wasm"
-    ).unwrap();
-
-    let backend_id = ic.create_canister();
-    ic.install_canister(backend_id, backend_wasm, vec![], None);
-
-    // ---------- Inject VetKeys ID ----------
-    let inject_args = Encode!(&Principal::from_slice(vetkeys_id.as_slice())).unwrap();
-
-    ic.update_call(
-        backend_id,
-        Principal::anonymous(),
-        "set_vetkeys_engine_id",
-        inject_args,
-    ).unwrap();
-
-    // ---------- 1) Fetch public key ----------
-    let resp = ic.query_call(
-        backend_id,
-        Principal::anonymous(),
-        "get_encryption_public_key",
-        Encode!().unwrap(),
-    ).unwrap();
-
-    let pk: Vec<u8> = Decode!(&resp, Vec<u8>).unwrap();
-    assert!(!pk.is_empty());
-
-    // ---------- 2) Derive AES key ----------
-    let key_hash = sha256(&pk);
-    let key_bytes = hex::decode(key_hash).unwrap();
-    let aes_key = Key::<Aes256Gcm>::from_slice(&key_bytes[..32]);
-    let cipher = Aes256Gcm::new(aes_key);
-
-    // ---------- 3) Encrypt order ----------
-    let plaintext = br#"{"order":"BUY"}"#;
-    let nonce_bytes = [0u8; 12];
-    let nonce = Nonce::from_slice(&nonce_bytes);
-
-    let encrypted = cipher.encrypt(nonce, plaintext.as_ref()).unwrap();
-    let commitment = sha256(plaintext);
-
-    // ---------- 4) Submit order ----------
-    let args = Encode!(&1u64, &encrypted, &commitment).unwrap();
-
-    ic.update_call(
-        backend_id,
-        Principal::anonymous(),
-        "pocketic_submit_order",
-        args,
-    ).unwrap();
-
-    // ---------- 5) Read stored ciphertext ----------
-    let stored_raw = ic.query_call(
-        backend_id,
-        Principal::anonymous(),
-        "pocketic_get_order_ciphertext",
-        Encode!().unwrap(),
-    ).unwrap();
-
-    let stored: Vec<u8> = Decode!(&stored_raw, Vec<u8>).unwrap();
-
-    // ---------- 6) Decrypt ----------
-    let decrypted = cipher.decrypt(nonce, stored.as_slice()).unwrap();
-    assert_eq!(&decrypted, plaintext);
-
-    println!("✅ Full PocketIC VetKD flow passed");
+fn tampered_orders_are_rejected_at_reveal() {
+    let veil = deploy();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+    let forger = Principal::from_slice(&[3; 29]);
+    let liar = Principal::from_slice(&[4; 29]);
+
+    let buy_order = seal(&veil, buyer, OrderType::Buy, 1_000_000, 5_000_000);
+    let sell_order = seal(&veil, seller, OrderType::Sell, 1_000_000, 4_000_000);
+    let buy = submit(&veil, buyer, OrderType::Buy, 50_000, buy_order);
+    let sell = submit(&veil, seller, OrderType::Sell, 1_000_000, sell_order);
+
+    // A ciphertext altered after encryption no longer opens
+    let (mut ciphertext, commitment) = seal(&veil, forger, OrderType::Buy, 1_000_000, 5_000_000);
+    *ciphertext.last_mut().unwrap() ^= 1;
+    let forged = submit(&veil, forger, OrderType::Buy, 50_000, (ciphertext, commitment));
+
+    // A commitment to a different order than the one encrypted
+    let (ciphertext, _) = seal(&veil, liar, OrderType::Sell, 1_000_000, 4_000_000);
+    let (_, other_commitment) = seal(&veil, liar, OrderType::Sell, 1_000_000, 1);
+    let lied = submit(&veil, liar, OrderType::Sell, 1_000_000, (ciphertext, other_commitment));
+
+    // The honest orders still clear between themselves
+    let result = run_clearing(&veil);
+    assert_eq!(result.total_volume, 1_000_000);
+    let mut filled: Vec<u64> = result.matches.iter().map(|m| m.order_id).collect();
+    filled.sort();
+    assert_eq!(filled, vec![buy, sell]);
+
+    assert_eq!(rejection(&veil, forged), Some(VeilError::DecryptionFailed { order_id: forged }));
+    assert_eq!(rejection(&veil, lied), Some(VeilError::CommitmentMismatch { order_id: lied }));
+    assert_eq!(rejection(&veil, buy), None);
+
+    println!("✅ Tampered ciphertext and commitment rejected");
 }
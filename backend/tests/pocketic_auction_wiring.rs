@@ -0,0 +1,89 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{Decode, Encode, Principal};
+use veil_core::types::{ClearingResult, Order, OrderStatus, PriceRule};
+use veil_core::{find_clearing_price_and_match, ClearingParams};
+use common::*;
+
+// The clearing rules themselves are property-tested against a brute-force
+// reference in veil_core/tests/clearing.rs. This test only checks that the
+// canister feeds the revealed orders and the market's parameters through to
+// them unchanged.
+
+const BTC_DECIMALS: u8 = 8;
+
+// Two buys and two sells whose best prices form a tied range, so the price
+// rule and a partial fill both come into play
+const BOOK: [(OrderType, u64, u64); 4] = [
+    (OrderType::Buy, 10, 105),
+    (OrderType::Buy, 6, 100),
+    (OrderType::Sell, 8, 95),
+    (OrderType::Sell, 5, 101),
+];
+
+
+fn host_order(id: u64, order_type: OrderType, amount: u64, price_limit: u64) -> Order {
+    Order {
+        id,
+        market_id: BTC_USD,
+        round_id: 1,
+        owner: Principal::anonymous(),
+        order_type,
+        collateral: 0,
+        amount,
+        price_limit,
+        created_at: 0,
+        encrypted_payload: Vec::new(),
+        commitment_hash: String::new(),
+        status: OrderStatus::Open,
+        revision: 0,
+        updated_at: 0,
+        escrow_nonce: 0,
+    }
+}
+
+#[test]
+fn canister_clears_like_the_auction_crate() {
+    let ic = PocketIc::new();
+
+    let backend = install_backend(&ic);
+
+    let admin = Principal::anonymous();
+
+    set_trading_rules(&ic, backend, &TradingRules::whole_units());
+    let resp = ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    let round_id = Decode!(&resp, Result<u64, candid::Reserved>).unwrap().unwrap();
+
+    let mut orders = Vec::new();
+    for (i, (side, amount, price_limit)) in BOOK.into_iter().enumerate() {
+        let owner = Principal::from_slice(&[i as u8 + 1; 29]);
+        let id = submit_exact(&ic, backend, &OrderPayload::new(owner, round_id, side.clone(), amount, price_limit));
+        orders.push(host_order(id, side, amount, price_limit));
+    }
+
+    let resp = ic.update_call(backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap()).unwrap();
+    let result = Decode!(&resp, Result<ClearingResult, candid::Reserved>).unwrap().expect("round clears");
+
+    let params = ClearingParams {
+        market_id: BTC_USD,
+        round_id,
+        rule: PriceRule::default(),
+        tick_size: 1,
+        base_decimals: BTC_DECIMALS,
+        reference_price: None,
+        timestamp: result.timestamp,
+    };
+    let expected = find_clearing_price_and_match(orders, &params, |_| {}).expect("book clears on the host");
+
+    assert_eq!(result.clearing_price, expected.clearing_price);
+    assert_eq!(result.total_volume, expected.total_volume);
+    let selection = |r: &ClearingResult| {
+        r.price_selection.as_ref().map(|s| (s.rule, s.reference_price, s.imbalance, s.range_low, s.range_high))
+    };
+    assert_eq!(selection(&result), selection(&expected));
+    let fills = |r: &ClearingResult| r.matches.iter().map(|m| (m.order_id, m.fill_amount)).collect::<Vec<_>>();
+    assert_eq!(fills(&result), fills(&expected));
+
+    println!("✅ Canister cleared like veil_core");
+}
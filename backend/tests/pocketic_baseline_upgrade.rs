@@ -0,0 +1,152 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::Deserialize;
+use common::*;
+
+// The single-market release this repo started from, also built with the
+// demo feature:
+//   git worktree add /tmp/veil-baseline 1fd0073
+//   cargo build --manifest-path /tmp/veil-baseline/backend/Cargo.toml \
+//     --target wasm32-unknown-unknown --release --features demo --target-dir target/baseline
+const BASELINE_WASM: &str = "target/baseline/wasm32-unknown-unknown/release/mempool_chess_backend.wasm";
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(CandidType)]
+enum BaselineAsset {
+    BTC,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct BaselineResult {
+    total_volume: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum RoundState {
+    Pending,
+    Active,
+    Revealing,
+    Clearing,
+    Executing,
+    Completed,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct MarketState {
+    round_id: u64,
+    round_state: RoundState,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ClearingResult {
+    round_id: u64,
+    total_volume: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct AuditEntry {
+    action: String,
+    detail: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct AuditPage {
+    entries: Vec<AuditEntry>,
+}
+
+fn baseline_call(ic: &PocketIc, backend: Principal, method: &str) -> String {
+    let resp = ic.update_call(backend, Principal::anonymous(), method, Encode!().unwrap()).unwrap();
+    Decode!(&resp, String).unwrap()
+}
+
+fn baseline_submit(ic: &PocketIc, backend: Principal, user: Principal, side: OrderType, amount: u64, price: u64) {
+    let args = Encode!(&side, &BaselineAsset::BTC, &amount, &price, &vec![1u8], &String::new()).unwrap();
+    let resp = ic.update_call(backend, user, "submit_order", args).unwrap();
+    Decode!(&resp, Result<u64, String>).unwrap().expect("baseline submit_order failed");
+}
+
+fn order_count(ic: &PocketIc, backend: Principal) -> u64 {
+    let resp = ic.query_call(backend, Principal::anonymous(), "get_order_count", Encode!().unwrap()).unwrap();
+    Decode!(&resp, u64).unwrap()
+}
+
+fn round_result(ic: &PocketIc, backend: Principal, round_id: u64) -> Option<ClearingResult> {
+    let resp = ic.query_call(
+        backend,
+        Principal::anonymous(),
+        "get_round_result",
+        Encode!(&BTC_USD, &round_id).unwrap(),
+    ).unwrap();
+    Decode!(&resp, Option<ClearingResult>).unwrap()
+}
+
+#[test]
+fn upgrade_from_baseline_starts_afresh() {
+    let ic = PocketIc::new();
+
+    let baseline = std::fs::read(BASELINE_WASM).expect("Build the baseline release into target/baseline first");
+    let backend = ic.create_canister();
+    ic.add_cycles(backend, 10_000_000_000_000u128);
+    ic.install_canister(backend, baseline, vec![], None);
+
+    let admin = Principal::anonymous();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+
+    // ============ BASELINE ROUNDS ============
+
+    // A cleared round, then an open one holding an order
+    baseline_call(&ic, backend, "admin_start_round");
+    baseline_submit(&ic, backend, buyer, OrderType::Buy, 10, 50);
+    baseline_submit(&ic, backend, seller, OrderType::Sell, 10, 40);
+    baseline_call(&ic, backend, "admin_run_clearing");
+    baseline_call(&ic, backend, "admin_reset_round");
+    baseline_call(&ic, backend, "admin_start_round");
+    baseline_submit(&ic, backend, buyer, OrderType::Buy, 5, 50);
+
+    assert_eq!(order_count(&ic, backend), 3);
+    let resp = ic.query_call(backend, admin, "get_round_result", Encode!(&1u64).unwrap()).unwrap();
+    assert!(Decode!(&resp, Option<BaselineResult>).unwrap().is_some_and(|r| r.total_volume > 0));
+
+    // ============ UPGRADE ============
+
+    let wasm = backend_wasm();
+    ic.upgrade_canister(backend, wasm.clone(), Encode!().unwrap(), None)
+        .expect("upgrade from the baseline failed");
+
+    // The baseline's orders and results are gone, as after a reinstall
+    assert_eq!(order_count(&ic, backend), 0);
+    assert!(round_result(&ic, backend, 1).is_none());
+    let resp = ic.query_call(backend, admin, "get_round_state", Encode!(&BTC_USD).unwrap()).unwrap();
+    let market = Decode!(&resp, Option<MarketState>).unwrap().unwrap();
+    assert_eq!((market.round_id, market.round_state), (0, RoundState::Pending));
+
+    let resp = ic.query_call(backend, admin, "get_audit_log", Encode!(&None::<u64>, &10u32).unwrap()).unwrap();
+    let log = Decode!(&resp, Result<AuditPage, candid::Reserved>).unwrap().unwrap();
+    assert_eq!(log.entries[0].action, "post_upgrade");
+    assert_eq!(log.entries[0].detail, "discarded 3 orders and 1 results of the single-market release");
+
+    // ============ FIRST ROUND AFTER ============
+
+    set_trading_rules(&ic, backend, &TradingRules::whole_units());
+    let resp = ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    assert_eq!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().ok(), Some(1));
+
+    let buy = submit_exact(&ic, backend, &OrderPayload::new(buyer, 1, OrderType::Buy, 10, 50));
+    let sell = submit_exact(&ic, backend, &OrderPayload::new(seller, 1, OrderType::Sell, 10, 40));
+    assert_eq!((buy, sell), (0, 1));
+
+    let resp = ic.update_call(backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap()).unwrap();
+    Decode!(&resp, Result<candid::Reserved, candid::Reserved>).unwrap().expect("round clears");
+    let result = round_result(&ic, backend, 1).expect("result stored");
+    assert_eq!((result.round_id, result.total_volume), (1, 10));
+
+    // Later upgrades keep everything
+    ic.upgrade_canister(backend, wasm, Encode!().unwrap(), None).unwrap();
+    assert_eq!(order_count(&ic, backend), 2);
+    assert!(round_result(&ic, backend, 1).is_some());
+
+    println!("✅ Baseline upgrade started afresh");
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::Deserialize;
+use common::*;
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+enum PriceRule {
+    Midpoint,
+    ClosestToReference,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct PriceSelection {
+    rule: PriceRule,
+    reference_price: Option<u64>,
+    imbalance: u64,
+    range_low: u64,
+    range_high: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ClearingResult {
+    clearing_price: u64,
+    total_volume: u64,
+    price_selection: Option<PriceSelection>,
+}
+
+
+fn run_round(ic: &PocketIc, backend: Principal, admin: Principal, buy_price: u64, sell_price: u64) -> ClearingResult {
+    let resp = ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    let round_id = Decode!(&resp, Result<u64, candid::Reserved>).unwrap().unwrap();
+
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+    submit_exact(ic, backend, &OrderPayload::new(buyer, round_id, OrderType::Buy, 10, buy_price));
+    submit_exact(ic, backend, &OrderPayload::new(seller, round_id, OrderType::Sell, 10, sell_price));
+
+    let resp = ic.update_call(backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap()).unwrap();
+    let result = Decode!(&resp, Result<ClearingResult, candid::Reserved>).unwrap().unwrap();
+
+    let resp = ic.update_call(backend, admin, "admin_reset_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    Decode!(&resp, Result<(), candid::Reserved>).unwrap().unwrap();
+    result
+}
+
+#[test]
+fn clearing_price_breaks_ties_by_rule() {
+    let ic = PocketIc::new();
+
+    let backend = install_backend(&ic);
+
+    let admin = Principal::anonymous();
+
+    set_trading_rules(&ic, backend, &TradingRules::whole_units());
+
+    // ============ MIDPOINT ============
+
+    // Every price from 40 to 50 matches all 10 units with no imbalance
+    let result = run_round(&ic, backend, admin, 50, 40);
+    assert_eq!(result.total_volume, 10);
+    assert_eq!(result.clearing_price, 45);
+    let selection = result.price_selection.unwrap();
+    assert_eq!(selection.rule, PriceRule::Midpoint);
+    assert_eq!((selection.range_low, selection.range_high), (40, 50));
+    assert_eq!(selection.imbalance, 0);
+    assert_eq!(selection.reference_price, None);
+
+    // ============ REFERENCE PRICE ============
+
+    let resp = ic.update_call(
+        backend,
+        admin,
+        "admin_set_price_rule",
+        Encode!(&BTC_USD, &PriceRule::ClosestToReference).unwrap(),
+    ).unwrap();
+    Decode!(&resp, Result<(), candid::Reserved>).unwrap().unwrap();
+
+    // The range 30..=70 holds the last clearing price, 45
+    let result = run_round(&ic, backend, admin, 70, 30);
+    assert_eq!(result.clearing_price, 45);
+    let selection = result.price_selection.unwrap();
+    assert_eq!(selection.rule, PriceRule::ClosestToReference);
+    assert_eq!(selection.reference_price, Some(45));
+
+    // Outside the range, the nearest end wins
+    let result = run_round(&ic, backend, admin, 80, 60);
+    assert_eq!(result.clearing_price, 60);
+
+    println!("✅ Clearing price ties broken by rule");
+}
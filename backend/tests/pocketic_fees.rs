@@ -0,0 +1,165 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::Deserialize;
+use common::*;
+
+#[derive(CandidType, Deserialize, Debug)]
+struct OrderMatch {
+    order_id: u64,
+    fill_amount: u64,
+    fee: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ClearingResult {
+    matches: Vec<OrderMatch>,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct OrderSettlement {
+    received_asset: Asset,
+    received: u64,
+    fee: u64,
+}
+
+// Only the rejection this test expects
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum VeilError {
+    InsufficientTreasury { asset: Asset, requested: u64, available: u64 },
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct TreasuryBalance {
+    backend: EscrowBackend,
+    asset: Asset,
+    collected: u128,
+    withdrawn: u128,
+    balance: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct Reconciliation {
+    balanced: bool,
+}
+
+// 0.01 BTC clearing at $45,000
+const AMOUNT: u64 = 1_000_000;
+const COST: u64 = 45_000;
+const FEE_BPS: u32 = 30;
+
+fn place(ic: &PocketIc, backend: Principal, user: Principal, side: OrderType) -> u64 {
+    let (price_limit, collateral) = match side {
+        OrderType::Buy => (5_000_000, 50_000),
+        OrderType::Sell => (4_000_000, AMOUNT),
+    };
+    submit(ic, backend, &OrderPayload::new(user, 1, side, AMOUNT, price_limit), collateral)
+}
+
+fn update(ic: &PocketIc, backend: Principal, caller: Principal, method: &str, args: Vec<u8>) -> Result<candid::Reserved, candid::Reserved> {
+    let resp = ic.update_call(backend, caller, method, args).unwrap();
+    Decode!(&resp, Result<candid::Reserved, candid::Reserved>).unwrap()
+}
+
+fn fee_rate(ic: &PocketIc, backend: Principal, owner: Principal) -> u32 {
+    let resp = ic.query_call(backend, owner, "get_fee_rate", Encode!(&BTC_USD, &owner).unwrap()).unwrap();
+    Decode!(&resp, u32).unwrap()
+}
+
+fn order_settlement(ic: &PocketIc, backend: Principal, order_id: u64) -> OrderSettlement {
+    let resp = ic.query_call(backend, Principal::anonymous(), "get_order_settlement", Encode!(&order_id).unwrap())
+        .unwrap();
+    Decode!(&resp, Option<OrderSettlement>).unwrap().expect("order not settled")
+}
+
+#[test]
+fn fees_are_withheld_at_settlement_and_collected() {
+    let ic = PocketIc::new();
+
+    let backend = install_backend(&ic);
+
+    let admin = Principal::anonymous();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+
+    // ============ SCHEDULE ============
+
+    // Only admins set fees, and never above 100%
+    assert!(update(&ic, backend, buyer, "admin_set_fee_rate", Encode!(&BTC_USD, &FEE_BPS).unwrap()).is_err());
+    assert!(update(&ic, backend, admin, "admin_set_fee_rate", Encode!(&BTC_USD, &10_001u32).unwrap()).is_err());
+    update(&ic, backend, admin, "admin_set_fee_rate", Encode!(&BTC_USD, &FEE_BPS).unwrap()).unwrap();
+
+    // The seller pays half the rate
+    update(&ic, backend, admin, "admin_set_fee_discount", Encode!(&seller, &Some(5_000u32)).unwrap()).unwrap();
+    assert_eq!(fee_rate(&ic, backend, buyer), 30);
+    assert_eq!(fee_rate(&ic, backend, seller), 15);
+
+    // ============ ROUND ============
+
+    update(&ic, backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    let buy = place(&ic, backend, buyer, OrderType::Buy);
+    let sell = place(&ic, backend, seller, OrderType::Sell);
+    update(&ic, backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap()).expect("clearing failed");
+
+    // 30 bps of 0.01 BTC, and 15 bps of $450.00 rounded down
+    let (buyer_fee, seller_fee) = (3_000, 67);
+    assert_eq!(
+        order_settlement(&ic, backend, buy),
+        OrderSettlement { received_asset: Asset::BTC, received: AMOUNT - buyer_fee, fee: buyer_fee }
+    );
+    assert_eq!(
+        order_settlement(&ic, backend, sell),
+        OrderSettlement { received_asset: Asset::USD, received: COST - seller_fee, fee: seller_fee }
+    );
+
+    // Each match records its fee
+    let resp = ic.query_call(backend, admin, "get_round_result", Encode!(&BTC_USD, &1u64).unwrap()).unwrap();
+    let result = Decode!(&resp, Option<ClearingResult>).unwrap().expect("no result");
+    let fee_of = |order_id: u64| result.matches.iter().find(|m| m.order_id == order_id).map(|m| m.fee);
+    assert_eq!(fee_of(buy), Some(buyer_fee));
+    assert_eq!(fee_of(sell), Some(seller_fee));
+
+    // ============ TREASURY ============
+
+    let resp = ic.query_call(backend, admin, "get_treasury", Encode!().unwrap()).unwrap();
+    let treasury = Decode!(&resp, Vec<TreasuryBalance>).unwrap();
+    assert_eq!(
+        treasury,
+        vec![
+            TreasuryBalance { backend: EscrowBackend::Demo, asset: Asset::BTC, collected: 3_000, withdrawn: 0, balance: 3_000 },
+            TreasuryBalance { backend: EscrowBackend::Demo, asset: Asset::USD, collected: 67, withdrawn: 0, balance: 67 },
+        ]
+    );
+
+    let resp = ic.query_call(backend, admin, "reconcile", Encode!().unwrap()).unwrap();
+    assert!(Decode!(&resp, Reconciliation).unwrap().balanced);
+
+    // Only admins withdraw fees, and demo fees are not backed by tokens
+    let to = Account { owner: buyer, subaccount: None };
+    let args = Encode!(&Asset::BTC, &1_000u64, &to).unwrap();
+    let resp = ic.update_call(backend, buyer, "admin_withdraw_fees", args.clone()).unwrap();
+    assert!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().is_err());
+    let resp = ic.update_call(backend, admin, "admin_withdraw_fees", args.clone()).unwrap();
+    assert!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().is_err());
+
+    // Nor do they become withdrawable once the canister holds real tokens
+    let config = EscrowConfig {
+        backend: EscrowBackend::Accounts,
+        ledgers: vec![(Asset::BTC, LedgerConfig { ledger_id: Principal::management_canister(), fee: 10 })],
+    };
+    update(&ic, backend, admin, "admin_configure_escrow", Encode!(&config).unwrap()).expect("configure failed");
+    let resp = ic.update_call(backend, admin, "admin_withdraw_fees", args).unwrap();
+    assert_eq!(
+        Decode!(&resp, Result<u64, VeilError>).unwrap(),
+        Err(VeilError::InsufficientTreasury { asset: Asset::BTC, requested: 1_000, available: 0 })
+    );
+
+    // Refused withdrawals leave nothing pending
+    let resp = ic.query_call(backend, buyer, "get_treasury_withdrawals", Encode!().unwrap()).unwrap();
+    assert!(Decode!(&resp, Result<Vec<candid::Reserved>, candid::Reserved>).unwrap().is_err());
+    let resp = ic.query_call(backend, admin, "get_treasury_withdrawals", Encode!().unwrap()).unwrap();
+    assert_eq!(Decode!(&resp, Result<Vec<candid::Reserved>, VeilError>).unwrap().map(|w| w.len()), Ok(0));
+
+    println!("✅ Fees withheld, recorded and collected");
+}
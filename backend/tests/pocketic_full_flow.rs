@@ -27,14 +27,14 @@ fn pocketic_full_encrypted_flow() {
     let sender = Principal::anonymous();
 
     // === Call backend: get encryption public key (demo mode)
-    let resp = ic.query_call(
+    let resp = ic.update_call(
         canister,
         sender,
         "get_encryption_public_key",
         Encode!().unwrap()
     ).unwrap();
 
-    let pk: Vec<u8> = Decode!(&resp, Vec<u8>).unwrap();
+    let pk = Decode!(&resp, Result<Vec<u8>, candid::Reserved>).unwrap().unwrap();
 
     assert!(!pk.is_empty());
 
@@ -80,7 +80,7 @@ fn pocketic_full_encrypted_flow() {
         Encode!().unwrap()
     ).unwrap();
 
-    let stored: Vec<u8> = Decode!(&stored_raw, Vec<u8>).unwrap();
+    let stored = Decode!(&stored_raw, Result<Vec<u8>, candid::Reserved>).unwrap().unwrap();
 
     // === Decrypt
     let decrypted = cipher.decrypt(nonce, stored.as_slice()).unwrap();
@@ -0,0 +1,252 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Int, Nat, Principal};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use common::*;
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+enum JournalAccount {
+    External,
+    Free(Principal),
+    Locked(Principal),
+    Clearing(u32),
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+enum JournalReason {
+    OpeningBalance,
+    Deposit,
+    Lock,
+    Release,
+    FillDebit,
+    FillCredit,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct JournalEntry {
+    id: u64,
+    posting: u64,
+    order_id: Option<u64>,
+    account: JournalAccount,
+    asset: Asset,
+    delta: Int,
+    reason: JournalReason,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct JournalPage {
+    entries: Vec<JournalEntry>,
+    next_cursor: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct AssetReconciliation {
+    asset: Asset,
+    deposits: Nat,
+    withdrawals: Nat,
+    free: Nat,
+    locked: Nat,
+    clearing: Int,
+    balanced: bool,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct Reconciliation {
+    assets: Vec<AssetReconciliation>,
+    balanced: bool,
+    entries: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct DemoUserBalance {
+    btc_free: u64,
+    btc_locked: u64,
+    usd_free: u64,
+    usd_locked: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct OrderSettlement {
+    fill_amount: u64,
+    clearing_price: u64,
+    paid_asset: Asset,
+    paid: u64,
+    refunded: u64,
+    received_asset: Asset,
+    received: u64,
+    fee: u64,
+}
+
+// 0.01 BTC, prices in cents per BTC
+const AMOUNT: u64 = 1_000_000;
+const BUY_LIMIT: u64 = 5_000_000;   // locks $500.00
+const SELL_LIMIT: u64 = 4_000_000;
+const COST: u64 = 45_000;           // 0.01 BTC at the $45,000 midpoint
+
+fn place(ic: &PocketIc, backend: Principal, user: Principal, side: OrderType) -> u64 {
+    let (price_limit, collateral) = match side {
+        OrderType::Buy => (BUY_LIMIT, 50_000),
+        OrderType::Sell => (SELL_LIMIT, AMOUNT),
+    };
+    submit(ic, backend, &OrderPayload::new(user, 1, side, AMOUNT, price_limit), collateral)
+}
+
+fn reconcile(ic: &PocketIc, backend: Principal) -> Reconciliation {
+    let resp = ic.query_call(backend, Principal::anonymous(), "reconcile", Encode!().unwrap()).unwrap();
+    Decode!(&resp, Reconciliation).unwrap()
+}
+
+fn journal(ic: &PocketIc, backend: Principal, admin: Principal) -> Vec<JournalEntry> {
+    let mut entries = Vec::new();
+    let mut cursor = None;
+    loop {
+        let resp = ic.query_call(backend, admin, "get_journal", Encode!(&cursor, &100u32).unwrap()).unwrap();
+        let page = Decode!(&resp, Result<JournalPage, candid::Reserved>).unwrap().expect("get_journal failed");
+        entries.extend(page.entries);
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            return entries;
+        }
+    }
+}
+
+fn order_settlement(ic: &PocketIc, backend: Principal, order_id: u64) -> Option<OrderSettlement> {
+    let resp = ic.query_call(backend, Principal::anonymous(), "get_order_settlement", Encode!(&order_id).unwrap())
+        .unwrap();
+    Decode!(&resp, Option<OrderSettlement>).unwrap()
+}
+
+fn demo_balance(ic: &PocketIc, backend: Principal, user: Principal) -> DemoUserBalance {
+    let resp = ic.query_call(backend, user, "get_demo_balance_of", Encode!(&user).unwrap()).unwrap();
+    Decode!(&resp, DemoUserBalance).unwrap()
+}
+
+#[test]
+fn settlement_is_journaled_and_reconciles() {
+    let ic = PocketIc::new();
+
+    let backend = install_backend(&ic);
+
+    let admin = Principal::anonymous();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+
+    assert!(reconcile(&ic, backend).balanced);
+
+    // ============ ROUND ============
+
+    let resp = ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    Decode!(&resp, Result<u64, candid::Reserved>).unwrap().unwrap();
+
+    let buy = place(&ic, backend, buyer, OrderType::Buy);
+    let sell = place(&ic, backend, seller, OrderType::Sell);
+    let funded_buyer = demo_balance(&ic, backend, buyer);
+    let funded_seller = demo_balance(&ic, backend, seller);
+    assert!(reconcile(&ic, backend).balanced);
+
+    let resp = ic.update_call(backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap()).unwrap();
+    Decode!(&resp, Result<candid::Reserved, candid::Reserved>).unwrap().expect("clearing failed");
+
+    // ============ BALANCES ============
+
+    let bought = demo_balance(&ic, backend, buyer);
+    assert_eq!(bought.usd_locked, 0);
+    assert_eq!(bought.usd_free, funded_buyer.usd_free + 50_000 - COST);
+    assert_eq!(bought.btc_free, funded_buyer.btc_free + AMOUNT);
+
+    let sold = demo_balance(&ic, backend, seller);
+    assert_eq!(sold.btc_locked, 0);
+    assert_eq!(sold.usd_free, funded_seller.usd_free + COST);
+
+    // ============ RECONCILIATION ============
+
+    let report = reconcile(&ic, backend);
+    assert!(report.balanced, "{:?}", report);
+    for asset in &report.assets {
+        assert!(asset.balanced, "{:?}", asset);
+        assert_eq!(asset.withdrawals, Nat::from(0u8));
+        assert_eq!(asset.locked, Nat::from(0u8));
+        // The midpoint divides evenly: no rounding dust
+        assert_eq!(asset.clearing, Int::from(0));
+    }
+
+    // ============ JOURNAL ============
+
+    let entries = journal(&ic, backend, admin);
+    assert_eq!(entries.len() as u64, report.entries);
+    assert!(entries.windows(2).all(|w| w[0].id + 1 == w[1].id));
+
+    // Every posting sums to zero per asset
+    let zero = Int::from(0);
+    let mut postings: BTreeMap<(u64, Asset), Int> = BTreeMap::new();
+    for entry in &entries {
+        *postings.entry((entry.posting, entry.asset.clone())).or_insert_with(|| zero.clone()) += entry.delta.clone();
+    }
+    assert!(postings.values().all(|sum| *sum == zero));
+
+    let reasons = |order_id: u64| -> Vec<JournalReason> {
+        let mut reasons: Vec<JournalReason> = entries
+            .iter()
+            .filter(|e| e.order_id == Some(order_id))
+            .map(|e| e.reason.clone())
+            .collect();
+        reasons.dedup();
+        reasons
+    };
+    let cycle = vec![JournalReason::Lock, JournalReason::FillDebit, JournalReason::FillCredit];
+    assert_eq!(reasons(buy), cycle);
+    assert_eq!(reasons(sell), cycle);
+
+    // The buyer's $500.00 left Locked, $50.00 of it back to Free
+    let buyer_usd: Vec<(JournalAccount, Int)> = entries
+        .iter()
+        .filter(|e| e.order_id == Some(buy) && e.reason == JournalReason::FillDebit)
+        .map(|e| (e.account.clone(), e.delta.clone()))
+        .collect();
+    assert_eq!(
+        buyer_usd,
+        vec![
+            (JournalAccount::Locked(buyer), Int::from(-50_000)),
+            (JournalAccount::Free(buyer), Int::from(5_000)),
+            (JournalAccount::Clearing(BTC_USD), Int::from(COST)),
+        ]
+    );
+
+    // ============ SETTLEMENT RECORDS ============
+
+    assert_eq!(
+        order_settlement(&ic, backend, buy),
+        Some(OrderSettlement {
+            fill_amount: AMOUNT,
+            clearing_price: 4_500_000,
+            paid_asset: Asset::USD,
+            paid: COST,
+            refunded: 5_000,
+            received_asset: Asset::BTC,
+            received: AMOUNT,
+            fee: 0,
+        })
+    );
+    assert_eq!(
+        order_settlement(&ic, backend, sell),
+        Some(OrderSettlement {
+            fill_amount: AMOUNT,
+            clearing_price: 4_500_000,
+            paid_asset: Asset::BTC,
+            paid: AMOUNT,
+            refunded: 0,
+            received_asset: Asset::USD,
+            received: COST,
+            fee: 0,
+        })
+    );
+    assert_eq!(order_settlement(&ic, backend, sell + 1), None);
+
+    // Only admins read the journal
+    let resp = ic.query_call(backend, buyer, "get_journal", Encode!(&None::<u64>, &10u32).unwrap()).unwrap();
+    assert!(Decode!(&resp, Result<JournalPage, candid::Reserved>).unwrap().is_err());
+
+    println!("✅ Settlement journaled and reconciled");
+}
@@ -0,0 +1,206 @@
+mod common;
+
+use pocket_ic::common::rest::RawMessageId;
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use serde::Deserialize;
+use common::*;
+
+fn buy_order(buyer: Principal) -> OrderPayload {
+    OrderPayload::new(buyer, 1, OrderType::Buy, 100_000, 5_000_000)
+}
+
+struct Round {
+    backend: Principal,
+    btc_ledger: Principal,
+    usd_ledger: Principal,
+    buyer: Principal,
+    seller: Principal,
+    buy: u64,
+}
+
+/// Install both ledgers and the backend on ledger escrow, and submit a buy
+/// and a sell that cross
+fn open_round(ic: &PocketIc) -> Round {
+    let wasm = ledger_wasm();
+
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+
+    // ============ INSTALL LEDGERS + BACKEND ============
+
+    let btc_ledger = install_ledger(ic, &wasm, "ckBTC", seller);
+    let usd_ledger = install_ledger(ic, &wasm, "ckUSDC", buyer);
+
+    let backend = install_backend(ic);
+
+    let config = EscrowConfig {
+        backend: EscrowBackend::Ledger,
+        ledgers: vec![
+            (Asset::BTC, LedgerConfig { ledger_id: btc_ledger, fee: FEE }),
+            (Asset::USD, LedgerConfig { ledger_id: usd_ledger, fee: FEE }),
+        ],
+    };
+    ic.update_call(
+        backend,
+        Principal::anonymous(),
+        "admin_configure_escrow",
+        Encode!(&config).unwrap(),
+    ).unwrap();
+
+    ic.update_call(backend, Principal::anonymous(), "admin_start_round", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    // ============ SUBMIT: funds move into the canister ============
+
+    // Prices in cents per BTC: buy 0.001 BTC @ $50,000 locks 5_000 cents,
+    // sell 0.001 BTC @ $40,000 locks 100_000 sats
+    approve(ic, usd_ledger, buyer, backend, 5_000 + FEE);
+    approve(ic, btc_ledger, seller, backend, 100_000 + FEE);
+
+    let buy = submit(ic, backend, &buy_order(buyer), 5_000);
+    submit(ic, backend, &OrderPayload::new(seller, 1, OrderType::Sell, 100_000, 4_000_000), 100_000);
+
+    Round { backend, btc_ledger, usd_ledger, buyer, seller, buy }
+}
+
+#[test]
+fn ledger_escrow_locks_and_settles_through_icrc_ledgers() {
+    let ic = PocketIc::new();
+    let Round { backend, btc_ledger, usd_ledger, buyer, seller, .. } = open_round(&ic);
+
+    assert_eq!(balance_of(&ic, usd_ledger, backend), Nat::from(5_000u64));
+    assert_eq!(balance_of(&ic, btc_ledger, backend), Nat::from(100_000u64));
+
+    // ============ CLEAR: payouts at the clearing price ============
+
+    ic.update_call(backend, Principal::anonymous(), "admin_run_clearing", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    // Clears at the $45,000 midpoint: buyer pays 4_500 and gets 500 back,
+    // seller receives 4_500. Every payout is net of the ledger fee.
+    assert_eq!(balance_of(&ic, btc_ledger, buyer), Nat::from(100_000 - FEE));
+    assert_eq!(balance_of(&ic, usd_ledger, seller), Nat::from(4_500 - FEE));
+    assert_eq!(
+        balance_of(&ic, usd_ledger, buyer),
+        Nat::from(1_000_000 - FEE - (5_000 + FEE) + (500 - FEE))
+    );
+
+    println!("✅ ICRC ledger escrow round passed");
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct FailedPayout {
+    order_id: u64,
+    amount: u64,
+}
+
+fn failed_payouts(ic: &PocketIc, backend: Principal) -> Vec<FailedPayout> {
+    let resp = ic.query_call(backend, Principal::anonymous(), "get_failed_payouts", Encode!().unwrap()).unwrap();
+    Decode!(&resp, Result<Vec<FailedPayout>, candid::Reserved>).unwrap().expect("get_failed_payouts failed")
+}
+
+#[test]
+fn failed_settlement_payouts_are_kept_and_retried() {
+    let ic = PocketIc::new();
+    let Round { backend, btc_ledger, buyer, buy, .. } = open_round(&ic);
+
+    // The BTC ledger is down while the round settles
+    ic.stop_canister(btc_ledger, None).unwrap();
+    ic.update_call(backend, Principal::anonymous(), "admin_run_clearing", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    let failed = failed_payouts(&ic, backend);
+    assert_eq!(failed.len(), 1);
+    assert_eq!((failed[0].order_id, failed[0].amount), (buy, 100_000));
+
+    // Only operators retry
+    let resp = ic.update_call(backend, buyer, "retry_settlement_payout", Encode!(&buy).unwrap()).unwrap();
+    assert!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().is_err());
+
+    ic.start_canister(btc_ledger, None).unwrap();
+    let resp = ic.update_call(backend, Principal::anonymous(), "retry_settlement_payout", Encode!(&buy).unwrap())
+        .unwrap();
+    assert_eq!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().ok(), Some(1));
+
+    assert_eq!(balance_of(&ic, btc_ledger, buyer), Nat::from(100_000 - FEE));
+    assert!(failed_payouts(&ic, backend).is_empty());
+
+    println!("✅ Failed settlement payout retried");
+}
+
+#[derive(CandidType)]
+struct SubmissionLimits {
+    max_orders_per_round: u32,
+    max_notional_per_round: u64,
+}
+
+fn set_notional_limit(ic: &PocketIc, backend: Principal, max_notional_per_round: u64) -> RawMessageId {
+    let limits = SubmissionLimits { max_orders_per_round: 50, max_notional_per_round };
+    ic.submit_call(backend, Principal::anonymous(), "admin_set_submission_limits", Encode!(&BTC_USD, &limits).unwrap())
+        .unwrap()
+}
+
+#[test]
+fn failed_amendment_does_not_reuse_escrow_memos() {
+    let ic = PocketIc::new();
+    let Round { backend, usd_ledger, buyer, buy, .. } = open_round(&ic);
+
+    approve(&ic, usd_ledger, buyer, backend, 6_000 + 7_000 + 2 * FEE);
+    let before = balance_of(&ic, usd_ledger, buyer);
+    let amend = |collateral: u64| {
+        let payload = buy_order(buyer).encode();
+        let args = Encode!(&buy, &collateral, &payload, &commitment(&payload)).unwrap();
+        ic.submit_call(backend, buyer, "amend_order", args).unwrap()
+    };
+
+    // The limit drops while the new lock is taken, so the amendment hands
+    // its 6_000 back and fails
+    let failed = amend(6_000);
+    let lowered = set_notional_limit(&ic, backend, 5_500);
+    let resp = ic.await_call(failed).unwrap();
+    assert!(Decode!(&resp, Result<(), candid::Reserved>).unwrap().is_err());
+    ic.await_call(lowered).unwrap();
+
+    // The next amendment and the cancel get memos of their own, so every
+    // transfer goes through
+    ic.await_call(set_notional_limit(&ic, backend, u64::MAX)).unwrap();
+    let resp = ic.await_call(amend(7_000)).unwrap();
+    assert!(Decode!(&resp, Result<(), candid::Reserved>).unwrap().is_ok());
+    let resp = ic.update_call(backend, buyer, "cancel_order", Encode!(&buy).unwrap()).unwrap();
+    assert!(Decode!(&resp, Result<(), candid::Reserved>).unwrap().is_ok());
+
+    // Two locks and three refunds since `before`, each costing a ledger fee;
+    // the buyer is back to holding nothing in escrow
+    assert_eq!(balance_of(&ic, usd_ledger, buyer), before + Nat::from(5_000 - 5 * FEE));
+    assert_eq!(balance_of(&ic, usd_ledger, backend), Nat::from(0u64));
+
+    println!("✅ Failed amendment left later memos intact");
+}
+
+#[test]
+fn failed_cancel_refund_is_kept_and_retried() {
+    let ic = PocketIc::new();
+    let Round { backend, usd_ledger, buyer, buy, .. } = open_round(&ic);
+    let before = balance_of(&ic, usd_ledger, buyer);
+
+    // The USD ledger is down while the buyer cancels: the order is
+    // cancelled, its refund kept
+    ic.stop_canister(usd_ledger, None).unwrap();
+    let resp = ic.update_call(backend, buyer, "cancel_order", Encode!(&buy).unwrap()).unwrap();
+    assert!(Decode!(&resp, Result<(), candid::Reserved>).unwrap().is_err());
+
+    let failed = failed_payouts(&ic, backend);
+    assert_eq!(failed.len(), 1);
+    assert_eq!((failed[0].order_id, failed[0].amount), (buy, 5_000));
+
+    ic.start_canister(usd_ledger, None).unwrap();
+    let resp = ic.update_call(backend, Principal::anonymous(), "retry_settlement_payout", Encode!(&buy).unwrap())
+        .unwrap();
+    assert_eq!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().ok(), Some(1));
+
+    assert_eq!(balance_of(&ic, usd_ledger, buyer), before + Nat::from(5_000 - FEE));
+    assert!(failed_payouts(&ic, backend).is_empty());
+
+    println!("✅ Failed cancel refund retried");
+}
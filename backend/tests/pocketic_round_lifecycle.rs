@@ -0,0 +1,188 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::Deserialize;
+use common::*;
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+enum RoundState {
+    Pending,
+    Active,
+    Revealing,
+    Clearing,
+    Executing,
+    Completed,
+    Failed,
+    Aborted,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum OrderStatus {
+    Open,
+    Cancelled,
+    Rejected,
+    Expired,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct Order {
+    status: OrderStatus,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct OrderPage {
+    orders: Vec<Order>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct MarketState {
+    round_id: u64,
+    round_state: RoundState,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct RoundPhase {
+    state: RoundState,
+    entered_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct DemoUserBalance {
+    usd_free: u64,
+    usd_locked: u64,
+}
+
+// 0.01 BTC at $50,000 (cents per BTC) locks $500.00
+const AMOUNT: u64 = 1_000_000;
+const PRICE: u64 = 5_000_000;
+const COLLATERAL: u64 = 50_000;
+
+fn submit_buy(ic: &PocketIc, backend: Principal, user: Principal, round_id: u64) {
+    submit(ic, backend, &OrderPayload::new(user, round_id, OrderType::Buy, AMOUNT, PRICE), COLLATERAL);
+}
+
+fn call(ic: &PocketIc, backend: Principal, admin: Principal, method: &str) -> Result<(), ()> {
+    let resp = ic.update_call(backend, admin, method, Encode!(&BTC_USD).unwrap()).unwrap();
+    match method {
+        "admin_run_clearing" => Decode!(&resp, Result<candid::Reserved, candid::Reserved>).unwrap().map(|_| ()).map_err(|_| ()),
+        "admin_reset_round" => Decode!(&resp, Result<(), candid::Reserved>).unwrap().map_err(|_| ()),
+        _ => Decode!(&resp, Result<u64, candid::Reserved>).unwrap().map(|_| ()).map_err(|_| ()),
+    }
+}
+
+fn round_state(ic: &PocketIc, backend: Principal) -> MarketState {
+    let resp = ic.query_call(backend, Principal::anonymous(), "get_round_state", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+    Decode!(&resp, Option<MarketState>).unwrap().unwrap()
+}
+
+fn timeline(ic: &PocketIc, backend: Principal, round_id: u64) -> Vec<RoundState> {
+    let resp = ic.query_call(
+        backend,
+        Principal::anonymous(),
+        "get_round_timeline",
+        Encode!(&BTC_USD, &round_id).unwrap(),
+    ).unwrap();
+    let phases = Decode!(&resp, Vec<RoundPhase>).unwrap();
+    assert!(phases.windows(2).all(|w| w[0].entered_at <= w[1].entered_at));
+    phases.into_iter().map(|p| p.state).collect()
+}
+
+fn statuses(ic: &PocketIc, backend: Principal, round_id: u64) -> Vec<OrderStatus> {
+    let resp = ic.query_call(
+        backend,
+        Principal::anonymous(),
+        "get_round_orders",
+        Encode!(&BTC_USD, &round_id, &None::<()>, &100u32).unwrap(),
+    ).unwrap();
+    Decode!(&resp, OrderPage).unwrap().orders.into_iter().map(|o| o.status).collect()
+}
+
+fn refund(ic: &PocketIc, backend: Principal, admin: Principal, round_id: u64) -> Result<u64, candid::Reserved> {
+    let resp = ic.update_call(backend, admin, "admin_refund_round", Encode!(&BTC_USD, &round_id).unwrap())
+        .unwrap();
+    Decode!(&resp, Result<u64, candid::Reserved>).unwrap()
+}
+
+fn demo_balance(ic: &PocketIc, backend: Principal, user: Principal) -> DemoUserBalance {
+    let resp = ic.query_call(backend, user, "get_demo_balance_of", Encode!(&user).unwrap()).unwrap();
+    Decode!(&resp, DemoUserBalance).unwrap()
+}
+
+#[test]
+fn round_lifecycle_rejects_illegal_transitions_and_refunds_ended_rounds() {
+    let ic = PocketIc::new();
+
+    let backend = install_backend(&ic);
+
+    let admin = Principal::anonymous();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let funded = demo_balance(&ic, backend, buyer);
+
+    // ============ ILLEGAL TRANSITIONS ============
+
+    assert!(call(&ic, backend, admin, "admin_run_clearing").is_err());
+    assert!(call(&ic, backend, admin, "admin_abort_round").is_err());
+    assert!(call(&ic, backend, admin, "admin_reset_round").is_err());
+
+    call(&ic, backend, admin, "admin_start_round").unwrap();
+    assert!(call(&ic, backend, admin, "admin_start_round").is_err());
+    assert!(call(&ic, backend, admin, "admin_reset_round").is_err());
+
+    // ============ ABORTED ROUND REFUNDS ============
+
+    submit_buy(&ic, backend, buyer, 1);
+    assert_eq!(demo_balance(&ic, backend, buyer).usd_locked, COLLATERAL);
+
+    call(&ic, backend, admin, "admin_abort_round").unwrap();
+    assert_eq!(round_state(&ic, backend).round_state, RoundState::Aborted);
+    assert_eq!(demo_balance(&ic, backend, buyer), funded);
+    assert_eq!(timeline(&ic, backend, 1), vec![RoundState::Active, RoundState::Aborted]);
+    assert_eq!(statuses(&ic, backend, 1), vec![OrderStatus::Expired]);
+
+    // An ended round cannot be cleared or aborted again
+    assert!(call(&ic, backend, admin, "admin_run_clearing").is_err());
+    assert!(call(&ic, backend, admin, "admin_abort_round").is_err());
+
+    // ============ FAILED ROUND REFUNDS ============
+
+    // The next round starts straight from Aborted
+    call(&ic, backend, admin, "admin_start_round").unwrap();
+    assert_eq!(round_state(&ic, backend).round_id, 2);
+
+    // A one-sided book cannot clear
+    submit_buy(&ic, backend, buyer, 2);
+    assert!(call(&ic, backend, admin, "admin_run_clearing").is_err());
+    assert_eq!(round_state(&ic, backend).round_state, RoundState::Failed);
+    assert_eq!(demo_balance(&ic, backend, buyer), funded);
+    assert_eq!(
+        timeline(&ic, backend, 2),
+        vec![RoundState::Active, RoundState::Revealing, RoundState::Clearing, RoundState::Failed]
+    );
+    assert_eq!(statuses(&ic, backend, 2), vec![OrderStatus::Expired]);
+
+    // ============ RETRIES NEVER REFUND TWICE ============
+
+    assert!(call(&ic, backend, admin, "admin_run_clearing").is_err());
+    assert_eq!(refund(&ic, backend, admin, 1).unwrap(), 0);
+    assert_eq!(refund(&ic, backend, admin, 2).unwrap(), 0);
+    assert_eq!(demo_balance(&ic, backend, buyer), funded);
+
+    // ============ RESET ============
+
+    call(&ic, backend, admin, "admin_reset_round").unwrap();
+    assert_eq!(round_state(&ic, backend).round_state, RoundState::Pending);
+    assert_eq!(
+        timeline(&ic, backend, 2).last(),
+        Some(&RoundState::Pending)
+    );
+
+    // Only rounds that failed or aborted can be refunded
+    call(&ic, backend, admin, "admin_start_round").unwrap();
+    submit_buy(&ic, backend, buyer, 3);
+    assert!(refund(&ic, backend, admin, 3).is_err());
+    assert_eq!(demo_balance(&ic, backend, buyer).usd_locked, COLLATERAL);
+
+    println!("✅ Round lifecycle enforced");
+}
@@ -0,0 +1,103 @@
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+enum OrderType {
+    Buy,
+    Sell,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum VeilError {
+    AnonymousCaller,
+    OrderLimitReached { max_orders_per_round: u32 },
+    NotionalLimitExceeded { notional: u128, max: u64 },
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct SubmissionLimits {
+    max_orders_per_round: u32,
+    max_notional_per_round: u64,
+    reference_price: Option<u64>,
+}
+
+const BTC_USD: u32 = 0;
+
+// Ingress dropped by inspect_message and errors returned by the canister
+// both come back as Err
+fn submit(ic: &PocketIc, backend: Principal, user: Principal, side: OrderType, collateral: u64) -> Result<u64, String> {
+    let args = Encode!(&BTC_USD, &side, &collateral, &vec![1u8; 32], &String::new()).unwrap();
+    let resp = ic.update_call(backend, user, "submit_order", args)
+        .map_err(|e| e.reject_message)?;
+    Decode!(&resp, Result<u64, VeilError>).unwrap().map_err(|e| format!("{:?}", e))
+}
+
+#[test]
+fn submissions_are_authenticated_and_capped() {
+    let ic = PocketIc::new();
+
+    let wasm = std::fs::read(
+        "target/wasm32-unknown-unknown/release/mempool_chess_backend.wasm"
+    ).expect("Run cargo build --target wasm32-unknown-unknown --release first");
+
+    let backend = ic.create_canister();
+    ic.add_cycles(backend, 10_000_000_000_000u128);
+    ic.install_canister(backend, wasm, vec![], None);
+
+    let admin = Principal::anonymous();
+    let trader = Principal::from_slice(&[1; 29]);
+    let other = Principal::from_slice(&[2; 29]);
+
+    let limits = SubmissionLimits { max_orders_per_round: 2, max_notional_per_round: 1_000, reference_price: None };
+
+    // Only admins configure limits
+    let resp = ic.update_call(backend, trader, "admin_set_submission_limits", Encode!(&BTC_USD, &limits).unwrap())
+        .unwrap();
+    assert!(Decode!(&resp, Result<(), candid::Reserved>).unwrap().is_err());
+    let resp = ic.update_call(backend, admin, "admin_set_submission_limits", Encode!(&BTC_USD, &limits).unwrap())
+        .unwrap();
+    Decode!(&resp, Result<(), candid::Reserved>).unwrap().unwrap();
+
+    let resp = ic.query_call(backend, trader, "get_submission_limits", Encode!(&BTC_USD).unwrap()).unwrap();
+    assert_eq!(Decode!(&resp, SubmissionLimits).unwrap(), limits);
+
+    ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    // ============ ANONYMOUS CALLERS ============
+
+    assert!(submit(&ic, backend, Principal::anonymous(), OrderType::Buy, 100).is_err());
+
+    // ============ NOTIONAL CAP ============
+
+    let first = submit(&ic, backend, trader, OrderType::Buy, 600).unwrap();
+    assert!(submit(&ic, backend, trader, OrderType::Buy, 500).is_err());
+
+    // Cancelling frees notional, but the order still counts
+    let resp = ic.update_call(backend, trader, "cancel_order", Encode!(&first).unwrap()).unwrap();
+    Decode!(&resp, Result<(), VeilError>).unwrap().unwrap();
+    submit(&ic, backend, trader, OrderType::Buy, 500).unwrap();
+
+    // ============ ORDER CAP ============
+
+    assert!(submit(&ic, backend, trader, OrderType::Buy, 1).is_err());
+
+    // ============ SELLS BEFORE THE FIRST CLEARING ============
+
+    // Nothing to value a sell's BTC by yet
+    assert!(submit(&ic, backend, other, OrderType::Sell, 20_000).is_err());
+
+    // Until the market clears, sells are valued at the reference price:
+    // 0.02 BTC at $45,000 is $900.00, over the cap
+    let limits = SubmissionLimits { reference_price: Some(4_500_000), ..limits };
+    let resp = ic.update_call(backend, admin, "admin_set_submission_limits", Encode!(&BTC_USD, &limits).unwrap())
+        .unwrap();
+    Decode!(&resp, Result<(), candid::Reserved>).unwrap().unwrap();
+    assert!(submit(&ic, backend, other, OrderType::Sell, 2_000_000).is_err());
+
+    // Quotas are per principal: 0.0002 BTC is $9.00
+    submit(&ic, backend, other, OrderType::Sell, 20_000).unwrap();
+
+    println!("✅ Submission limits enforced");
+}
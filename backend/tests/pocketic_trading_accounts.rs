@@ -0,0 +1,238 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use serde::Deserialize;
+use common::*;
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct AssetBalance {
+    asset: Asset,
+    free: u64,
+    locked: u64,
+    pending_withdrawal: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum TransferStatus {
+    Pending,
+    Completed,
+    Failed(String),
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct AccountTransfer {
+    id: u64,
+    status: TransferStatus,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct Reconciliation {
+    balanced: bool,
+}
+
+// What the backend holds on `ledger` for `user`, in their own subaccount
+fn custody_of(ic: &PocketIc, backend: Principal, ledger: Principal, user: Principal) -> Nat {
+    let resp = ic.query_call(backend, user, "get_custody", Encode!(&user).unwrap()).unwrap();
+    let (account, _) = Decode!(&resp, Account, Vec<(Asset, u64)>).unwrap();
+    assert_eq!(account.owner, backend);
+    let resp = ic.query_call(ledger, user, "icrc1_balance_of", Encode!(&account).unwrap()).unwrap();
+    Decode!(&resp, Nat).unwrap()
+}
+
+fn deposit(ic: &PocketIc, backend: Principal, user: Principal, asset: Asset, amount: u64) -> Result<u64, candid::Reserved> {
+    let resp = ic.update_call(backend, user, "deposit", Encode!(&asset, &amount).unwrap()).unwrap();
+    Decode!(&resp, Result<u64, candid::Reserved>).unwrap()
+}
+
+fn withdraw_args(user: Principal, asset: Asset, amount: u64) -> Vec<u8> {
+    Encode!(&asset, &amount, &account(user)).unwrap()
+}
+
+fn balance(ic: &PocketIc, backend: Principal, user: Principal, asset: Asset) -> AssetBalance {
+    let resp = ic.query_call(backend, user, "get_balances", Encode!(&user).unwrap()).unwrap();
+    Decode!(&resp, Vec<AssetBalance>).unwrap().into_iter().find(|b| b.asset == asset).unwrap()
+}
+
+fn last_transfer(ic: &PocketIc, backend: Principal, user: Principal) -> AccountTransfer {
+    let resp = ic.query_call(backend, user, "get_my_transfers", Encode!(&1u32).unwrap()).unwrap();
+    Decode!(&resp, Vec<AccountTransfer>).unwrap().remove(0)
+}
+
+#[test]
+fn trading_accounts_deposit_trade_and_withdraw() {
+    let ic = PocketIc::new();
+
+    let wasm = ledger_wasm();
+
+    let admin = Principal::anonymous();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+
+    // ============ INSTALL LEDGERS + BACKEND ============
+
+    let btc_ledger = install_ledger(&ic, &wasm, "ckBTC", seller);
+    let usd_ledger = install_ledger(&ic, &wasm, "ckUSDC", buyer);
+
+    let backend = install_backend(&ic);
+
+    // Deposits need the Accounts backend
+    assert!(deposit(&ic, backend, buyer, Asset::USD, 5_000).is_err());
+
+    let config = EscrowConfig {
+        backend: EscrowBackend::Accounts,
+        ledgers: vec![
+            (Asset::BTC, LedgerConfig { ledger_id: btc_ledger, fee: FEE }),
+            (Asset::USD, LedgerConfig { ledger_id: usd_ledger, fee: FEE }),
+        ],
+    };
+    ic.update_call(backend, admin, "admin_configure_escrow", Encode!(&config).unwrap()).unwrap();
+
+    // ============ DEPOSIT ============
+
+    // Nobody starts funded
+    assert_eq!(balance(&ic, backend, buyer, Asset::USD).free, 0);
+
+    // Without an approval the ledger refuses and nothing is credited
+    assert!(deposit(&ic, backend, buyer, Asset::USD, 5_000).is_err());
+
+    approve(&ic, usd_ledger, buyer, backend, 5_000 + FEE);
+    approve(&ic, btc_ledger, seller, backend, 100_000 + FEE);
+    deposit(&ic, backend, buyer, Asset::USD, 5_000).unwrap();
+    deposit(&ic, backend, seller, Asset::BTC, 100_000).unwrap();
+
+    // Each deposit lands in its depositor's subaccount
+    assert_eq!(balance(&ic, backend, buyer, Asset::USD).free, 5_000);
+    assert_eq!(custody_of(&ic, backend, usd_ledger, buyer), Nat::from(5_000u64));
+    assert_eq!(custody_of(&ic, backend, btc_ledger, seller), Nat::from(100_000u64));
+    assert_eq!(balance_of(&ic, usd_ledger, backend), Nat::from(0u64));
+
+    // ============ TRADE ON THE BOOK ============
+
+    // 0.001 BTC bought at $50,000 and sold at $40,000 clears at $45,000
+    ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap()).unwrap();
+    submit(&ic, backend, &OrderPayload::new(buyer, 1, OrderType::Buy, 100_000, 5_000_000), 5_000);
+    submit(&ic, backend, &OrderPayload::new(seller, 1, OrderType::Sell, 100_000, 4_000_000), 100_000);
+    assert_eq!(balance(&ic, backend, buyer, Asset::USD).locked, 5_000);
+
+    ic.update_call(backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap()).unwrap();
+
+    // No tokens moved on the ledgers, only ownership on the book
+    assert_eq!(custody_of(&ic, backend, usd_ledger, buyer), Nat::from(5_000u64));
+    assert_eq!(
+        balance(&ic, backend, seller, Asset::USD),
+        AssetBalance { asset: Asset::USD, free: 4_500, locked: 0, pending_withdrawal: 0 }
+    );
+
+    // ============ WITHDRAW ============
+
+    // More than the free balance, or not more than the fee, is refused
+    let resp = ic.update_call(backend, seller, "withdraw", withdraw_args(seller, Asset::USD, 4_501)).unwrap();
+    assert!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().is_err());
+    let resp = ic.update_call(backend, seller, "withdraw", withdraw_args(seller, Asset::USD, FEE)).unwrap();
+    assert!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().is_err());
+
+    // Two withdrawals at once: the second finds the first in flight
+    let first = ic.submit_call(backend, seller, "withdraw", withdraw_args(seller, Asset::USD, 2_000)).unwrap();
+    let second = ic.submit_call(backend, seller, "withdraw", withdraw_args(seller, Asset::USD, 2_000)).unwrap();
+    let first = Decode!(&ic.await_call(first).unwrap(), Result<u64, candid::Reserved>).unwrap();
+    let second = Decode!(&ic.await_call(second).unwrap(), Result<u64, candid::Reserved>).unwrap();
+    assert!(first.is_ok());
+    assert!(second.is_err());
+
+    // The seller's proceeds come out of the buyer's subaccount, which holds
+    // what the buyer paid
+    assert_eq!(balance_of(&ic, usd_ledger, seller), Nat::from(2_000 - FEE));
+    assert_eq!(custody_of(&ic, backend, usd_ledger, buyer), Nat::from(3_000u64));
+    assert_eq!(
+        balance(&ic, backend, seller, Asset::USD),
+        AssetBalance { asset: Asset::USD, free: 2_500, locked: 0, pending_withdrawal: 0 }
+    );
+
+    // The buyer takes out the bitcoin bought
+    let resp = ic.update_call(backend, buyer, "withdraw", withdraw_args(buyer, Asset::BTC, 100_000)).unwrap();
+    Decode!(&resp, Result<u64, candid::Reserved>).unwrap().expect("withdraw failed");
+    assert_eq!(balance_of(&ic, btc_ledger, buyer), Nat::from(100_000 - FEE));
+    assert_eq!(custody_of(&ic, backend, btc_ledger, seller), Nat::from(0u64));
+
+    // The buyer's unspent dollars are still in their own subaccount
+    let resp = ic.update_call(backend, buyer, "withdraw", withdraw_args(buyer, Asset::USD, 500)).unwrap();
+    Decode!(&resp, Result<u64, candid::Reserved>).unwrap().expect("withdraw failed");
+    assert_eq!(custody_of(&ic, backend, usd_ledger, buyer), Nat::from(2_500u64));
+
+    // ============ RECONCILE ============
+
+    let resp = ic.query_call(backend, admin, "reconcile", Encode!().unwrap()).unwrap();
+    assert!(Decode!(&resp, Reconciliation).unwrap().balanced);
+
+    println!("✅ Trading accounts deposit, trade and withdraw");
+}
+
+#[test]
+fn expired_withdrawal_is_settled_from_the_ledger_log() {
+    let ic = PocketIc::new();
+    let wasm = ledger_wasm();
+
+    let admin = Principal::anonymous();
+    let user = Principal::from_slice(&[1; 29]);
+    let usd_ledger = install_ledger(&ic, &wasm, "ckUSDC", user);
+
+    let backend = install_backend(&ic);
+    let config = EscrowConfig {
+        backend: EscrowBackend::Accounts,
+        ledgers: vec![(Asset::USD, LedgerConfig { ledger_id: usd_ledger, fee: FEE })],
+    };
+    ic.update_call(backend, admin, "admin_configure_escrow", Encode!(&config).unwrap()).unwrap();
+
+    approve(&ic, usd_ledger, user, backend, 5_000 + FEE);
+    deposit(&ic, backend, user, Asset::USD, 5_000).unwrap();
+
+    // ============ UNKNOWN OUTCOME ============
+
+    // The ledger is down: the withdrawal stays pending
+    ic.stop_canister(usd_ledger, None).unwrap();
+    let resp = ic.update_call(backend, user, "withdraw", withdraw_args(user, Asset::USD, 2_000)).unwrap();
+    assert!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().is_err());
+    let transfer = last_transfer(&ic, backend, user);
+    assert_eq!(transfer.status, TransferStatus::Pending);
+
+    // It blocks another withdrawal, and cannot be settled as unsent while a
+    // resend could still land
+    let resp = ic.update_call(backend, user, "withdraw", withdraw_args(user, Asset::USD, 1_000)).unwrap();
+    assert!(Decode!(&resp, Result<u64, candid::Reserved>).unwrap().is_err());
+    let resp = ic.update_call(backend, admin, "settle_withdrawal", Encode!(&transfer.id, &None::<u64>).unwrap()).unwrap();
+    assert!(Decode!(&resp, Result<(), candid::Reserved>).unwrap().is_err());
+
+    // A day later the ledger can no longer deduplicate the retry, which
+    // refuses it as TooOld: the amount stays pending, not back in Free
+    ic.start_canister(usd_ledger, None).unwrap();
+    ic.advance_time(std::time::Duration::from_secs(25 * 60 * 60));
+    let resp = ic.update_call(backend, user, "retry_withdrawal", Encode!(&transfer.id).unwrap()).unwrap();
+    assert!(Decode!(&resp, Result<(), candid::Reserved>).unwrap().is_err());
+    assert_eq!(
+        balance(&ic, backend, user, Asset::USD),
+        AssetBalance { asset: Asset::USD, free: 3_000, locked: 0, pending_withdrawal: 2_000 }
+    );
+
+    // ============ SETTLE ============
+
+    // Only operators settle, and a block that is not the payout is refused
+    let settle = |sender: Principal, block: Option<u64>| {
+        let resp = ic.update_call(backend, sender, "settle_withdrawal", Encode!(&transfer.id, &block).unwrap()).unwrap();
+        Decode!(&resp, Result<(), candid::Reserved>).unwrap()
+    };
+    assert!(settle(user, None).is_err());
+    assert!(settle(admin, Some(0)).is_err());
+    assert_eq!(last_transfer(&ic, backend, user).status, TransferStatus::Pending);
+
+    // No block carries its memo: the amount returns to Free
+    settle(admin, None).expect("settle_withdrawal failed");
+    assert_eq!(balance(&ic, backend, user, Asset::USD).free, 5_000);
+    assert!(matches!(last_transfer(&ic, backend, user).status, TransferStatus::Failed(_)));
+    assert_eq!(balance_of(&ic, usd_ledger, user), Nat::from(1_000_000 - 5_000 - 2 * FEE));
+
+    let resp = ic.query_call(backend, admin, "reconcile", Encode!().unwrap()).unwrap();
+    assert!(Decode!(&resp, Reconciliation).unwrap().balanced);
+
+    println!("✅ Expired withdrawal settled from the ledger log");
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::Deserialize;
+use common::*;
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum OrderStatus {
+    Open,
+    Cancelled,
+    Rejected,
+    Filled,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct Order {
+    id: u64,
+    status: OrderStatus,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct OrderPage {
+    orders: Vec<Order>,
+}
+
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum VeilError {
+    UnknownMarket(u32),
+    InvalidTick { price_limit: u64, tick_size: u64 },
+    InvalidLot { amount: u64, lot_size: u64 },
+    BelowMinimumSize { amount: u64, min_size: u64 },
+    OutsidePriceBand { price_limit: u64, min: u64, max: u64 },
+    InsufficientCollateral { collateral: u64, required: u64 },
+}
+
+// Ingress errors and errors returned by the canister both come back as Err
+fn place(ic: &PocketIc, backend: Principal, user: Principal, side: OrderType, collateral: u64, amount: u64, price: u64) -> Result<u64, String> {
+    let order = OrderPayload::new(user, 1, side, amount, price);
+    let resp = ic.update_call(backend, user, "submit_order", submit_args(&order, collateral))
+        .map_err(|e| e.reject_message)?;
+    Decode!(&resp, Result<u64, VeilError>).unwrap().map_err(|e| format!("{:?}", e))
+}
+
+fn validate(ic: &PocketIc, backend: Principal, amount: u64, price: u64) -> Result<(), VeilError> {
+    let resp = ic.query_call(backend, Principal::anonymous(), "validate_order", Encode!(&BTC_USD, &amount, &price).unwrap())
+        .unwrap();
+    Decode!(&resp, Result<(), VeilError>).unwrap()
+}
+
+#[test]
+fn orders_follow_market_rules() {
+    let ic = PocketIc::new();
+
+    let backend = install_backend(&ic);
+
+    let admin = Principal::anonymous();
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+
+    // ============ DEFAULT RULES ============
+
+    // BTC markets start with 0.00001 BTC lots and a 0.0001 BTC minimum
+    let resp = ic.query_call(backend, admin, "get_trading_rules", Encode!(&BTC_USD).unwrap()).unwrap();
+    assert_eq!(
+        Decode!(&resp, TradingRules).unwrap(),
+        TradingRules { tick_size: 1, lot_size: 1_000, min_size: 10_000, max_price_deviation_bps: 0 }
+    );
+
+    // A 1-satoshi dust order does not pass
+    assert_eq!(
+        validate(&ic, backend, 1, 5_000_000),
+        Err(VeilError::BelowMinimumSize { amount: 1, min_size: 10_000 })
+    );
+    assert_eq!(
+        validate(&ic, backend, 10_500, 5_000_000),
+        Err(VeilError::InvalidLot { amount: 10_500, lot_size: 1_000 })
+    );
+    assert_eq!(validate(&ic, backend, 10_000, 5_000_000), Ok(()));
+
+    // ============ CUSTOM RULES ============
+
+    // Prices in cents per BTC, sizes in satoshis: $1,000 ticks, 0.001 BTC
+    // lots, 0.01 BTC minimum
+    let rules = TradingRules {
+        tick_size: 100_000,
+        lot_size: 100_000,
+        min_size: 1_000_000,
+        max_price_deviation_bps: 1_000,
+    };
+    let resp = ic.update_call(backend, admin, "admin_set_trading_rules", Encode!(&BTC_USD, &rules).unwrap())
+        .unwrap();
+    Decode!(&resp, Result<(), candid::Reserved>).unwrap().unwrap();
+
+    // ============ PLAINTEXT VALIDATION ============
+
+    assert_eq!(validate(&ic, backend, 1_000_000, 5_000_000), Ok(()));
+    assert_eq!(
+        validate(&ic, backend, 900_000, 5_000_000),
+        Err(VeilError::BelowMinimumSize { amount: 900_000, min_size: 1_000_000 })
+    );
+    assert_eq!(
+        validate(&ic, backend, 1_050_000, 5_000_000),
+        Err(VeilError::InvalidLot { amount: 1_050_000, lot_size: 100_000 })
+    );
+    assert_eq!(
+        validate(&ic, backend, 1_000_000, 5_050_000),
+        Err(VeilError::InvalidTick { price_limit: 5_050_000, tick_size: 100_000 })
+    );
+    assert_eq!(
+        validate(&ic, backend, 1_000_000, 0),
+        Err(VeilError::InvalidTick { price_limit: 0, tick_size: 100_000 })
+    );
+
+    // ============ SUBMISSION ============
+
+    ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    // Smallest valid Buy locks 0.01 BTC at $1,000 = 1_000 cents, smallest Sell 0.01 BTC
+    assert!(place(&ic, backend, buyer, OrderType::Buy, 999, 1_000_000, 100_000).is_err());
+    assert!(place(&ic, backend, seller, OrderType::Sell, 999_999, 1_000_000, 100_000).is_err());
+
+    // ============ REVEAL ============
+
+    let valid_buy = place(&ic, backend, buyer, OrderType::Buy, 50_000, 1_000_000, 5_000_000).unwrap();
+    let valid_sell = place(&ic, backend, seller, OrderType::Sell, 1_000_000, 1_000_000, 4_000_000).unwrap();
+    let off_lot = place(&ic, backend, seller, OrderType::Sell, 1_100_000, 1_050_000, 4_000_000).unwrap();
+
+    ic.update_call(backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    let resp = ic.query_call(
+        backend,
+        admin,
+        "get_round_orders",
+        Encode!(&BTC_USD, &1u64, &None::<()>, &100u32).unwrap(),
+    ).unwrap();
+    let statuses: Vec<(u64, OrderStatus)> = Decode!(&resp, OrderPage).unwrap()
+        .orders
+        .into_iter()
+        .map(|o| (o.id, o.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            (valid_buy, OrderStatus::Filled),
+            (valid_sell, OrderStatus::Filled),
+            (off_lot, OrderStatus::Rejected),
+        ]
+    );
+
+    // ============ PRICE BAND ============
+
+    // The round cleared between $40,000 and $50,000: a 10% band now applies
+    match validate(&ic, backend, 1_000_000, 10_000_000) {
+        Err(VeilError::OutsidePriceBand { price_limit: 10_000_000, .. }) => {}
+        other => panic!("expected a band violation, got {:?}", other),
+    }
+
+    println!("✅ Trading rules enforced");
+}
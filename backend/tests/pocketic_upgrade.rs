@@ -0,0 +1,247 @@
+mod common;
+
+use pocket_ic::PocketIc;
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::Deserialize;
+use common::*;
+
+// ============ MINIMAL BACKEND TYPES ============
+// Records only list the fields this test looks at
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum RoundState {
+    Pending,
+    Active,
+    Revealing,
+    Clearing,
+    Executing,
+    Completed,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct MarketState {
+    round_id: u64,
+    round_state: RoundState,
+    round_duration_ns: u64,
+    clearing_price_history: Vec<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct Order {
+    id: u64,
+    round_id: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct OrderCursor {
+    market_id: u32,
+    round_id: u64,
+    order_id: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct OrderPage {
+    orders: Vec<Order>,
+    next_cursor: Option<OrderCursor>,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct UserStats {
+    total_orders: u64,
+    filled_orders: u64,
+    total_surplus: u128,
+    rounds_participated: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct LeaderboardEntry {
+    user: Principal,
+    surplus: u128,
+    rank: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct LeaderboardCursor {
+    surplus: u128,
+    user: Principal,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct LeaderboardPage {
+    entries: Vec<LeaderboardEntry>,
+    next_cursor: Option<LeaderboardCursor>,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct DemoUserBalance {
+    btc_free: u64,
+    btc_locked: u64,
+    usd_free: u64,
+    usd_locked: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct ClearingResult {
+    clearing_price: u64,
+    total_volume: u64,
+}
+
+
+fn round_state(ic: &PocketIc, backend: Principal) -> MarketState {
+    let resp = ic.query_call(backend, Principal::anonymous(), "get_round_state", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+    Decode!(&resp, Option<MarketState>).unwrap().unwrap()
+}
+
+fn user_stats(ic: &PocketIc, backend: Principal, user: Principal) -> Option<UserStats> {
+    let resp = ic.query_call(backend, user, "get_user_stats", Encode!(&user).unwrap()).unwrap();
+    Decode!(&resp, Option<UserStats>).unwrap()
+}
+
+fn demo_balance(ic: &PocketIc, backend: Principal, user: Principal) -> DemoUserBalance {
+    let resp = ic.query_call(backend, user, "get_demo_balance_of", Encode!(&user).unwrap()).unwrap();
+    Decode!(&resp, DemoUserBalance).unwrap()
+}
+
+fn round_result(ic: &PocketIc, backend: Principal, round_id: u64) -> Option<ClearingResult> {
+    let resp = ic.query_call(
+        backend,
+        Principal::anonymous(),
+        "get_round_result",
+        Encode!(&BTC_USD, &round_id).unwrap(),
+    ).unwrap();
+    Decode!(&resp, Option<ClearingResult>).unwrap()
+}
+
+// Walks every page one order at a time, so the cursors are exercised too
+fn user_orders(ic: &PocketIc, backend: Principal, user: Principal) -> Vec<Order> {
+    let mut orders = Vec::new();
+    let mut cursor: Option<OrderCursor> = None;
+    loop {
+        let resp = ic.query_call(backend, user, "get_user_orders", Encode!(&user, &cursor, &1u32).unwrap())
+            .unwrap();
+        let page = Decode!(&resp, OrderPage).unwrap();
+        orders.extend(page.orders);
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return orders,
+        }
+    }
+}
+
+// Walks the global leaderboard one entry at a time
+fn leaderboard(ic: &PocketIc, backend: Principal) -> Vec<LeaderboardEntry> {
+    let mut entries = Vec::new();
+    let mut cursor: Option<LeaderboardCursor> = None;
+    loop {
+        let resp = ic.query_call(backend, Principal::anonymous(), "get_global_leaderboard", Encode!(&cursor, &1u32).unwrap())
+            .unwrap();
+        let page = Decode!(&resp, LeaderboardPage).unwrap();
+        entries.extend(page.entries);
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return entries,
+        }
+    }
+}
+
+fn round_orders(ic: &PocketIc, backend: Principal, round_id: u64) -> Vec<Order> {
+    let resp = ic.query_call(
+        backend,
+        Principal::anonymous(),
+        "get_round_orders",
+        Encode!(&BTC_USD, &round_id, &None::<OrderCursor>, &100u32).unwrap(),
+    ).unwrap();
+    let page = Decode!(&resp, OrderPage).unwrap();
+    assert!(page.next_cursor.is_none());
+    page.orders
+}
+
+fn order_count(ic: &PocketIc, backend: Principal) -> u64 {
+    let resp = ic.query_call(backend, Principal::anonymous(), "get_order_count", Encode!().unwrap())
+        .unwrap();
+    Decode!(&resp, u64).unwrap()
+}
+
+#[test]
+fn upgrade_preserves_canister_state() {
+    let ic = PocketIc::new();
+
+    let wasm = backend_wasm();
+
+    let backend = ic.create_canister();
+    ic.add_cycles(backend, 10_000_000_000_000u128);
+    ic.install_canister(backend, wasm.clone(), vec![], None);
+
+    let buyer = Principal::from_slice(&[1; 29]);
+    let seller = Principal::from_slice(&[2; 29]);
+    let admin = Principal::anonymous();
+
+    set_trading_rules(&ic, backend, &TradingRules::whole_units());
+
+    // ============ PLAY ONE ROUND ============
+
+    ic.update_call(backend, admin, "set_round_duration", Encode!(&BTC_USD, &120u64).unwrap())
+        .unwrap();
+    ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    let first_buy = submit_exact(&ic, backend, &OrderPayload::new(buyer, 1, OrderType::Buy, 100, 50));
+    submit_exact(&ic, backend, &OrderPayload::new(seller, 1, OrderType::Sell, 100, 40));
+
+    ic.update_call(backend, admin, "admin_run_clearing", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+
+    // A resting order in the next round keeps funds locked across the upgrade
+    ic.update_call(backend, admin, "admin_reset_round", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+    ic.update_call(backend, admin, "admin_start_round", Encode!(&BTC_USD).unwrap())
+        .unwrap();
+    let resting = submit_exact(&ic, backend, &OrderPayload::new(buyer, 2, OrderType::Buy, 10, 100));
+
+    let market_before = round_state(&ic, backend);
+    let buyer_stats_before = user_stats(&ic, backend, buyer);
+    let buyer_balance_before = demo_balance(&ic, backend, buyer);
+    let seller_balance_before = demo_balance(&ic, backend, seller);
+    let result_before = round_result(&ic, backend, 1);
+    let orders_before = order_count(&ic, backend);
+    let leaderboard_before = leaderboard(&ic, backend);
+
+    assert_eq!(market_before.round_id, 2);
+    assert_eq!(market_before.clearing_price_history.len(), 1);
+    assert!(buyer_stats_before.is_some());
+    assert!(result_before.is_some());
+    assert_eq!(buyer_balance_before.usd_locked, 1_000);
+    assert_eq!(leaderboard_before.iter().map(|e| e.rank).collect::<Vec<_>>(), vec![1, 2]);
+    assert!(leaderboard_before[0].surplus >= leaderboard_before[1].surplus);
+
+    // Round and owner indexes
+    let buyer_orders = vec![Order { id: first_buy, round_id: 1 }, Order { id: resting, round_id: 2 }];
+    assert_eq!(user_orders(&ic, backend, buyer), buyer_orders);
+    assert_eq!(round_orders(&ic, backend, 1).len(), 2);
+    assert_eq!(round_orders(&ic, backend, 2).len(), 1);
+
+    // ============ UPGRADE ============
+
+    ic.upgrade_canister(backend, wasm, Encode!().unwrap(), None)
+        .expect("upgrade succeeds");
+
+    // ============ NOTHING IS LOST ============
+
+    assert_eq!(round_state(&ic, backend), market_before);
+    assert_eq!(user_stats(&ic, backend, buyer), buyer_stats_before);
+    assert_eq!(demo_balance(&ic, backend, buyer), buyer_balance_before);
+    assert_eq!(demo_balance(&ic, backend, seller), seller_balance_before);
+    assert_eq!(round_result(&ic, backend, 1), result_before);
+    assert_eq!(order_count(&ic, backend), orders_before);
+    assert_eq!(user_orders(&ic, backend, buyer), buyer_orders);
+    assert_eq!(leaderboard(&ic, backend), leaderboard_before);
+
+    // Order ids keep counting instead of colliding with stored orders
+    let next = submit_exact(&ic, backend, &OrderPayload::new(seller, 2, OrderType::Sell, 10, 90));
+    assert_eq!(next, resting + 1);
+    assert_eq!(order_count(&ic, backend), orders_before + 1);
+    assert_eq!(round_orders(&ic, backend, 2).len(), 2);
+
+    println!("✅ Upgrade preserved state");
+}
@@ -1,17 +1,93 @@
-use pocket_ic::PocketIc;
-use candid::{Encode, Decode};
-use std::process::Command;
+use pocket_ic::PocketIcBuilder;
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_vetkeys::{DerivedPublicKey, EncryptedVetKey, IbeCiphertext, IbeIdentity, IbeSeed, TransportSecretKey};
+use serde::Deserialize;
 
-// This spins up a local IC replica exactly like mainnet
+#[derive(CandidType, Deserialize)]
+struct EngineConfig {
+    backend: Option<Principal>,
+    key_name: Option<String>,
+}
+
+fn round_identity(market_id: u32, round_id: u64) -> Vec<u8> {
+    let mut identity = b"ROUND:".to_vec();
+    identity.extend_from_slice(&market_id.to_be_bytes());
+    identity.extend_from_slice(&round_id.to_be_bytes());
+    identity
+}
+
+// This spins up a local IC with a subnet holding the vetKD test keys
 #[test]
-fn vetkd_public_key_works() {
-    let mut pic = PocketIc::new();
+fn vetkd_round_key_verifies_end_to_end() {
+    let pic = PocketIcBuilder::new()
+        .with_application_subnet()
+        .with_ii_subnet()
+        .build();
+
+    let vetkd_wasm = std::fs::read(
+        "vetkeys_engine/target/wasm32-unknown-unknown/release/vetkeys_engine.wasm"
+    ).expect("Build vetkeys_engine first");
+
+    // The backend is the only principal allowed to derive keys
+    let backend = Principal::from_slice(&[7; 29]);
+    let config = Some(EngineConfig {
+        backend: Some(backend),
+        key_name: Some("test_key_1".to_string()),
+    });
 
-    // Create empty canister
     let canister_id = pic.create_canister();
+    pic.add_cycles(canister_id, 100_000_000_000_000);
+    pic.install_canister(canister_id, vetkd_wasm, Encode!(&config).unwrap(), None);
+
+    // ============ PUBLIC KEY ============
+
+    let resp = pic.update_call(canister_id, backend, "get_public_key", Encode!().unwrap())
+        .unwrap();
+    let pk_bytes = Decode!(&resp, Result<Vec<u8>, String>).unwrap().unwrap();
+    let dpk = DerivedPublicKey::deserialize(&pk_bytes).expect("valid derived public key");
+
+    // ============ ROUND KEY ============
+
+    let tsk = TransportSecretKey::from_seed(vec![42; 32]).unwrap();
+    let (market_id, round_id) = (0u32, 7u64);
+
+    let resp = pic.update_call(
+        canister_id,
+        backend,
+        "derive_round_key",
+        Encode!(&market_id, &round_id, &tsk.public_key()).unwrap(),
+    ).unwrap();
+    let encrypted = Decode!(&resp, Result<Vec<u8>, String>).unwrap().unwrap();
+
+    let vetkey = EncryptedVetKey::deserialize(&encrypted)
+        .unwrap()
+        .decrypt_and_verify(&tsk, &dpk, &round_identity(market_id, round_id))
+        .expect("round key verifies against the derived public key");
+    assert!(!vetkey.signature_bytes().is_empty());
+
+    // An order encrypted to the round identity opens with the round key
+    let order = b"sealed order";
+    let ciphertext = IbeCiphertext::encrypt(
+        &dpk,
+        &IbeIdentity::from_bytes(&round_identity(market_id, round_id)),
+        order,
+        &IbeSeed::from_bytes(&[9; 32]).unwrap(),
+    );
+    let decrypted = IbeCiphertext::deserialize(&ciphertext.serialize())
+        .unwrap()
+        .decrypt(&vetkey)
+        .expect("order decrypts with the round key");
+    assert_eq!(decrypted, order.to_vec());
+
+    // ============ ACCESS CONTROL ============
 
-    // Add cycles
-    pic.add_cycles(canister_id, 2_000_000_000_000);
+    let resp = pic.update_call(
+        canister_id,
+        Principal::anonymous(),
+        "derive_round_key",
+        Encode!(&market_id, &round_id, &tsk.public_key()).unwrap(),
+    ).unwrap();
+    assert!(Decode!(&resp, Result<Vec<u8>, String>).unwrap().is_err());
 
-    println!("✅ PocketIC started. Canister: {:?}", canister_id);
+    println!("✅ vetKD round key derived and verified. Canister: {:?}", canister_id);
 }
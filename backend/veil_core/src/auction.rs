@@ -0,0 +1,503 @@
+use crate::types::{
+    Order, OrderType, OrderMatch, ClearingResult, AllocationBasis, MarketId, PriceRule, PriceSelection,
+    RoundId, Timestamp,
+};
+use crate::pricing::{quote_value, Rounding};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClearingError {
+    OneSided { buy_orders: usize, sell_orders: usize },
+    NoOverlap,
+    Overflow,  // Sizes or surplus beyond what the result can carry
+}
+
+impl fmt::Display for ClearingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClearingError::OneSided { buy_orders, sell_orders } => {
+                write!(f, "Cannot clear: {} buy orders, {} sell orders", buy_orders, sell_orders)
+            }
+            ClearingError::NoOverlap => write!(f, "No clearing price found - orders don't overlap"),
+            ClearingError::Overflow => write!(f, "Arithmetic overflow while clearing"),
+        }
+    }
+}
+
+/// Everything a round is cleared with besides its orders
+#[derive(Clone, Copy, Debug)]
+pub struct ClearingParams {
+    pub market_id: MarketId,
+    pub round_id: RoundId,
+    pub rule: PriceRule,
+    pub tick_size: u64,
+    pub base_decimals: u8,             // prices are per whole base unit, see `pricing`
+    pub reference_price: Option<u64>,  // last clearing price of the market
+    pub timestamp: Timestamp,          // recorded in the result
+}
+
+/// Clear a round with the standard call-auction rules: the price maximizes
+/// matched volume, then minimizes the imbalance between demand and supply.
+/// Prices still tied form a range, settled by `params.rule`. Prices between
+/// two limit prices are candidates too, on the `tick_size` grid. Progress is
+/// reported through `log`.
+pub fn find_clearing_price_and_match(
+    orders: Vec<Order>,
+    params: &ClearingParams,
+    log: impl Fn(String),
+) -> Result<ClearingResult, ClearingError> {
+    let ClearingParams { market_id, round_id, rule, tick_size, base_decimals, reference_price, timestamp } = *params;
+    log(format!("Starting clearing for market {} round {} with {} orders", market_id, round_id, orders.len()));
+    
+    // 1. Separate buy and sell orders
+    let (mut buy_orders, mut sell_orders): (Vec<_>, Vec<_>) = orders
+        .into_iter()
+        .partition(|o| matches!(o.order_type, OrderType::Buy));
+    
+    if buy_orders.is_empty() || sell_orders.is_empty() {
+        return Err(ClearingError::OneSided {
+            buy_orders: buy_orders.len(),
+            sell_orders: sell_orders.len(),
+        });
+    }
+    
+    // 2. Sort orders (price priority, then order id so ties never depend on storage order)
+    buy_orders.sort_by(|a, b| b.price_limit.cmp(&a.price_limit).then(a.id.cmp(&b.id)));  // Highest first
+    sell_orders.sort_by(|a, b| a.price_limit.cmp(&b.price_limit).then(a.id.cmp(&b.id))); // Lowest first
+    
+    // 3. Aggregate each side into price levels
+    let bids = Ladder::new(OrderType::Buy, &buy_orders)?;
+    let asks = Ladder::new(OrderType::Sell, &sell_orders)?;
+    
+    // 4. Find clearing price
+    let mut all_prices: Vec<u64> = bids.prices().chain(asks.prices()).collect();
+    all_prices.sort();
+    all_prices.dedup();
+    
+    let segments = price_segments(
+        &all_prices,
+        tick_size.max(1),
+        |price| bids.quantity_at(price),
+        |price| asks.quantity_at(price),
+    );
+    
+    let max_volume = segments.iter().map(|s| s.volume).max().unwrap_or(0);
+    if max_volume == 0 {
+        return Err(ClearingError::NoOverlap);
+    }
+    let min_imbalance = segments
+        .iter()
+        .filter(|s| s.volume == max_volume)
+        .map(|s| s.imbalance)
+        .min()
+        .unwrap_or(0);
+    let best: Vec<&Segment> = segments
+        .iter()
+        .filter(|s| s.volume == max_volume && s.imbalance == min_imbalance)
+        .collect();
+    
+    let range_low = best[0].low;
+    let range_high = best[best.len() - 1].high;
+    let midpoint = range_low + (range_high - range_low) / 2;
+    let target = match (rule, reference_price) {
+        (PriceRule::ClosestToReference, Some(reference)) => reference,
+        _ => midpoint,
+    };
+    let best_price = closest_price(&best, target, tick_size.max(1));
+    
+    log(format!(
+        "Found clearing price: {}, volume: {}, imbalance: {}, range: [{}, {}]",
+        best_price,
+        max_volume,
+        min_imbalance,
+        range_low,
+        range_high
+    ));
+    
+    // 5. Match orders at clearing price, valuing fills in quote units
+    let value = |amount: u64, price: u64| quote_value(base_decimals, amount, price, Rounding::Down);
+    let mut matches = Vec::new();
+    let mut total_surplus = 0u128;
+    
+    // Match buy orders
+    let buy_fills = allocate_side(&bids, max_volume, best_price);
+    for (order, m) in buy_orders.iter().zip(buy_fills) {
+        if !m.filled {
+            matches.push(m);
+            continue;
+        }
+        let surplus = value(m.fill_amount, order.price_limit - best_price);
+        total_surplus = total_surplus.checked_add(surplus).ok_or(ClearingError::Overflow)?;
+        matches.push(OrderMatch {
+            fill_price: best_price,
+            notional: value(m.fill_amount, best_price),
+            surplus,
+            ..m
+        });
+    }
+    
+    // Match sell orders
+    let sell_fills = allocate_side(&asks, max_volume, best_price);
+    for (order, m) in sell_orders.iter().zip(sell_fills) {
+        if !m.filled {
+            matches.push(m);
+            continue;
+        }
+        let surplus = value(m.fill_amount, best_price - order.price_limit);
+        total_surplus = total_surplus.checked_add(surplus).ok_or(ClearingError::Overflow)?;
+        matches.push(OrderMatch {
+            fill_price: best_price,
+            notional: value(m.fill_amount, best_price),
+            surplus,
+            ..m
+        });
+    }
+    
+    log(format!("Matched {} orders with {} total surplus", matches.len(), total_surplus));
+    
+    Ok(ClearingResult {
+        market_id,
+        round_id,
+        clearing_price: best_price,
+        total_volume: max_volume,
+        total_notional: value(max_volume, best_price),
+        total_surplus,
+        matches,
+        timestamp,
+        price_selection: Some(PriceSelection {
+            rule,
+            reference_price,
+            imbalance: min_imbalance,
+            range_low,
+            range_high,
+        }),
+    })
+}
+
+// ============================================================================
+// PRICE LADDERS
+// ============================================================================
+
+/// One side of the book aggregated by limit price, best price first
+struct Ladder<'a> {
+    side: OrderType,
+    levels: Vec<Level<'a>>,
+}
+
+/// The orders sharing one limit price, in order id order
+struct Level<'a> {
+    price: u64,
+    quantity: u64,    // total size at this price
+    cumulative: u64,  // total size at this price or better
+    orders: &'a [Order],
+}
+
+impl<'a> Ladder<'a> {
+    /// `orders` must be in price priority for `side`. Fails if the side's
+    /// total size does not fit a u64.
+    fn new(side: OrderType, orders: &'a [Order]) -> Result<Self, ClearingError> {
+        let mut cumulative = 0u64;
+        let levels = orders
+            .chunk_by(|a, b| a.price_limit == b.price_limit)
+            .map(|orders| {
+                let quantity = orders
+                    .iter()
+                    .try_fold(0u64, |sum, o| sum.checked_add(o.amount))
+                    .ok_or(ClearingError::Overflow)?;
+                cumulative = cumulative.checked_add(quantity).ok_or(ClearingError::Overflow)?;
+                Ok(Level { price: orders[0].price_limit, quantity, cumulative, orders })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Ladder { side, levels })
+    }
+
+    /// Whether an order limited at `limit` trades at `price`
+    fn crosses(&self, limit: u64, price: u64) -> bool {
+        match self.side {
+            OrderType::Buy => limit >= price,
+            OrderType::Sell => limit <= price,
+        }
+    }
+
+    /// Size willing to trade at `price`: demand for bids, supply for asks.
+    /// Crossing levels form a prefix of the ladder.
+    fn quantity_at(&self, price: u64) -> u64 {
+        let crossing = self.levels.partition_point(|l| self.crosses(l.price, price));
+        crossing.checked_sub(1).map_or(0, |last| self.levels[last].cumulative)
+    }
+
+    fn prices(&self) -> impl Iterator<Item = u64> + '_ {
+        self.levels.iter().map(|l| l.price)
+    }
+}
+
+// ============================================================================
+// PRICE SELECTION
+// ============================================================================
+
+/// Candidate clearing prices sharing the same demand and supply: a limit
+/// price, or the tick-grid prices strictly between two neighbouring ones
+struct Segment {
+    low: u64,
+    high: u64,
+    volume: u64,
+    imbalance: u64,
+}
+
+/// Split the price axis at `prices` (sorted, distinct limit prices) into
+/// segments, ascending. Between two limits, demand is that of the upper one
+/// and supply that of the lower one.
+fn price_segments(
+    prices: &[u64],
+    tick_size: u64,
+    demand_at: impl Fn(u64) -> u64,
+    supply_at: impl Fn(u64) -> u64,
+) -> Vec<Segment> {
+    let segment = |low, high, demand: u64, supply: u64| Segment {
+        low,
+        high,
+        volume: demand.min(supply),
+        imbalance: demand.abs_diff(supply),
+    };
+
+    let mut segments = Vec::with_capacity(prices.len() * 2);
+    for (i, &price) in prices.iter().enumerate() {
+        segments.push(segment(price, price, demand_at(price), supply_at(price)));
+
+        let Some(&next) = prices.get(i + 1) else { continue };
+        let low = (price / tick_size + 1).saturating_mul(tick_size);
+        let high = (next - 1) / tick_size * tick_size;
+        if low <= high {
+            segments.push(segment(low, high, demand_at(next), supply_at(price)));
+        }
+    }
+    segments
+}
+
+/// The price within `segments` nearest to `target`, the lower one on ties.
+/// Inside a range, only prices on the tick grid are considered.
+fn closest_price(segments: &[&Segment], target: u64, tick_size: u64) -> u64 {
+    let mut best = segments[0].low;
+    for segment in segments {
+        let clamped = target.clamp(segment.low, segment.high);
+        let below = (clamped / tick_size * tick_size).max(segment.low);
+        let above = below.saturating_add(tick_size).min(segment.high);
+        for price in [below, above] {
+            let closer = price.abs_diff(target) < best.abs_diff(target);
+            let tied_lower = price.abs_diff(target) == best.abs_diff(target) && price < best;
+            if closer || tied_lower {
+                best = price;
+            }
+        }
+    }
+    best
+}
+
+// ============================================================================
+// ALLOCATION
+// ============================================================================
+
+/// Distribute `volume` across one side of the book at `price`.
+///
+/// Price levels strictly better than the marginal level fill
+/// completely. The marginal level — the first level whose total size exceeds
+/// the volume still left — is shared pro-rata by size:
+///
+/// * each order first receives `floor(remaining * amount / level_quantity)`;
+/// * the units lost to rounding (always fewer than the number of orders at the
+///   level) are handed out one at a time, largest fractional remainder first,
+///   lower order id first on equal remainders.
+///
+/// Levels behind the marginal level, and levels that do not cross `price`,
+/// receive nothing. Matches are returned in ladder order, with
+/// `fill_price`/`notional`/`surplus` left at zero for the caller to price.
+fn allocate_side(ladder: &Ladder, volume: u64, price: u64) -> Vec<OrderMatch> {
+    let mut matches = Vec::new();
+    let mut remaining = volume;
+
+    for level in &ladder.levels {
+        if remaining == 0 || !ladder.crosses(level.price, price) {
+            matches.extend(level.orders.iter().map(|o| unfilled(o.id, o.amount)));
+            continue;
+        }
+
+        let level_quantity = level.quantity;
+
+        if level_quantity <= remaining {
+            for order in level.orders {
+                matches.push(OrderMatch {
+                    order_id: order.id,
+                    filled: true,
+                    requested_amount: order.amount,
+                    fill_amount: order.amount,
+                    fill_price: 0,
+                    notional: 0,
+                    surplus: 0,
+                    allocation: AllocationBasis::Full,
+                    fee: 0,
+                });
+            }
+            remaining -= level_quantity;
+            continue;
+        }
+
+        // Marginal level: pro-rata with largest-remainder rounding
+        let level_allocation = remaining;
+        let mut shares: Vec<(u64, u64, &Order)> = level
+            .orders
+            .iter()
+            .map(|o| {
+                let scaled = level_allocation as u128 * o.amount as u128;
+                let base = (scaled / level_quantity as u128) as u64;
+                let fraction = (scaled % level_quantity as u128) as u64;
+                (base, fraction, o)
+            })
+            .collect();
+
+        let allocated: u64 = shares.iter().map(|(base, _, _)| base).sum();
+        let leftover = level_allocation - allocated;
+
+        let mut by_remainder: Vec<usize> = (0..shares.len()).collect();
+        by_remainder.sort_by(|&a, &b| {
+            shares[b].1.cmp(&shares[a].1).then(shares[a].2.id.cmp(&shares[b].2.id))
+        });
+        let mut bonus = vec![0u64; shares.len()];
+        for &i in by_remainder.iter().take(leftover as usize) {
+            shares[i].0 += 1;
+            bonus[i] = 1;
+        }
+
+        for (i, (fill_amount, _, order)) in shares.into_iter().enumerate() {
+            matches.push(OrderMatch {
+                order_id: order.id,
+                filled: fill_amount > 0,
+                requested_amount: order.amount,
+                fill_amount,
+                fill_price: 0,
+                notional: 0,
+                surplus: 0,
+                allocation: AllocationBasis::ProRata {
+                    level_quantity,
+                    level_allocation,
+                    rounding_units: bonus[i],
+                },
+                fee: 0,
+            });
+        }
+        remaining = 0;
+    }
+
+    matches
+}
+
+fn unfilled(order_id: u64, requested_amount: u64) -> OrderMatch {
+    OrderMatch {
+        order_id,
+        filled: false,
+        requested_amount,
+        fill_amount: 0,
+        fill_price: 0,
+        notional: 0,
+        surplus: 0,
+        allocation: AllocationBasis::NotFilled,
+        fee: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OrderStatus;
+    use candid::Principal;
+
+    fn order(id: u64, order_type: OrderType, amount: u64, price_limit: u64) -> Order {
+        Order {
+            id,
+            market_id: 0,
+            round_id: 1,
+            owner: Principal::anonymous(),
+            order_type,
+            collateral: 0,
+            amount,
+            price_limit,
+            created_at: 0,
+            encrypted_payload: Vec::new(),
+            commitment_hash: String::new(),
+            status: OrderStatus::Open,
+            revision: 0,
+            updated_at: 0,
+            escrow_nonce: 0,
+        }
+    }
+
+    fn params(rule: PriceRule, reference_price: Option<u64>) -> ClearingParams {
+        ClearingParams {
+            market_id: 0,
+            round_id: 1,
+            rule,
+            tick_size: 1,
+            base_decimals: 0,
+            reference_price,
+            timestamp: 0,
+        }
+    }
+
+    fn clear(orders: Vec<Order>, params: &ClearingParams) -> Result<ClearingResult, ClearingError> {
+        find_clearing_price_and_match(orders, params, |_| {})
+    }
+
+    fn fills(result: &ClearingResult) -> Vec<(u64, u64)> {
+        result.matches.iter().map(|m| (m.order_id, m.fill_amount)).collect()
+    }
+
+    #[test]
+    fn midpoint_of_tied_range() {
+        let orders = vec![order(1, OrderType::Buy, 10, 50), order(2, OrderType::Sell, 10, 40)];
+        let result = clear(orders, &params(PriceRule::Midpoint, Some(41))).unwrap();
+
+        assert_eq!(result.clearing_price, 45);
+        assert_eq!(result.total_volume, 10);
+        assert_eq!(result.total_surplus, 100);
+        let selection = result.price_selection.unwrap();
+        assert_eq!((selection.range_low, selection.range_high, selection.imbalance), (40, 50, 0));
+    }
+
+    #[test]
+    fn closest_to_reference_price() {
+        let book = || vec![order(1, OrderType::Buy, 10, 50), order(2, OrderType::Sell, 10, 40)];
+
+        let result = clear(book(), &params(PriceRule::ClosestToReference, Some(41))).unwrap();
+        assert_eq!(result.clearing_price, 41);
+
+        let result = clear(book(), &params(PriceRule::ClosestToReference, Some(90))).unwrap();
+        assert_eq!(result.clearing_price, 50);
+
+        // Without a reference the midpoint applies
+        let result = clear(book(), &params(PriceRule::ClosestToReference, None)).unwrap();
+        assert_eq!(result.clearing_price, 45);
+    }
+
+    #[test]
+    fn marginal_level_shared_pro_rata() {
+        // 10 units of supply against 3 + 3 + 3 + 2 of demand at one price
+        let orders = vec![
+            order(1, OrderType::Buy, 3, 100),
+            order(2, OrderType::Buy, 3, 100),
+            order(3, OrderType::Buy, 3, 100),
+            order(4, OrderType::Buy, 2, 100),
+            order(5, OrderType::Sell, 10, 100),
+        ];
+        let result = clear(orders, &params(PriceRule::Midpoint, None)).unwrap();
+
+        assert_eq!(result.total_volume, 10);
+        // floor(10 * 3 / 11) = 2 each, floor(10 * 2 / 11) = 1; the three
+        // leftover units go to the largest remainders (8, 8, 8 vs 9 for order 4)
+        assert_eq!(fills(&result), vec![(1, 3), (2, 3), (3, 2), (4, 2), (5, 10)]);
+        match &result.matches[0].allocation {
+            AllocationBasis::ProRata { level_quantity, level_allocation, rounding_units } => {
+                assert_eq!((*level_quantity, *level_allocation, *rounding_units), (11, 10, 1));
+            }
+            other => panic!("expected a pro-rata fill, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,11 @@
+//! Clearing engine of the VEIL batch auction, free of IC dependencies.
+//!
+//! The canister feeds it revealed orders, the current time and a logger;
+//! host tools and tests can run exactly the same matching natively.
+
+pub mod auction;
+pub mod pricing;
+pub mod settlement;
+pub mod types;
+
+pub use auction::{find_clearing_price_and_match, ClearingError, ClearingParams};
@@ -0,0 +1,45 @@
+use crate::types::Asset;
+
+// ============================================================================
+// PRICES AND UNITS
+// ============================================================================
+//
+// Sizes and balances are integers in an asset's smallest unit (satoshi,
+// gwei, cent). ETH is counted in gwei rather than wei, as a u64 of wei tops
+// out near 18.4 ETH; the escrow converts to wei at the ledger. A price is
+// in smallest quote units per *whole* base unit, so BTC/USD at $67,500.00 is
+// 6_750_000 (cents per BTC) and ETH/BTC at 0.05 is 5_000_000 (satoshis per
+// ETH). The quote value of `amount` at `price` is
+//
+//     amount * price / 10^base_decimals
+//
+// Rounding always favours escrow: what a buyer locks or pays rounds up, what
+// a seller receives rounds down, and so do the notional and surplus reported.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+impl Asset {
+    /// Decimal places of the asset's smallest unit
+    pub fn decimals(&self) -> u8 {
+        match self {
+            Asset::BTC => 8,   // satoshi
+            Asset::ETH => 9,   // gwei
+            Asset::USD => 2,   // cent
+        }
+    }
+}
+
+/// Quote value, in smallest quote units, of `amount` smallest base units at
+/// `price` for a base asset with `base_decimals`
+pub fn quote_value(base_decimals: u8, amount: u64, price: u64, rounding: Rounding) -> u128 {
+    let scale = 10u128.pow(base_decimals as u32);
+    let raw = amount as u128 * price as u128;
+    match rounding {
+        Rounding::Down => raw / scale,
+        Rounding::Up => raw.div_ceil(scale),
+    }
+}
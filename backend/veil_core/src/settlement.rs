@@ -0,0 +1,214 @@
+use crate::pricing::{quote_value, Rounding};
+use crate::types::{Asset, Market, Order, OrderId, OrderMatch, OrderType};
+use std::fmt;
+
+// ============================================================================
+// SETTLEMENT MATH
+// ============================================================================
+//
+// What escrow locks for an order and what it pays out once the order fills.
+// Moving the funds is left to the caller.
+//
+// A fee, in basis points, is withheld from what an order receives: buyers pay
+// it in base, sellers in quote. It rounds down, so a fill too small to owe a
+// whole unit pays none.
+
+/// Basis points in a whole; the highest fee rate
+pub const MAX_FEE_BPS: u32 = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SettlementError {
+    Overflow,
+    CostExceedsLock { order_id: OrderId, cost: u128, reserved: u64 },
+    FillExceedsLock { order_id: OrderId, fill_amount: u64, reserved: u64 },
+    PriceMismatch { order_id: OrderId, fill_price: u64, clearing_price: u64 },
+    VolumeMismatch { bought: u128, sold: u128 },
+    PaysOutMoreThanSpent { asset: Asset, paid: u128, spent: u128 },
+    FeeRateTooHigh { fee_bps: u32 },
+}
+
+impl fmt::Display for SettlementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettlementError::Overflow => write!(f, "Arithmetic overflow"),
+            SettlementError::CostExceedsLock { order_id, cost, reserved } => write!(
+                f,
+                "Settlement invariant violated for BUY order {}: cost {} > reserved {}",
+                order_id, cost, reserved
+            ),
+            SettlementError::FillExceedsLock { order_id, fill_amount, reserved } => write!(
+                f,
+                "Settlement invariant violated for SELL order {}: fill {} > reserved {}",
+                order_id, fill_amount, reserved
+            ),
+            SettlementError::PriceMismatch { order_id, fill_price, clearing_price } => write!(
+                f,
+                "Order {} filled at {} instead of the clearing price {}",
+                order_id, fill_price, clearing_price
+            ),
+            SettlementError::VolumeMismatch { bought, sold } => {
+                write!(f, "Round buys {} but sells {}", bought, sold)
+            }
+            SettlementError::PaysOutMoreThanSpent { asset, paid, spent } => {
+                write!(f, "Round pays out {} {:?} but its orders spend {}", paid, asset, spent)
+            }
+            SettlementError::FeeRateTooHigh { fee_bps } => {
+                write!(f, "Fee rate {} bps exceeds {} bps", fee_bps, MAX_FEE_BPS)
+            }
+        }
+    }
+}
+
+/// Funds moved when an order settles: the whole lock is released, `received`
+/// is credited in `received_asset` net of `fee`, and `refund` returned in
+/// `locked_asset`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settlement {
+    pub locked_asset: Asset,
+    pub reserved: u64,
+    pub received_asset: Asset,
+    pub received: u64,
+    pub refund: u64,
+    pub fee: u64,
+}
+
+impl Settlement {
+    /// Part of the lock spent on the fill, passed to the other side
+    pub fn paid(&self) -> u64 {
+        self.reserved - self.refund
+    }
+}
+
+/// Fee owed on `gross` at `fee_bps`, rounded down
+pub fn fee_on(gross: u64, fee_bps: u32) -> Result<u64, SettlementError> {
+    if fee_bps > MAX_FEE_BPS {
+        return Err(SettlementError::FeeRateTooHigh { fee_bps });
+    }
+    Ok((gross as u128 * fee_bps as u128 / MAX_FEE_BPS as u128) as u64)
+}
+
+/// Asset an order's collateral is locked in: quote for BUY, base for SELL
+pub fn locked_asset(market: &Market, order_type: &OrderType) -> Asset {
+    match order_type {
+        OrderType::Buy => market.quote.clone(),
+        OrderType::Sell => market.base.clone(),
+    }
+}
+
+/// Collateral a revealed order needs: BUY needs the quote value of
+/// `amount` at `price_limit` (rounded up), SELL needs base = amount
+pub fn required_lock(
+    market: &Market,
+    order_type: &OrderType,
+    amount: u64,
+    price_limit: u64,
+) -> Result<(Asset, u64), SettlementError> {
+    let required = match order_type {
+        OrderType::Buy => {
+            let cost = quote_value(market.base.decimals(), amount, price_limit, Rounding::Up);
+            u64::try_from(cost).map_err(|_| SettlementError::Overflow)?
+        }
+        OrderType::Sell => amount,
+    };
+    Ok((locked_asset(market, order_type), required))
+}
+
+/// Settle `fill` of `order` at the clearing price, withholding `fee_bps` of
+/// what it receives
+pub fn settle(
+    order: &Order,
+    market: &Market,
+    fill: &OrderMatch,
+    clearing_price: u64,
+    fee_bps: u32,
+) -> Result<Settlement, SettlementError> {
+    let reserved = order.collateral;
+    if fill.fill_amount > 0 && fill.fill_price != clearing_price {
+        return Err(SettlementError::PriceMismatch {
+            order_id: order.id,
+            fill_price: fill.fill_price,
+            clearing_price,
+        });
+    }
+
+    // What the owner receives for the filled part, and what is left of the lock
+    let (received_asset, received, refund) = match order.order_type {
+        OrderType::Buy => {
+            let cost = quote_value(market.base.decimals(), fill.fill_amount, clearing_price, Rounding::Up);
+            if cost > reserved as u128 {
+                return Err(SettlementError::CostExceedsLock { order_id: order.id, cost, reserved });
+            }
+
+            // Buyer pays 'cost' and gets the base asset
+            (market.base.clone(), fill.fill_amount, reserved - cost as u64)
+        }
+        OrderType::Sell => {
+            let proceeds = quote_value(market.base.decimals(), fill.fill_amount, clearing_price, Rounding::Down);
+            let proceeds = u64::try_from(proceeds).map_err(|_| SettlementError::Overflow)?;
+
+            let unsold = reserved.checked_sub(fill.fill_amount).ok_or(SettlementError::FillExceedsLock {
+                order_id: order.id,
+                fill_amount: fill.fill_amount,
+                reserved,
+            })?;
+
+            // Seller gets proceeds in quote, unsold base is returned
+            (market.quote.clone(), proceeds, unsold)
+        }
+    };
+
+    let fee = fee_on(received, fee_bps)?;
+    Ok(Settlement {
+        locked_asset: locked_asset(market, &order.order_type),
+        reserved,
+        received_asset,
+        received: received - fee,
+        refund,
+        fee,
+    })
+}
+
+/// Settle every fill of a round at its clearing price. Fails, before anything
+/// moves, unless the round buys exactly what it sells and, per asset, pays
+/// out no more than its orders spend (rounding leaves the difference behind).
+/// Each order pays the fee rate `fee_bps` gives it; fees count as paid out.
+pub fn settle_round(
+    market: &Market,
+    fills: &[(Order, OrderMatch)],
+    clearing_price: u64,
+    fee_bps: impl Fn(&Order) -> u32,
+) -> Result<Vec<Settlement>, SettlementError> {
+    let volume = |side: OrderType| -> u128 {
+        fills
+            .iter()
+            .filter(|(order, _)| order.order_type == side)
+            .map(|(_, fill)| fill.fill_amount as u128)
+            .sum()
+    };
+    let (bought, sold) = (volume(OrderType::Buy), volume(OrderType::Sell));
+    if bought != sold {
+        return Err(SettlementError::VolumeMismatch { bought, sold });
+    }
+
+    let settlements = fills
+        .iter()
+        .map(|(order, fill)| settle(order, market, fill, clearing_price, fee_bps(order)))
+        .collect::<Result<Vec<Settlement>, SettlementError>>()?;
+
+    for asset in [&market.base, &market.quote] {
+        let spent: u128 = settlements
+            .iter()
+            .filter(|s| s.locked_asset == *asset)
+            .map(|s| s.paid() as u128)
+            .sum();
+        let paid: u128 = settlements
+            .iter()
+            .filter(|s| s.received_asset == *asset)
+            .map(|s| s.received as u128 + s.fee as u128)
+            .sum();
+        if paid > spent {
+            return Err(SettlementError::PaysOutMoreThanSpent { asset: asset.clone(), paid, spent });
+        }
+    }
+    Ok(settlements)
+}
@@ -0,0 +1,180 @@
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+pub type OrderId = u64;
+pub type RoundId = u64;
+pub type MarketId = u32;
+pub type Timestamp = u64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Asset {
+    BTC,
+    ETH,
+    USD,
+}
+
+impl Asset {
+    pub const ALL: [Asset; 3] = [Asset::BTC, Asset::ETH, Asset::USD];
+}
+
+// A trading pair: orders buy or sell `base`, priced in `quote`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Market {
+    pub id: MarketId,
+    pub base: Asset,
+    pub quote: Asset,
+    pub symbol: String,  // e.g. "BTC/USD"
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OrderType {
+    Buy,
+    Sell,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum OrderStatus {
+    Open,       // Resting in the current round
+    Cancelled,  // Withdrawn by the owner, escrow released
+    Rejected,   // Failed decryption or validation at reveal, escrow released
+    Expired,    // Round failed or was aborted before it could fill, escrow released
+    Filled,           // Settled, filled in full
+    PartiallyFilled,  // Settled, filled in part and the rest refunded
+    Unfilled,         // Settled without a fill, escrow refunded
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct Order {
+    pub id: OrderId,
+    pub market_id: MarketId,
+    pub round_id: RoundId,
+    pub owner: Principal,
+    pub order_type: OrderType,
+    pub collateral: u64,       // Escrow locked at submission (quote for BUY, base for SELL)
+    pub amount: u64,           // Base amount in smallest units (satoshis/gwei), 0 until revealed
+    pub price_limit: u64,      // Smallest quote units per whole base unit (6_750_000 = $67,500.00/BTC), 0 until revealed
+    pub created_at: Timestamp,
+    pub encrypted_payload: Vec<u8>,
+    pub commitment_hash: String,  // Hash of unencrypted order for verification
+    pub status: OrderStatus,
+    pub revision: u32,            // Bumped on every amendment
+    pub updated_at: Timestamp,
+    pub escrow_nonce: u32,        // Tags the escrow memos of this version's lock; never reused
+}
+
+// Why an order received the fill it did
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AllocationBasis {
+    NotFilled,  // Limit did not cross, or no volume left for its price level
+    Full,       // Price level better than the marginal level, filled completely
+    ProRata {
+        level_quantity: u64,    // Total size resting at the marginal price level
+        level_allocation: u64,  // Volume left for the level, shared by size
+        rounding_units: u64,    // Extra units from largest-remainder rounding (0 or 1)
+    },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OrderMatch {
+    pub order_id: OrderId,
+    pub filled: bool,
+    pub requested_amount: u64,
+    pub fill_amount: u64,
+    pub fill_price: u64,
+    pub notional: u128,  // Value of fill_amount at fill_price, in quote units (see `pricing`)
+    pub surplus: u128,   // Savings for buyer or extra earnings for seller
+    pub allocation: AllocationBasis,
+    pub fee: u64,        // Protocol fee withheld at settlement, in the asset received
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ClearingResult {
+    pub market_id: MarketId,
+    pub round_id: RoundId,
+    pub clearing_price: u64,
+    pub total_volume: u64,
+    pub total_notional: u128,  // Value of total_volume at clearing_price, in quote units
+    pub total_surplus: u128,
+    pub matches: Vec<OrderMatch>,
+    pub timestamp: Timestamp,
+    pub price_selection: Option<PriceSelection>,  // None for rounds cleared before it was recorded
+}
+
+// Tie-breaker among prices with the same volume and imbalance
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PriceRule {
+    #[default]
+    Midpoint,            // middle of the tied range
+    ClosestToReference,  // nearest the last clearing price, else the midpoint
+}
+
+// How a round's clearing price was chosen
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PriceSelection {
+    pub rule: PriceRule,
+    pub reference_price: Option<u64>,  // last clearing price of the market, if any
+    pub imbalance: u64,                // |demand - supply| at the clearing price
+    pub range_low: u64,                // lowest price with the same volume and imbalance
+    pub range_high: u64,               // highest such price
+}
+
+// ============================================================================
+// STABLE MEMORY
+// ============================================================================
+
+#[cfg(feature = "stable")]
+mod stable {
+    use super::*;
+    use candid::{Decode, Encode};
+    use ic_stable_structures::storable::Bound;
+    use ic_stable_structures::Storable;
+    use std::borrow::Cow;
+
+    impl Storable for Order {
+        fn into_bytes(self) -> Vec<u8> {
+            Encode!(&self).unwrap()
+        }
+
+        fn to_bytes(&self) -> Cow<[u8]> {
+            Cow::Owned(Encode!(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Decode!(bytes.as_ref(), Self).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    impl Storable for ClearingResult {
+        fn into_bytes(self) -> Vec<u8> {
+            Encode!(&self).unwrap()
+        }
+
+        fn to_bytes(&self) -> Cow<[u8]> {
+            Cow::Owned(Encode!(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Decode!(bytes.as_ref(), Self).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    impl Storable for PriceRule {
+        fn into_bytes(self) -> Vec<u8> {
+            Encode!(&self).unwrap()
+        }
+
+        fn to_bytes(&self) -> Cow<[u8]> {
+            Cow::Owned(Encode!(self).unwrap())
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Decode!(bytes.as_ref(), Self).unwrap()
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+}
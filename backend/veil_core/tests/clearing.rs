@@ -0,0 +1,249 @@
+use candid::Principal;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use veil_core::types::{ClearingResult, Order, OrderStatus, OrderType, PriceRule};
+use veil_core::{find_clearing_price_and_match, ClearingError, ClearingParams};
+
+fn order(id: u64, order_type: OrderType, amount: u64, price_limit: u64) -> Order {
+    Order {
+        id,
+        market_id: 0,
+        round_id: 1,
+        owner: Principal::anonymous(),
+        order_type,
+        collateral: 0,
+        amount,
+        price_limit,
+        created_at: 0,
+        encrypted_payload: Vec::new(),
+        commitment_hash: String::new(),
+        status: OrderStatus::Open,
+        revision: 0,
+        updated_at: 0,
+        escrow_nonce: 0,
+    }
+}
+
+fn params(rule: PriceRule, tick_size: u64, reference_price: Option<u64>) -> ClearingParams {
+    ClearingParams {
+        market_id: 0,
+        round_id: 1,
+        rule,
+        tick_size,
+        base_decimals: 0,
+        reference_price,
+        timestamp: 42,
+    }
+}
+
+fn clear(orders: Vec<Order>, params: &ClearingParams) -> Result<ClearingResult, ClearingError> {
+    find_clearing_price_and_match(orders, params, |_| {})
+}
+
+fn fills(result: &ClearingResult) -> Vec<(u64, u64)> {
+    result.matches.iter().map(|m| (m.order_id, m.fill_amount)).collect()
+}
+
+// ============================================================================
+// PRICE SELECTION
+// ============================================================================
+
+#[test]
+fn midpoint_stays_on_tick_grid() {
+    let orders = vec![order(1, OrderType::Buy, 10, 60), order(2, OrderType::Sell, 10, 40)];
+    let result = clear(orders, &params(PriceRule::Midpoint, 20, None)).unwrap();
+
+    // The midpoint 50 is off the 20-tick grid: the lower of 40 and 60 wins
+    assert_eq!(result.clearing_price, 40);
+}
+
+#[test]
+fn prices_between_limits_can_balance_the_book() {
+    // At 40 demand exceeds supply by 5, at 50 supply exceeds demand by 5;
+    // strictly between them both sides hold 10
+    let orders = vec![
+        order(1, OrderType::Buy, 10, 50),
+        order(2, OrderType::Buy, 5, 40),
+        order(3, OrderType::Sell, 10, 40),
+        order(4, OrderType::Sell, 5, 50),
+    ];
+    let result = clear(orders, &params(PriceRule::Midpoint, 1, None)).unwrap();
+
+    assert_eq!(result.clearing_price, 45);
+    assert_eq!(fills(&result), vec![(1, 10), (2, 0), (3, 10), (4, 0)]);
+    let selection = result.price_selection.unwrap();
+    assert_eq!((selection.range_low, selection.range_high, selection.imbalance), (41, 49, 0));
+}
+
+#[test]
+fn no_overlap_fails() {
+    let orders = vec![order(1, OrderType::Buy, 10, 39), order(2, OrderType::Sell, 10, 40)];
+    assert_eq!(clear(orders, &params(PriceRule::Midpoint, 1, None)).err(), Some(ClearingError::NoOverlap));
+
+    let orders = vec![order(1, OrderType::Buy, 10, 50)];
+    assert_eq!(
+        clear(orders, &params(PriceRule::Midpoint, 1, None)).err(),
+        Some(ClearingError::OneSided { buy_orders: 1, sell_orders: 0 })
+    );
+}
+
+// ============================================================================
+// NOTIONAL AND OVERFLOW
+// ============================================================================
+
+#[test]
+fn notional_and_surplus_are_u128() {
+    // 2^40 units at 2^40 is far beyond u64 in quote units
+    let big = 1u64 << 40;
+    let orders = vec![order(1, OrderType::Buy, big, big + 2), order(2, OrderType::Sell, big, big - 2)];
+    let result = clear(orders, &params(PriceRule::Midpoint, 1, None)).unwrap();
+
+    assert_eq!(result.clearing_price, big);
+    assert_eq!(result.total_notional, (big as u128) * (big as u128));
+    assert_eq!(result.total_surplus, 4 * big as u128);
+    for m in &result.matches {
+        assert_eq!(m.notional, (big as u128) * (big as u128));
+        assert_eq!(m.surplus, 2 * big as u128);
+    }
+}
+
+#[test]
+fn fills_are_valued_per_whole_base_unit() {
+    // 0.015 BTC bought at $50,000 and sold at $40,000, prices in cents per BTC
+    let orders = vec![
+        order(1, OrderType::Buy, 1_500_000, 5_000_000),
+        order(2, OrderType::Sell, 1_500_000, 4_000_000),
+    ];
+    let mut btc_usd = params(PriceRule::Midpoint, 1, None);
+    btc_usd.base_decimals = 8;
+    let result = clear(orders, &btc_usd).unwrap();
+
+    assert_eq!(result.clearing_price, 4_500_000);
+    // $675.00 changes hands, each side saves $75.00
+    assert_eq!(result.total_notional, 67_500);
+    assert_eq!(result.total_surplus, 15_000);
+    assert!(result.matches.iter().all(|m| (m.notional, m.surplus) == (67_500, 7_500)));
+}
+
+#[test]
+fn cumulative_quantity_overflow_fails_cleanly() {
+    let orders = vec![
+        order(1, OrderType::Buy, u64::MAX, 50),
+        order(2, OrderType::Buy, u64::MAX, 50),
+        order(3, OrderType::Sell, 10, 40),
+    ];
+    assert_eq!(clear(orders, &params(PriceRule::Midpoint, 1, None)).err(), Some(ClearingError::Overflow));
+}
+
+// ============================================================================
+// INJECTED TIME AND LOGGING
+// ============================================================================
+
+#[test]
+fn timestamp_and_log_come_from_the_caller() {
+    let lines = RefCell::new(Vec::new());
+    let orders = vec![order(1, OrderType::Buy, 10, 50), order(2, OrderType::Sell, 10, 40)];
+    let result = find_clearing_price_and_match(orders, &params(PriceRule::Midpoint, 1, None), |line| {
+        lines.borrow_mut().push(line)
+    })
+    .unwrap();
+
+    assert_eq!(result.timestamp, 42);
+    assert!(lines.borrow().iter().any(|l| l.starts_with("Found clearing price")));
+}
+
+// ============================================================================
+// BRUTE-FORCE REFERENCE
+// ============================================================================
+
+/// Every grid price between the lowest and highest limit: highest volume,
+/// then lowest imbalance, then nearest the middle of the tied range
+fn reference_clearing(orders: &[Order], tick_size: u64) -> Option<(u64, u64, u64, u64, u64)> {
+    let at = |price: u64| {
+        let demand: u64 = orders
+            .iter()
+            .filter(|o| o.order_type == OrderType::Buy && o.price_limit >= price)
+            .map(|o| o.amount)
+            .sum();
+        let supply: u64 = orders
+            .iter()
+            .filter(|o| o.order_type == OrderType::Sell && o.price_limit <= price)
+            .map(|o| o.amount)
+            .sum();
+        (demand.min(supply), demand.abs_diff(supply))
+    };
+
+    let low = orders.iter().map(|o| o.price_limit).min()?;
+    let high = orders.iter().map(|o| o.price_limit).max()?;
+    let grid: Vec<u64> = (low..=high).step_by(tick_size as usize).collect();
+
+    let volume = grid.iter().map(|&p| at(p).0).max()?;
+    if volume == 0 {
+        return None;
+    }
+    let imbalance = grid.iter().filter(|&&p| at(p).0 == volume).map(|&p| at(p).1).min()?;
+    let tied: Vec<u64> = grid.iter().copied().filter(|&p| at(p) == (volume, imbalance)).collect();
+
+    let (range_low, range_high) = (tied[0], tied[tied.len() - 1]);
+    let midpoint = range_low + (range_high - range_low) / 2;
+    let price = *tied.iter().min_by_key(|&&p| (p.abs_diff(midpoint), p))?;
+    Some((price, volume, imbalance, range_low, range_high))
+}
+
+#[test]
+fn random_books_clear_like_the_reference() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+
+    for case in 0..5_000 {
+        let tick_size: u64 = if case % 2 == 0 { 1 } else { 5 };
+        let len = rng.random_range(2..=12);
+        let mut orders: Vec<Order> = (0..len)
+            .map(|id| {
+                let side = if rng.random_bool(0.5) { OrderType::Buy } else { OrderType::Sell };
+                order(id, side, rng.random_range(1..=20), rng.random_range(18..=22) * tick_size)
+            })
+            .collect();
+        orders[0].order_type = OrderType::Buy;
+        orders[1].order_type = OrderType::Sell;
+
+        let expected = reference_clearing(&orders, tick_size);
+        let result = clear(orders.clone(), &params(PriceRule::Midpoint, tick_size, None));
+
+        let result = match (result, expected) {
+            (Err(_), None) => continue,
+            (Ok(result), Some(expected)) => {
+                let selection = result.price_selection.clone().unwrap();
+                let actual = (
+                    result.clearing_price,
+                    result.total_volume,
+                    selection.imbalance,
+                    selection.range_low,
+                    selection.range_high,
+                );
+                assert_eq!(actual, expected, "case {}", case);
+                result
+            }
+            (result, expected) => panic!("case {}: cleared as {:?}, expected {:?}", case, result.map(|r| r.clearing_price), expected),
+        };
+
+        // Both sides trade exactly the volume, within their limits
+        let mut bought = 0;
+        let mut sold = 0;
+        for m in &result.matches {
+            let o = orders.iter().find(|o| o.id == m.order_id).unwrap();
+            assert!(m.fill_amount <= o.amount);
+            match o.order_type {
+                OrderType::Buy => {
+                    assert!(m.fill_amount == 0 || o.price_limit >= result.clearing_price);
+                    bought += m.fill_amount;
+                }
+                OrderType::Sell => {
+                    assert!(m.fill_amount == 0 || o.price_limit <= result.clearing_price);
+                    sold += m.fill_amount;
+                }
+            }
+        }
+        assert_eq!((bought, sold), (result.total_volume, result.total_volume), "case {}", case);
+    }
+}
@@ -0,0 +1,215 @@
+use candid::Principal;
+use veil_core::settlement::{fee_on, required_lock, settle, settle_round, Settlement, SettlementError};
+use veil_core::types::{AllocationBasis, Asset, Market, Order, OrderMatch, OrderStatus, OrderType};
+
+// Prices are cents per whole BTC, sizes satoshis
+const LIMIT: u64 = 5_000_000;      // $50,000.00
+const CLEARING: u64 = 4_500_000;   // $45,000.00
+const AMOUNT: u64 = 1_000_000;     // 0.01 BTC
+
+fn btc_usd() -> Market {
+    Market {
+        id: 0,
+        base: Asset::BTC,
+        quote: Asset::USD,
+        symbol: "BTC/USD".to_string(),
+    }
+}
+
+fn order(order_type: OrderType, collateral: u64) -> Order {
+    Order {
+        id: match order_type {
+            OrderType::Buy => 7,
+            OrderType::Sell => 8,
+        },
+        market_id: 0,
+        round_id: 1,
+        owner: Principal::anonymous(),
+        order_type,
+        collateral,
+        amount: AMOUNT,
+        price_limit: LIMIT,
+        created_at: 0,
+        encrypted_payload: Vec::new(),
+        commitment_hash: String::new(),
+        status: OrderStatus::Open,
+        revision: 0,
+        updated_at: 0,
+        escrow_nonce: 0,
+    }
+}
+
+fn fill(fill_amount: u64) -> OrderMatch {
+    OrderMatch {
+        order_id: 7,
+        filled: fill_amount > 0,
+        requested_amount: AMOUNT,
+        fill_amount,
+        fill_price: CLEARING,
+        notional: 0,
+        surplus: 0,
+        allocation: AllocationBasis::Full,
+        fee: 0,
+    }
+}
+
+#[test]
+fn locks_follow_the_side() {
+    // 0.01 BTC at $50,000 locks $500.00
+    assert_eq!(required_lock(&btc_usd(), &OrderType::Buy, AMOUNT, LIMIT), Ok((Asset::USD, 50_000)));
+    assert_eq!(required_lock(&btc_usd(), &OrderType::Sell, AMOUNT, LIMIT), Ok((Asset::BTC, AMOUNT)));
+    assert_eq!(required_lock(&btc_usd(), &OrderType::Buy, u64::MAX, u64::MAX), Err(SettlementError::Overflow));
+}
+
+#[test]
+fn sub_cent_values_round_in_favour_of_escrow() {
+    // One satoshi at $67,500 is worth 0.0675 cents
+    assert_eq!(required_lock(&btc_usd(), &OrderType::Buy, 1, 6_750_000), Ok((Asset::USD, 1)));
+
+    let mut one_sat = fill(1);
+    one_sat.fill_price = 6_750_000;
+    let buyer = settle(&order(OrderType::Buy, 1), &btc_usd(), &one_sat, 6_750_000, 0).unwrap();
+    assert_eq!((buyer.received, buyer.refund), (1, 0));
+    let seller = settle(&order(OrderType::Sell, 1), &btc_usd(), &one_sat, 6_750_000, 0).unwrap();
+    assert_eq!((seller.received, seller.refund), (0, 0));
+}
+
+#[test]
+fn buyer_receives_base_and_the_unspent_quote() {
+    let settlement = settle(&order(OrderType::Buy, 50_000), &btc_usd(), &fill(600_000), CLEARING, 0).unwrap();
+    assert_eq!(
+        settlement,
+        Settlement {
+            locked_asset: Asset::USD,
+            reserved: 50_000,
+            received_asset: Asset::BTC,
+            received: 600_000,
+            refund: 23_000,
+            fee: 0,
+        }
+    );
+}
+
+#[test]
+fn seller_receives_quote_and_the_unsold_base() {
+    let settlement = settle(&order(OrderType::Sell, AMOUNT), &btc_usd(), &fill(600_000), CLEARING, 0).unwrap();
+    assert_eq!(
+        settlement,
+        Settlement {
+            locked_asset: Asset::BTC,
+            reserved: AMOUNT,
+            received_asset: Asset::USD,
+            received: 27_000,
+            refund: 400_000,
+            fee: 0,
+        }
+    );
+}
+
+#[test]
+fn buyer_cannot_spend_more_than_locked() {
+    assert_eq!(
+        settle(&order(OrderType::Buy, 20_000), &btc_usd(), &fill(600_000), CLEARING, 0),
+        Err(SettlementError::CostExceedsLock { order_id: 7, cost: 27_000, reserved: 20_000 })
+    );
+}
+
+#[test]
+fn seller_proceeds_must_fit_the_ledger() {
+    let mut huge = fill(u64::MAX);
+    huge.fill_price = u64::MAX;
+    assert_eq!(
+        settle(&order(OrderType::Sell, u64::MAX), &btc_usd(), &huge, u64::MAX, 0),
+        Err(SettlementError::Overflow)
+    );
+}
+
+#[test]
+fn seller_cannot_deliver_more_than_locked() {
+    assert_eq!(
+        settle(&order(OrderType::Sell, 500_000), &btc_usd(), &fill(600_000), CLEARING, 0),
+        Err(SettlementError::FillExceedsLock { order_id: 8, fill_amount: 600_000, reserved: 500_000 })
+    );
+}
+
+#[test]
+fn fills_settle_at_the_clearing_price_only() {
+    assert_eq!(
+        settle(&order(OrderType::Sell, AMOUNT), &btc_usd(), &fill(600_000), LIMIT, 0),
+        Err(SettlementError::PriceMismatch { order_id: 8, fill_price: CLEARING, clearing_price: LIMIT })
+    );
+}
+
+#[test]
+fn fees_come_out_of_what_the_order_receives() {
+    // 30 bps: the buyer gets 0.006 BTC less 1,800 sat, the seller $270.00 less 81 cents
+    let buyer = settle(&order(OrderType::Buy, 50_000), &btc_usd(), &fill(600_000), CLEARING, 30).unwrap();
+    assert_eq!((buyer.received, buyer.fee, buyer.refund), (598_200, 1_800, 23_000));
+    let seller = settle(&order(OrderType::Sell, AMOUNT), &btc_usd(), &fill(600_000), CLEARING, 30).unwrap();
+    assert_eq!((seller.received, seller.fee, seller.refund), (26_919, 81, 400_000));
+}
+
+#[test]
+fn fees_round_down_and_stay_within_the_proceeds() {
+    assert_eq!(fee_on(333, 30), Ok(0));
+    assert_eq!(fee_on(u64::MAX, 10_000), Ok(u64::MAX));
+    assert_eq!(fee_on(1, 10_001), Err(SettlementError::FeeRateTooHigh { fee_bps: 10_001 }));
+}
+
+// ============================================================================
+// ROUNDS
+// ============================================================================
+
+#[test]
+fn round_moves_value_without_minting() {
+    let fills = vec![
+        (order(OrderType::Buy, 50_000), fill(600_000)),
+        (order(OrderType::Sell, AMOUNT), fill(600_000)),
+    ];
+    let settlements = settle_round(&btc_usd(), &fills, CLEARING, |_| 0).unwrap();
+
+    // The buyer's $270.00 is exactly the seller's proceeds, the seller's
+    // 0.006 BTC exactly what the buyer receives
+    assert_eq!(settlements[0].paid(), settlements[1].received);
+    assert_eq!(settlements[1].paid(), settlements[0].received);
+}
+
+#[test]
+fn round_fees_follow_each_order() {
+    let fills = vec![
+        (order(OrderType::Buy, 50_000), fill(600_000)),
+        (order(OrderType::Sell, AMOUNT), fill(600_000)),
+    ];
+    // The seller has a discounted rate
+    let rate = |o: &Order| if o.order_type == OrderType::Sell { 10 } else { 30 };
+    let settlements = settle_round(&btc_usd(), &fills, CLEARING, rate).unwrap();
+
+    assert_eq!((settlements[0].fee, settlements[1].fee), (1_800, 27));
+    assert_eq!(settlements[0].paid(), settlements[1].received + settlements[1].fee);
+    assert_eq!(settlements[1].paid(), settlements[0].received + settlements[0].fee);
+}
+
+#[test]
+fn round_volumes_must_match() {
+    let fills = vec![
+        (order(OrderType::Buy, 50_000), fill(600_000)),
+        (order(OrderType::Sell, AMOUNT), fill(500_000)),
+    ];
+    assert_eq!(
+        settle_round(&btc_usd(), &fills, CLEARING, |_| 0),
+        Err(SettlementError::VolumeMismatch { bought: 600_000, sold: 500_000 })
+    );
+}
+
+#[test]
+fn rounding_dust_stays_behind() {
+    // One satoshi at $67,500: the buyer pays a whole cent, the seller gets none
+    let mut one_sat = fill(1);
+    one_sat.fill_price = 6_750_000;
+    let fills = vec![
+        (order(OrderType::Buy, 1), one_sat.clone()),
+        (order(OrderType::Sell, 1), one_sat),
+    ];
+    let settlements = settle_round(&btc_usd(), &fills, 6_750_000, |_| 0).unwrap();
+    assert_eq!((settlements[0].paid(), settlements[1].received), (1, 0));
+}
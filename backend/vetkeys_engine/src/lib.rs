@@ -1,6 +1,12 @@
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::call::Call;
 use ic_cdk_macros::*;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableCell, Storable};
 use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
 
 // ==============================
 // Types
@@ -8,7 +14,7 @@ use serde::Serialize;
 
 #[derive(CandidType, Serialize, Deserialize, Clone)]
 pub enum VetKDCurve {
-    #[serde(rename = "bls12_381")]
+    #[serde(rename = "bls12_381_g2")]
     Bls12_381,
 }
 
@@ -43,45 +49,178 @@ pub struct VetKDDeriveKeyResponse {
     pub encrypted_key: Vec<u8>,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone, Default)]
+pub struct EngineConfig {
+    /// The only canister allowed to derive round and user keys
+    pub backend: Option<Principal>,
+    /// vetKD master key: "dfx_test_key" locally, "test_key_1" / "key_1" on mainnet
+    pub key_name: Option<String>,
+}
+
+impl Storable for EngineConfig {
+    fn into_bytes(self) -> Vec<u8> {
+        Encode!(&self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
 // ==============================
 // Config
 // ==============================
 
 const VEIL_DOMAIN_SEPARATOR: &[u8] = b"VEIL-BATCH-AUCTION-V1";
+const DEFAULT_KEY_NAME: &str = "test_key_1";
+
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(0);
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // Kept in stable memory so the backend stays authorised across upgrades
+    static CONFIG: RefCell<StableCell<EngineConfig, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG_MEMORY_ID)), EngineConfig::default())
+    );
+}
+
+fn config() -> EngineConfig {
+    CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn update_config(f: impl FnOnce(&mut EngineConfig)) {
+    CONFIG.with(|c| {
+        let mut cell = c.borrow_mut();
+        let mut config = cell.get().clone();
+        f(&mut config);
+        cell.set(config);
+    });
+}
 
 fn key_id() -> VetKDKeyId {
     VetKDKeyId {
         curve: VetKDCurve::Bls12_381,
-        name: "test_key_1".to_string(),
+        name: config().key_name.unwrap_or_else(|| DEFAULT_KEY_NAME.to_string()),
     }
 }
 
+/// Must match the backend's `encryption::generate_timelock_identity`
+fn round_identity(market_id: u32, round_id: u64) -> Vec<u8> {
+    let mut identity = Vec::new();
+    identity.extend_from_slice(b"ROUND:");
+    identity.extend_from_slice(&market_id.to_be_bytes());
+    identity.extend_from_slice(&round_id.to_be_bytes());
+    identity
+}
+
+fn ensure_backend_caller() -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    match config().backend {
+        Some(backend) if backend == caller => Ok(()),
+        Some(_) => Err(format!("Caller {} may not derive keys", caller)),
+        None => Err("Backend canister not configured".to_string()),
+    }
+}
+
+#[init]
+fn init(config: Option<EngineConfig>) {
+    if let Some(config) = config {
+        update_config(|c| *c = config);
+    }
+}
+
+/// Upgrading without an argument keeps the stored config
+#[post_upgrade]
+fn post_upgrade(config: Option<EngineConfig>) {
+    init(config);
+}
+
+#[update]
+fn set_backend_canister(backend: Principal) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::api::msg_caller()) {
+        return Err("Only controllers may set the backend canister".to_string());
+    }
+    update_config(|c| c.backend = Some(backend));
+    Ok(())
+}
+
 // ==============================
-// Public API (mock vetKD for PocketIC)
+// vetKD (management canister)
 // ==============================
 
+async fn vetkd_derive_key(input: Vec<u8>, transport_public_key: Vec<u8>) -> Result<Vec<u8>, String> {
+    let args = VetKDDeriveKeyArgs {
+        input,
+        context: VEIL_DOMAIN_SEPARATOR.to_vec(),
+        transport_public_key,
+        key_id: key_id(),
+    };
+
+    // vetkd_derive_key must be paid for with attached cycles
+    let cycles = ic_cdk::api::cost_vetkd_derive_key(&args.key_id.name, 0)
+        .map_err(|e| format!("vetkd_derive_key cost: {:?}", e))?;
+
+    let res: VetKDDeriveKeyResponse = Call::unbounded_wait(Principal::management_canister(), "vetkd_derive_key")
+        .with_arg(args)
+        .with_cycles(cycles)
+        .await
+        .map_err(|e| format!("vetkd_derive_key failed: {}", e))?
+        .candid()
+        .map_err(|e| format!("vetkd_derive_key decode failed: {}", e))?;
+
+    Ok(res.encrypted_key)
+}
+
+// ==============================
+// Public API
+// ==============================
+
+/// Derived public key for the VEIL context; clients encrypt orders to it
 #[update]
-fn get_public_key() -> Vec<u8> {
-    // PocketIC has no real vetKD
-    let mut fake = vec![0u8; 32];
-    fake[0..4].copy_from_slice(b"VTKD");
-    fake
+async fn get_public_key() -> Result<Vec<u8>, String> {
+    let args = VetKDPublicKeyArgs {
+        canister_id: None,
+        context: VEIL_DOMAIN_SEPARATOR.to_vec(),
+        key_id: key_id(),
+    };
+
+    let res: VetKDPublicKeyResponse = Call::bounded_wait(Principal::management_canister(), "vetkd_public_key")
+        .with_arg(args)
+        .await
+        .map_err(|e| format!("vetkd_public_key failed: {}", e))?
+        .candid()
+        .map_err(|e| format!("vetkd_public_key decode failed: {}", e))?;
+
+    Ok(res.public_key)
 }
 
+/// Round decryption key (IBE key for the round's timelock identity),
+/// encrypted under `transport_public_key`. Backend only.
 #[update]
-fn derive_round_key(round_id: u64) -> Vec<u8> {
-    let mut out = vec![0u8; 32];
-    out[..8].copy_from_slice(&round_id.to_be_bytes());
-    out
+async fn derive_round_key(
+    market_id: u32,
+    round_id: u64,
+    transport_public_key: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    ensure_backend_caller()?;
+    vetkd_derive_key(round_identity(market_id, round_id), transport_public_key).await
 }
 
+/// Per-user key, encrypted under `transport_public_key`. Backend only.
 #[update]
-fn derive_user_key(user: Principal) -> Vec<u8> {
-    let mut out = vec![0u8; 32];
-    let u = user.as_slice();
-    let n = core::cmp::min(u.len(), 32);
-    out[..n].copy_from_slice(&u[..n]);
-    out
+async fn derive_user_key(user: Principal, transport_public_key: Vec<u8>) -> Result<Vec<u8>, String> {
+    ensure_backend_caller()?;
+    vetkd_derive_key(user.as_slice().to_vec(), transport_public_key).await
 }
 
 ic_cdk::export_candid!();